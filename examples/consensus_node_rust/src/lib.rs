@@ -0,0 +1,29 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! The update loop and block publishing shared by the example consensus engines.
+//!
+//! A `Node` drives a consensus engine from its updates: it builds blocks on the chain head,
+//! publishes them once their wait is over, has new blocks checked, commits valid ones and starts
+//! over when the chain head moves. The rules which make one engine differ from another are
+//! supplied by a `Policy`.
+
+#[macro_use]
+extern crate log;
+
+pub mod node;
+pub mod testing;
@@ -0,0 +1,243 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! A consensus engine's node, which applies a `Policy` to the updates from the validator.
+
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use sawtooth_sdk::consensus::block_cache::BlockCache;
+use sawtooth_sdk::consensus::engine::{Block, BlockId, Error, StartupState, Update};
+use sawtooth_sdk::consensus::service::Service;
+use sawtooth_sdk::settings::{Settings, SettingsCache};
+
+/// How often the node checks whether its block should be published
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The number of blocks whose settings are kept
+const SETTINGS_CACHE_SIZE: usize = 16;
+
+/// The rules of a consensus engine run by a `Node`
+pub trait Policy {
+    /// The on-chain settings of the engine
+    type Settings: Settings;
+
+    /// The name of the engine, used in log messages
+    const NAME: &'static str;
+
+    /// The consensus payload of the blocks published by the engine
+    const PAYLOAD: &'static [u8];
+
+    /// Returns how long to wait before publishing a block built on the chain head, or `None` if
+    /// the local validator should not build the next block
+    fn initialize_block(
+        &mut self,
+        chain: &mut Chain<Self::Settings>,
+    ) -> Result<Option<Duration>, Error>;
+
+    /// Handle a block received from the validator; by default, have it checked
+    fn on_block_new(
+        &mut self,
+        chain: &mut Chain<Self::Settings>,
+        block: Block,
+    ) -> Result<(), Error> {
+        chain.service.check_blocks(vec![block.block_id])
+    }
+
+    /// Whether a valid block should be committed, replacing `chain_head`, or ignored
+    fn should_commit(&self, block: &Block, chain_head: &Block) -> bool;
+}
+
+/// The node's view of the chain, shared with its policy
+pub struct Chain<S> {
+    pub service: Box<dyn Service>,
+    pub head: Block,
+    pub blocks: BlockCache,
+    settings: SettingsCache<S>,
+}
+
+impl<S: Settings> Chain<S> {
+    /// Returns the settings as of the given block
+    pub fn settings_at(&mut self, block_id: &[u8]) -> Result<&S, Error> {
+        self.settings
+            .get(&mut *self.service, block_id)
+            .map_err(|err| Error::InvalidState(format!("Invalid settings: {}", err)))
+    }
+
+    /// Returns the block with the given id, fetching it from the validator if needed
+    pub fn block(&mut self, block_id: &[u8]) -> Result<Block, Error> {
+        self.blocks.get_or_fetch(&mut *self.service, block_id)
+    }
+}
+
+/// The state of a running consensus engine
+pub struct Node<P: Policy> {
+    policy: P,
+    chain: Chain<P::Settings>,
+    // When the block in progress should be published, if a block is in progress
+    publish_at: Option<Instant>,
+}
+
+impl<P: Policy> Node<P> {
+    pub fn new(policy: P, service: Box<dyn Service>, chain_head: Block) -> Self {
+        Node {
+            policy,
+            chain: Chain {
+                service,
+                head: chain_head,
+                blocks: BlockCache::new(),
+                settings: SettingsCache::new(SETTINGS_CACHE_SIZE),
+            },
+            publish_at: None,
+        }
+    }
+
+    /// Handle updates until the engine is shut down or deactivated
+    pub fn run(&mut self, updates: Receiver<Update>) -> Result<(), Error> {
+        self.initialize_block()?;
+
+        loop {
+            match updates.recv_timeout(POLL_INTERVAL) {
+                Ok(Update::Shutdown) => {
+                    info!("Received shutdown; stopping {} engine", P::NAME);
+                    return Ok(());
+                }
+                Ok(Update::Deactivated) => {
+                    info!("{} engine deactivated", P::NAME);
+                    return Ok(());
+                }
+                Ok(update) => {
+                    if let Err(err) = self.handle_update(update) {
+                        error!("Failed to handle update: {}", err);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => {
+                    warn!("Update channel disconnected; stopping {} engine", P::NAME);
+                    return Ok(());
+                }
+            }
+
+            if let Err(err) = self.try_publish() {
+                error!("Failed to publish block: {}", err);
+            }
+        }
+    }
+
+    /// Start building a block on the chain head, if the policy says the local validator should
+    fn initialize_block(&mut self) -> Result<(), Error> {
+        let wait_time = match self.policy.initialize_block(&mut self.chain)? {
+            Some(wait_time) => wait_time,
+            None => return Ok(()),
+        };
+
+        self.chain
+            .service
+            .initialize_block(Some(self.chain.head.block_id.clone()))?;
+        self.publish_at = Some(Instant::now() + wait_time);
+        debug!(
+            "Building block {} to publish in {:?}",
+            self.chain.head.block_num + 1,
+            wait_time
+        );
+        Ok(())
+    }
+
+    /// Cancel the block in progress, if any
+    fn cancel_block(&mut self) {
+        if self.publish_at.take().is_some() {
+            if let Err(err) = self.chain.service.cancel_block() {
+                // The validator may have already dropped the block
+                debug!("Failed to cancel block: {}", err);
+            }
+        }
+    }
+
+    /// Publish the block in progress once its wait is over and the validator can summarize it
+    fn try_publish(&mut self) -> Result<(), Error> {
+        match self.publish_at {
+            Some(publish_at) if Instant::now() >= publish_at => (),
+            _ => return Ok(()),
+        }
+
+        match self.chain.service.summarize_block() {
+            Ok(_) => (),
+            Err(Error::BlockNotReady) => return Ok(()),
+            Err(err) => return Err(err),
+        }
+
+        self.publish_at = None;
+        let block_id = self.chain.service.finalize_block(P::PAYLOAD.to_vec())?;
+        info!("Published block {}", hex::encode(&block_id));
+        Ok(())
+    }
+
+    fn handle_update(&mut self, update: Update) -> Result<(), Error> {
+        self.chain.blocks.update(&update);
+
+        match update {
+            Update::BlockNew(block) => self.policy.on_block_new(&mut self.chain, block),
+            Update::BlockValid(block_id) => self.on_block_valid(block_id),
+            Update::BlockInvalid(block_id) => {
+                info!("Block {} is invalid", hex::encode(&block_id));
+                self.chain.service.fail_block(block_id)
+            }
+            Update::BlockCommit(block_id) => self.on_block_commit(block_id),
+            Update::Reconnected(startup_state) | Update::Activated(startup_state) => {
+                self.resync(startup_state)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Commit a valid block if the policy accepts it, and ignore it otherwise
+    fn on_block_valid(&mut self, block_id: BlockId) -> Result<(), Error> {
+        let block = self.chain.block(&block_id)?;
+
+        if self.policy.should_commit(&block, &self.chain.head) {
+            info!(
+                "Committing block {} at height {}",
+                hex::encode(&block_id),
+                block.block_num
+            );
+            self.chain.service.commit_block(block_id)
+        } else {
+            debug!(
+                "Ignoring block {} at height {}",
+                hex::encode(&block_id),
+                block.block_num
+            );
+            self.chain.service.ignore_block(block_id)
+        }
+    }
+
+    /// Move to the new chain head and start building the next block on it
+    fn on_block_commit(&mut self, block_id: BlockId) -> Result<(), Error> {
+        self.chain.head = self.chain.block(&block_id)?;
+        self.cancel_block();
+        self.initialize_block()
+    }
+
+    /// Start over from the validator's state, since updates may have been missed
+    fn resync(&mut self, startup_state: StartupState) -> Result<(), Error> {
+        self.cancel_block();
+        self.chain.blocks = BlockCache::new();
+        self.chain.settings.clear();
+        self.chain.head = startup_state.chain_head;
+        self.initialize_block()
+    }
+}
@@ -0,0 +1,57 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Helpers for testing the example consensus engines against a `TestService`.
+
+use std::sync::mpsc::Receiver;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use sawtooth_sdk::consensus::engine::{Engine, Update};
+use sawtooth_sdk::consensus::testing::{ServiceCall, TestService};
+
+/// How long the helpers wait for the engine
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Run `engine` on a thread, with the given service and its updates
+pub fn start_engine<E: Engine + Send + 'static>(
+    mut engine: E,
+    service: &TestService,
+    updates: Receiver<Update>,
+) -> JoinHandle<()> {
+    let startup_state = service.startup_state();
+    let engine_service = service.clone();
+    thread::spawn(move || {
+        engine
+            .start(updates, Box::new(engine_service), startup_state)
+            .unwrap()
+    })
+}
+
+/// Shut the engine down and wait for it to return
+pub fn stop_engine(service: &TestService, engine: JoinHandle<()>) {
+    service.send_update(Update::Shutdown);
+    engine.join().unwrap();
+}
+
+/// Wait for the engine to commit the given block
+pub fn wait_for_commit(service: &TestService, block_id: &[u8]) {
+    service.wait_for_call(
+        |call| *call == ServiceCall::CommitBlock(block_id.to_vec()),
+        TIMEOUT,
+    );
+}
@@ -0,0 +1,250 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! A devmode-style consensus engine.
+//!
+//! Every validator builds a block on its chain head and publishes it after a random wait between
+//! `sawtooth.consensus.min_wait_time` and `sawtooth.consensus.max_wait_time` seconds. Valid blocks
+//! are committed if they extend the longest chain, ties between blocks at the same height being
+//! broken by the greater block id, so that every validator eventually follows the same chain.
+//!
+//! Devmode gives no protection against misbehaving validators and is only meant for development
+//! and testing networks.
+
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use rand::Rng;
+
+use sawtooth_consensus_node::node::{Chain, Node, Policy};
+use sawtooth_sdk::consensus::engine::{Block, Engine, Error, StartupState, Update};
+use sawtooth_sdk::consensus::service::Service;
+use sawtooth_sdk::settings::{SettingValues, Settings, SettingsError};
+
+pub const MIN_WAIT_TIME_SETTING: &str = "sawtooth.consensus.min_wait_time";
+pub const MAX_WAIT_TIME_SETTING: &str = "sawtooth.consensus.max_wait_time";
+
+/// The settings of the devmode engine
+#[derive(Clone, Debug, PartialEq)]
+pub struct DevmodeSettings {
+    pub min_wait_time: Duration,
+    pub max_wait_time: Duration,
+}
+
+impl DevmodeSettings {
+    /// Returns a random wait time between the minimum and maximum wait times
+    pub fn wait_time<R: Rng>(&self, rng: &mut R) -> Duration {
+        if self.min_wait_time == self.max_wait_time {
+            self.min_wait_time
+        } else {
+            rng.gen_range(self.min_wait_time..=self.max_wait_time)
+        }
+    }
+}
+
+impl Settings for DevmodeSettings {
+    fn keys() -> Vec<String> {
+        vec![MIN_WAIT_TIME_SETTING.into(), MAX_WAIT_TIME_SETTING.into()]
+    }
+
+    fn from_values(values: &SettingValues) -> Result<Self, SettingsError> {
+        let min_wait_time = values.get_seconds(MIN_WAIT_TIME_SETTING, 0)?;
+        let max_wait_time = values.get_seconds(MAX_WAIT_TIME_SETTING, 0)?;
+        if min_wait_time > max_wait_time {
+            return Err(SettingsError::InvalidSetting(format!(
+                "{} is greater than {}",
+                MIN_WAIT_TIME_SETTING, MAX_WAIT_TIME_SETTING
+            )));
+        }
+        Ok(DevmodeSettings {
+            min_wait_time,
+            max_wait_time,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct DevmodeEngine {}
+
+impl DevmodeEngine {
+    pub fn new() -> Self {
+        DevmodeEngine::default()
+    }
+}
+
+impl Engine for DevmodeEngine {
+    fn start(
+        &mut self,
+        updates: Receiver<Update>,
+        service: Box<dyn Service>,
+        startup_state: StartupState,
+    ) -> Result<(), Error> {
+        Node::new(DevmodePolicy, service, startup_state.chain_head).run(updates)
+    }
+
+    fn version(&self) -> String {
+        "0.1".into()
+    }
+
+    fn name(&self) -> String {
+        "Devmode".into()
+    }
+
+    fn additional_protocols(&self) -> Vec<(String, String)> {
+        vec![]
+    }
+}
+
+/// The devmode rules: publish after a random wait and follow the longest chain
+struct DevmodePolicy;
+
+impl Policy for DevmodePolicy {
+    type Settings = DevmodeSettings;
+
+    const NAME: &'static str = "Devmode";
+
+    const PAYLOAD: &'static [u8] = b"Devmode";
+
+    fn initialize_block(
+        &mut self,
+        chain: &mut Chain<DevmodeSettings>,
+    ) -> Result<Option<Duration>, Error> {
+        let chain_head_id = chain.head.block_id.clone();
+        let wait_time = chain
+            .settings_at(&chain_head_id)?
+            .wait_time(&mut rand::thread_rng());
+        Ok(Some(wait_time))
+    }
+
+    /// A block replaces the chain head if it is higher, or at the same height with a greater id
+    fn should_commit(&self, block: &Block, chain_head: &Block) -> bool {
+        block.block_num > chain_head.block_num
+            || (block.block_num == chain_head.block_num && block.block_id > chain_head.block_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use sawtooth_consensus_node::testing::{start_engine, stop_engine, wait_for_commit, TIMEOUT};
+    use sawtooth_sdk::consensus::testing::{ServiceCall, TestService};
+
+    #[test]
+    fn wait_time_settings() {
+        let values = |min: &str, max: &str| {
+            let mut values = HashMap::new();
+            values.insert(MIN_WAIT_TIME_SETTING.to_string(), min.to_string());
+            values.insert(MAX_WAIT_TIME_SETTING.to_string(), max.to_string());
+            SettingValues::new(values)
+        };
+
+        let settings = DevmodeSettings::from_values(&SettingValues::default()).unwrap();
+        assert_eq!(
+            settings.wait_time(&mut rand::thread_rng()),
+            Duration::from_secs(0)
+        );
+
+        let settings = DevmodeSettings::from_values(&values("1", "3")).unwrap();
+        for _ in 0..100 {
+            let wait_time = settings.wait_time(&mut rand::thread_rng());
+            assert!(wait_time >= Duration::from_secs(1) && wait_time <= Duration::from_secs(3));
+        }
+
+        match DevmodeSettings::from_values(&values("3", "1")) {
+            Err(SettingsError::InvalidSetting(_)) => (),
+            res => panic!("Expected InvalidSetting, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn publishes_and_commits_blocks() {
+        let (service, updates) = TestService::new(b"local".to_vec());
+        let engine = start_engine(DevmodeEngine::new(), &service, updates);
+
+        let block1 = service.wait_for_block(1, TIMEOUT);
+        wait_for_commit(&service, &block1.block_id);
+        let block2 = service.wait_for_block(2, TIMEOUT);
+        wait_for_commit(&service, &block2.block_id);
+
+        stop_engine(&service, engine);
+
+        assert_eq!(block1.payload, DevmodePolicy::PAYLOAD);
+        assert_eq!(block2.previous_id, block1.block_id);
+        service.assert_committed(&block2.block_id);
+    }
+
+    #[test]
+    fn follows_longest_chain() {
+        let (service, updates) = TestService::new(b"local".to_vec());
+        // Keep the engine from publishing its own blocks
+        service.set_setting(MIN_WAIT_TIME_SETTING, "3600");
+        service.set_setting(MAX_WAIT_TIME_SETTING, "3600");
+        let genesis_id = service.chain_head().block_id;
+        let engine = start_engine(DevmodeEngine::new(), &service, updates);
+
+        let mut forks = vec![
+            service.build_block(&genesis_id, b"peer1", b""),
+            service.build_block(&genesis_id, b"peer2", b""),
+        ];
+        forks.sort_by(|a, b| a.block_id.cmp(&b.block_id));
+        let (lower, greater) = (forks.remove(0), forks.remove(0));
+
+        service.receive_block(greater.clone());
+        wait_for_commit(&service, &greater.block_id);
+
+        // A block at the same height with a lower id does not replace the chain head
+        service.receive_block(lower.clone());
+        service.wait_for_call(
+            |call| *call == ServiceCall::IgnoreBlock(lower.block_id.clone()),
+            TIMEOUT,
+        );
+
+        // A longer chain does
+        let block2 = service.build_block(&lower.block_id, b"peer1", b"");
+        service.receive_block(block2.clone());
+        wait_for_commit(&service, &block2.block_id);
+
+        stop_engine(&service, engine);
+
+        service.assert_not_called(|call| *call == ServiceCall::CommitBlock(lower.block_id.clone()));
+        service.assert_not_called(|call| matches!(call, ServiceCall::FinalizeBlock(_)));
+    }
+
+    #[test]
+    fn fails_invalid_blocks() {
+        let (service, updates) = TestService::new(b"local".to_vec());
+        service.set_setting(MIN_WAIT_TIME_SETTING, "3600");
+        service.set_setting(MAX_WAIT_TIME_SETTING, "3600");
+        let genesis_id = service.chain_head().block_id;
+        let engine = start_engine(DevmodeEngine::new(), &service, updates);
+
+        let block = service.build_block(&genesis_id, b"peer", b"");
+        service.set_invalid(&block.block_id);
+        service.receive_block(block.clone());
+        service.wait_for_call(
+            |call| *call == ServiceCall::FailBlock(block.block_id.clone()),
+            TIMEOUT,
+        );
+
+        stop_engine(&service, engine);
+
+        service.assert_not_called(|call| *call == ServiceCall::CommitBlock(block.block_id.clone()));
+    }
+}
@@ -0,0 +1,265 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! A round-robin proof-of-authority consensus engine.
+//!
+//! The validators allowed to publish blocks are listed, by public key, in the
+//! `sawtooth.consensus.poa.signers` setting. They take turns in the order of the list: block `n`
+//! is published by the signer at index `(n - 1) % len`, as listed in the settings of its parent,
+//! once `sawtooth.consensus.poa.block_interval` milliseconds have passed since the parent was
+//! committed. Blocks published by any other validator are failed, and since each height has a
+//! single signer the chain never forks; a valid block is committed if it extends the chain head.
+//!
+//! The engine does not handle a signer going offline: the chain stalls until it comes back or the
+//! signer list is changed.
+
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use sawtooth_consensus_node::node::{Chain, Node, Policy};
+use sawtooth_sdk::consensus::engine::{Block, Engine, Error, StartupState, Update};
+use sawtooth_sdk::consensus::service::Service;
+use sawtooth_sdk::settings::{SettingValues, Settings, SettingsError};
+
+pub const SIGNERS_SETTING: &str = "sawtooth.consensus.poa.signers";
+pub const BLOCK_INTERVAL_SETTING: &str = "sawtooth.consensus.poa.block_interval";
+
+const DEFAULT_BLOCK_INTERVAL_MILLIS: u64 = 1000;
+
+/// The settings of the proof-of-authority engine
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoaSettings {
+    /// The public keys of the signers, as hex, in the order they publish blocks
+    pub signers: Vec<String>,
+    pub block_interval: Duration,
+}
+
+impl PoaSettings {
+    /// Returns the public key of the signer whose turn it is to publish the block at `block_num`
+    pub fn signer_of(&self, block_num: u64) -> &str {
+        let index = block_num.saturating_sub(1) % self.signers.len() as u64;
+        &self.signers[index as usize]
+    }
+}
+
+impl Settings for PoaSettings {
+    fn keys() -> Vec<String> {
+        vec![SIGNERS_SETTING.into(), BLOCK_INTERVAL_SETTING.into()]
+    }
+
+    fn from_values(values: &SettingValues) -> Result<Self, SettingsError> {
+        let signers = values
+            .get_list(SIGNERS_SETTING, &[])?
+            .into_iter()
+            .map(|signer| signer.to_lowercase())
+            .collect::<Vec<_>>();
+        if signers.is_empty() {
+            return Err(SettingsError::InvalidSetting(format!(
+                "{} must list at least one signer",
+                SIGNERS_SETTING
+            )));
+        }
+
+        Ok(PoaSettings {
+            signers,
+            block_interval: values
+                .get_millis(BLOCK_INTERVAL_SETTING, DEFAULT_BLOCK_INTERVAL_MILLIS)?,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct PoaEngine {}
+
+impl PoaEngine {
+    pub fn new() -> Self {
+        PoaEngine::default()
+    }
+}
+
+impl Engine for PoaEngine {
+    fn start(
+        &mut self,
+        updates: Receiver<Update>,
+        service: Box<dyn Service>,
+        startup_state: StartupState,
+    ) -> Result<(), Error> {
+        let policy = PoaPolicy {
+            local_signer: hex::encode(&startup_state.local_peer_info.peer_id),
+        };
+        Node::new(policy, service, startup_state.chain_head).run(updates)
+    }
+
+    fn version(&self) -> String {
+        "0.1".into()
+    }
+
+    fn name(&self) -> String {
+        "PoA".into()
+    }
+
+    fn additional_protocols(&self) -> Vec<(String, String)> {
+        vec![]
+    }
+}
+
+/// The proof-of-authority rules: signers publish in turn and the chain never forks
+struct PoaPolicy {
+    // The local validator's public key, as hex
+    local_signer: String,
+}
+
+impl Policy for PoaPolicy {
+    type Settings = PoaSettings;
+
+    const NAME: &'static str = "PoA";
+
+    const PAYLOAD: &'static [u8] = b"PoA";
+
+    /// Build the next block after the block interval if it is the local validator's turn
+    fn initialize_block(
+        &mut self,
+        chain: &mut Chain<PoaSettings>,
+    ) -> Result<Option<Duration>, Error> {
+        let block_num = chain.head.block_num + 1;
+        let chain_head_id = chain.head.block_id.clone();
+        let settings = chain.settings_at(&chain_head_id)?;
+        let signer = settings.signer_of(block_num);
+        if signer != self.local_signer {
+            debug!("Waiting for {} to publish block {}", signer, block_num);
+            return Ok(None);
+        }
+        Ok(Some(settings.block_interval))
+    }
+
+    /// Fail a new block unless it was published by the signer whose turn it was, and have it
+    /// checked otherwise
+    fn on_block_new(&mut self, chain: &mut Chain<PoaSettings>, block: Block) -> Result<(), Error> {
+        let signer = hex::encode(&block.signer_id);
+        let expected = chain
+            .settings_at(&block.previous_id)?
+            .signer_of(block.block_num)
+            .to_string();
+        if signer != expected {
+            warn!(
+                "Block {} at height {} was published by {} instead of {}",
+                hex::encode(&block.block_id),
+                block.block_num,
+                signer,
+                expected
+            );
+            return chain.service.fail_block(block.block_id);
+        }
+        chain.service.check_blocks(vec![block.block_id])
+    }
+
+    /// Only a block which extends the chain head is committed
+    fn should_commit(&self, block: &Block, chain_head: &Block) -> bool {
+        block.previous_id == chain_head.block_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use sawtooth_consensus_node::testing::{start_engine, stop_engine, wait_for_commit, TIMEOUT};
+    use sawtooth_sdk::consensus::testing::{ServiceCall, TestService};
+
+    fn set_signers(service: &TestService, signers: &[&[u8]]) {
+        let signers = signers.iter().map(hex::encode).collect::<Vec<_>>();
+        service.set_setting(SIGNERS_SETTING, &signers.join(","));
+        service.set_setting(BLOCK_INTERVAL_SETTING, "0");
+    }
+
+    #[test]
+    fn signer_rotation() {
+        let mut values = HashMap::new();
+        values.insert(
+            SIGNERS_SETTING.to_string(),
+            "[\"AA\", \"bb\", \"cc\"]".to_string(),
+        );
+        let settings = PoaSettings::from_values(&SettingValues::new(values)).unwrap();
+
+        assert_eq!(settings.block_interval, Duration::from_secs(1));
+        assert_eq!(settings.signer_of(1), "aa");
+        assert_eq!(settings.signer_of(2), "bb");
+        assert_eq!(settings.signer_of(3), "cc");
+        assert_eq!(settings.signer_of(4), "aa");
+
+        match PoaSettings::from_values(&SettingValues::default()) {
+            Err(SettingsError::InvalidSetting(_)) => (),
+            res => panic!("Expected InvalidSetting, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn signers_take_turns() {
+        let (service, updates) = TestService::new(b"local".to_vec());
+        set_signers(&service, &[b"local", b"peer"]);
+        let engine = start_engine(PoaEngine::new(), &service, updates);
+
+        let block1 = service.wait_for_block(1, TIMEOUT);
+        wait_for_commit(&service, &block1.block_id);
+
+        // Block 2 is the peer's to publish
+        let block2 = service.build_block(&block1.block_id, b"peer", b"");
+        service.receive_block(block2.clone());
+        wait_for_commit(&service, &block2.block_id);
+
+        let block3 = service.wait_for_block(3, TIMEOUT);
+        assert_eq!(block3.previous_id, block2.block_id);
+        wait_for_commit(&service, &block3.block_id);
+
+        stop_engine(&service, engine);
+
+        let published = service
+            .calls()
+            .into_iter()
+            .filter(|call| matches!(call, ServiceCall::FinalizeBlock(_)))
+            .count();
+        assert_eq!(published, 2);
+        assert_eq!(block1.payload, PoaPolicy::PAYLOAD);
+    }
+
+    #[test]
+    fn fails_blocks_from_other_signers() {
+        let (service, updates) = TestService::new(b"local".to_vec());
+        set_signers(&service, &[b"peer1", b"peer2"]);
+        let genesis_id = service.chain_head().block_id;
+        let engine = start_engine(PoaEngine::new(), &service, updates);
+
+        // Block 1 is peer1's to publish
+        let block = service.build_block(&genesis_id, b"peer2", b"");
+        service.receive_block(block.clone());
+        service.wait_for_call(
+            |call| *call == ServiceCall::FailBlock(block.block_id.clone()),
+            TIMEOUT,
+        );
+
+        let block = service.build_block(&genesis_id, b"peer1", b"");
+        service.receive_block(block.clone());
+        wait_for_commit(&service, &block.block_id);
+
+        stop_engine(&service, engine);
+
+        // The local validator is not a signer, so it never builds blocks
+        service.assert_not_called(|call| matches!(call, ServiceCall::InitializeBlock(_)));
+    }
+}
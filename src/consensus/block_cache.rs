@@ -0,0 +1,504 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! A cache of the block tree seen by a consensus engine.
+//!
+//! `BlockCache` indexes the blocks announced with `Update::BlockNew` by their id and answers
+//! fork-resolution queries: walking a block's ancestors, finding the common ancestor of two
+//! blocks and comparing the branches leading to them. Ancestors which are not in the cache are
+//! fetched from the validator with `Service::get_blocks` as they are needed. When a block is
+//! committed, the blocks more than a retention window below it are pruned; ancestors fetched
+//! within the window are kept, so that repeated walks across recent forks do not fetch them
+//! again.
+//!
+//! ```no_run
+//! # use sawtooth_sdk::consensus::block_cache::BlockCache;
+//! # use sawtooth_sdk::consensus::engine::{Error, Update};
+//! # use sawtooth_sdk::consensus::service::Service;
+//! # use std::cmp::Ordering;
+//! # fn handle(
+//! #     cache: &mut BlockCache,
+//! #     service: &mut dyn Service,
+//! #     update: Update,
+//! #     chain_head: &[u8],
+//! # ) -> Result<(), Error> {
+//! cache.update(&update);
+//! if let Update::BlockNew(block) = update {
+//!     let fork = cache.fork(service, chain_head, &block.block_id)?;
+//!     if fork.compare() == Ordering::Less {
+//!         // The new block is on a longer branch than the chain head
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::consensus::engine::{Block, BlockId, Error, Update};
+use crate::consensus::service::Service;
+
+/// The number of heights below the last committed block whose blocks are kept by default
+const DEFAULT_RETENTION: u64 = 16;
+
+/// The two branches leading from the common ancestor of two blocks to each of them
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fork {
+    /// The most recent block which both branches extend
+    pub common_ancestor: Block,
+    /// The blocks after the common ancestor leading to the first block, in ascending order
+    pub left: Vec<Block>,
+    /// The blocks after the common ancestor leading to the second block, in ascending order
+    pub right: Vec<Block>,
+}
+
+impl Fork {
+    /// Compare the lengths of the two branches
+    pub fn compare(&self) -> Ordering {
+        self.left.len().cmp(&self.right.len())
+    }
+
+    /// Returns true if one of the blocks is an ancestor of, or the same as, the other
+    pub fn is_linear(&self) -> bool {
+        self.left.is_empty() || self.right.is_empty()
+    }
+}
+
+/// Blocks indexed by id, with their ancestors fetched from the validator as needed
+#[derive(Debug)]
+pub struct BlockCache {
+    blocks: HashMap<BlockId, Block>,
+    // The height of the last committed block
+    committed_num: Option<u64>,
+    // How many heights below the last committed block are kept; lower blocks are pruned
+    retention: u64,
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        BlockCache::with_retention(DEFAULT_RETENTION)
+    }
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+        BlockCache::default()
+    }
+
+    /// Create a cache which keeps the blocks down to `retention` heights below the last
+    /// committed block
+    pub fn with_retention(retention: u64) -> Self {
+        BlockCache {
+            blocks: HashMap::new(),
+            committed_num: None,
+            retention,
+        }
+    }
+
+    /// Index the block of an `Update::BlockNew`, and prune the blocks below the retention window
+    /// of the block of an `Update::BlockCommit`. Other updates are ignored.
+    pub fn update(&mut self, update: &Update) {
+        match update {
+            Update::BlockNew(block) => self.insert(block.clone()),
+            Update::BlockCommit(block_id) => self.commit(block_id),
+            _ => (),
+        }
+    }
+
+    /// Add a block to the cache, unless it is below the retention window
+    pub fn insert(&mut self, block: Block) {
+        if self
+            .retained_num()
+            .map(|retained_num| block.block_num >= retained_num)
+            .unwrap_or(true)
+        {
+            self.blocks.insert(block.block_id.clone(), block);
+        }
+    }
+
+    /// Record that a block was committed, removing every block more than the retention window
+    /// below its height
+    pub fn commit(&mut self, block_id: &[u8]) {
+        let committed_num = match self.blocks.get(block_id) {
+            Some(block) => block.block_num,
+            None => {
+                warn!(
+                    "Committed block {} is not in the cache",
+                    hex::encode(block_id)
+                );
+                return;
+            }
+        };
+        self.committed_num = Some(committed_num);
+        let retained_num = committed_num.saturating_sub(self.retention);
+        self.blocks
+            .retain(|_, block| block.block_num >= retained_num);
+    }
+
+    /// Returns the lowest height whose blocks are kept, if a block has been committed
+    fn retained_num(&self) -> Option<u64> {
+        self.committed_num
+            .map(|committed_num| committed_num.saturating_sub(self.retention))
+    }
+
+    /// Returns the block with the given id, if it is in the cache
+    pub fn get(&self, block_id: &[u8]) -> Option<&Block> {
+        self.blocks.get(block_id)
+    }
+
+    pub fn contains(&self, block_id: &[u8]) -> bool {
+        self.blocks.contains_key(block_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Returns the block with the given id, fetching it from the validator if it is not in the
+    /// cache
+    pub fn get_or_fetch(
+        &mut self,
+        service: &mut dyn Service,
+        block_id: &[u8],
+    ) -> Result<Block, Error> {
+        if let Some(block) = self.blocks.get(block_id) {
+            return Ok(block.clone());
+        }
+
+        let block = service
+            .get_blocks(vec![block_id.to_vec()])?
+            .remove(block_id)
+            .ok_or_else(|| Error::UnknownBlock(hex::encode(block_id)))?;
+        self.insert(block.clone());
+        Ok(block)
+    }
+
+    /// Returns the parent of a block, or `None` for the genesis block
+    pub fn parent(
+        &mut self,
+        service: &mut dyn Service,
+        block: &Block,
+    ) -> Result<Option<Block>, Error> {
+        if block.block_num == 0 {
+            Ok(None)
+        } else {
+            self.get_or_fetch(service, &block.previous_id).map(Some)
+        }
+    }
+
+    /// Returns the blocks from `block_id` back to its ancestor at `block_num`, both included, in
+    /// descending order
+    pub fn ancestors(
+        &mut self,
+        service: &mut dyn Service,
+        block_id: &[u8],
+        block_num: u64,
+    ) -> Result<Vec<Block>, Error> {
+        let mut block = self.get_or_fetch(service, block_id)?;
+        if block.block_num < block_num {
+            return Err(Error::InvalidState(format!(
+                "Block {} is below height {}",
+                hex::encode(block_id),
+                block_num
+            )));
+        }
+
+        let mut ancestors = vec![];
+        while block.block_num > block_num {
+            let parent = self.get_or_fetch(service, &block.previous_id)?;
+            ancestors.push(block);
+            block = parent;
+        }
+        ancestors.push(block);
+        Ok(ancestors)
+    }
+
+    /// Returns the ancestor of `block_id` at `block_num`
+    pub fn ancestor_at(
+        &mut self,
+        service: &mut dyn Service,
+        block_id: &[u8],
+        block_num: u64,
+    ) -> Result<Block, Error> {
+        let mut block = self.get_or_fetch(service, block_id)?;
+        if block.block_num < block_num {
+            return Err(Error::InvalidState(format!(
+                "Block {} is below height {}",
+                hex::encode(block_id),
+                block_num
+            )));
+        }
+        while block.block_num > block_num {
+            block = self.get_or_fetch(service, &block.previous_id)?;
+        }
+        Ok(block)
+    }
+
+    /// Returns true if `ancestor_id` is `block_id` or one of its ancestors
+    pub fn is_ancestor(
+        &mut self,
+        service: &mut dyn Service,
+        ancestor_id: &[u8],
+        block_id: &[u8],
+    ) -> Result<bool, Error> {
+        let ancestor = self.get_or_fetch(service, ancestor_id)?;
+        let block = self.get_or_fetch(service, block_id)?;
+        if block.block_num < ancestor.block_num {
+            return Ok(false);
+        }
+        Ok(self
+            .ancestor_at(service, block_id, ancestor.block_num)?
+            .block_id
+            == ancestor_id)
+    }
+
+    /// Returns the most recent block which both blocks are or extend
+    pub fn common_ancestor(
+        &mut self,
+        service: &mut dyn Service,
+        left_id: &[u8],
+        right_id: &[u8],
+    ) -> Result<Block, Error> {
+        self.fork(service, left_id, right_id)
+            .map(|fork| fork.common_ancestor)
+    }
+
+    /// Find the common ancestor of two blocks and the branches leading from it to each of them
+    pub fn fork(
+        &mut self,
+        service: &mut dyn Service,
+        left_id: &[u8],
+        right_id: &[u8],
+    ) -> Result<Fork, Error> {
+        let mut left = self.get_or_fetch(service, left_id)?;
+        let mut right = self.get_or_fetch(service, right_id)?;
+        let mut left_branch = vec![];
+        let mut right_branch = vec![];
+
+        // Walk back the higher branch until both are at the same height, then both together
+        while left.block_id != right.block_id {
+            let (left_parent, right_parent) = match left.block_num.cmp(&right.block_num) {
+                Ordering::Greater => (self.parent(service, &left)?, Some(right.clone())),
+                Ordering::Less => (Some(left.clone()), self.parent(service, &right)?),
+                Ordering::Equal => (self.parent(service, &left)?, self.parent(service, &right)?),
+            };
+            match (left_parent, right_parent) {
+                (Some(left_parent), Some(right_parent)) => {
+                    if left_parent.block_id != left.block_id {
+                        left_branch.push(left);
+                    }
+                    if right_parent.block_id != right.block_id {
+                        right_branch.push(right);
+                    }
+                    left = left_parent;
+                    right = right_parent;
+                }
+                _ => {
+                    return Err(Error::InvalidState(format!(
+                        "Blocks {} and {} have no common ancestor",
+                        hex::encode(left_id),
+                        hex::encode(right_id)
+                    )))
+                }
+            }
+        }
+
+        left_branch.reverse();
+        right_branch.reverse();
+        Ok(Fork {
+            common_ancestor: left,
+            left: left_branch,
+            right: right_branch,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::consensus::testing::{ServiceCall, TestService};
+
+    /// Add a chain of `length` blocks on `previous_id` to the service's block tree
+    fn build_chain(
+        service: &TestService,
+        previous_id: &[u8],
+        signer_id: &[u8],
+        length: usize,
+    ) -> Vec<Block> {
+        let mut previous_id = previous_id.to_vec();
+        (0..length)
+            .map(|_| {
+                let block = service.build_block(&previous_id, signer_id, b"");
+                service.receive_block(block.clone());
+                previous_id = block.block_id.clone();
+                block
+            })
+            .collect()
+    }
+
+    fn get_blocks_calls(service: &TestService) -> usize {
+        service
+            .calls()
+            .iter()
+            .filter(|call| matches!(call, ServiceCall::GetBlocks(_)))
+            .count()
+    }
+
+    #[test]
+    fn fork_fetches_missing_ancestors() {
+        let (mut service, _updates) = TestService::new(b"local".to_vec());
+        let genesis = service.chain_head();
+        let trunk = build_chain(&service, &genesis.block_id, b"a", 3);
+        let left = build_chain(&service, &trunk[2].block_id, b"a", 2);
+        let right = build_chain(&service, &trunk[2].block_id, b"b", 3);
+
+        // Only the tips are announced to the cache
+        let mut cache = BlockCache::new();
+        cache.update(&Update::BlockNew(left[1].clone()));
+        cache.update(&Update::BlockNew(right[2].clone()));
+
+        let fork = cache
+            .fork(&mut service, &left[1].block_id, &right[2].block_id)
+            .unwrap();
+        assert_eq!(fork.common_ancestor, trunk[2]);
+        assert_eq!(fork.left, left);
+        assert_eq!(fork.right, right);
+        assert_eq!(fork.compare(), Ordering::Less);
+        assert!(!fork.is_linear());
+        assert_eq!(get_blocks_calls(&service), 4);
+
+        // The fetched ancestors are cached
+        assert_eq!(
+            cache
+                .common_ancestor(&mut service, &right[2].block_id, &left[1].block_id)
+                .unwrap(),
+            trunk[2]
+        );
+        assert_eq!(get_blocks_calls(&service), 4);
+
+        let fork = cache
+            .fork(&mut service, &trunk[0].block_id, &left[1].block_id)
+            .unwrap();
+        assert_eq!(fork.common_ancestor, trunk[0]);
+        assert!(fork.is_linear());
+        assert_eq!(fork.right.len(), 4);
+
+        assert!(cache
+            .is_ancestor(&mut service, &genesis.block_id, &right[0].block_id)
+            .unwrap());
+        assert!(!cache
+            .is_ancestor(&mut service, &left[0].block_id, &right[2].block_id)
+            .unwrap());
+    }
+
+    #[test]
+    fn ancestor_walk() {
+        let (mut service, _updates) = TestService::new(b"local".to_vec());
+        let genesis = service.chain_head();
+        let chain = build_chain(&service, &genesis.block_id, b"a", 4);
+
+        let mut cache = BlockCache::new();
+        let ancestors = cache
+            .ancestors(&mut service, &chain[3].block_id, 2)
+            .unwrap();
+        assert_eq!(
+            ancestors,
+            vec![chain[3].clone(), chain[2].clone(), chain[1].clone()]
+        );
+        assert_eq!(
+            cache
+                .ancestor_at(&mut service, &chain[3].block_id, 0)
+                .unwrap(),
+            genesis
+        );
+        match cache.ancestor_at(&mut service, &chain[0].block_id, 2) {
+            Err(Error::InvalidState(_)) => (),
+            res => panic!("Expected InvalidState, got {:?}", res),
+        }
+        match cache.get_or_fetch(&mut service, b"unknown") {
+            Err(Error::UnknownBlock(_)) => (),
+            res => panic!("Expected UnknownBlock, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn commit_prunes_lower_blocks() {
+        let (mut service, _updates) = TestService::new(b"local".to_vec());
+        let genesis = service.chain_head();
+        let chain = build_chain(&service, &genesis.block_id, b"a", 3);
+
+        let mut cache = BlockCache::with_retention(0);
+        cache.update(&Update::BlockNew(genesis.clone()));
+        for block in &chain {
+            cache.update(&Update::BlockNew(block.clone()));
+        }
+        assert_eq!(cache.len(), 4);
+
+        cache.update(&Update::BlockCommit(chain[1].block_id.clone()));
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains(&genesis.block_id));
+        assert!(!cache.contains(&chain[0].block_id));
+        assert!(cache.contains(&chain[1].block_id));
+
+        // Blocks below the committed height are not cached again
+        cache
+            .get_or_fetch(&mut service, &chain[0].block_id)
+            .unwrap();
+        assert!(!cache.contains(&chain[0].block_id));
+    }
+
+    #[test]
+    fn commit_retains_recent_ancestors() {
+        let (mut service, _updates) = TestService::new(b"local".to_vec());
+        let genesis = service.chain_head();
+        let chain = build_chain(&service, &genesis.block_id, b"a", 4);
+
+        let mut cache = BlockCache::with_retention(1);
+        cache.update(&Update::BlockNew(chain[3].clone()));
+        cache.update(&Update::BlockCommit(chain[3].block_id.clone()));
+
+        // The parent of the committed block is within the window, so it is fetched only once
+        for _ in 0..2 {
+            assert_eq!(
+                cache
+                    .get_or_fetch(&mut service, &chain[2].block_id)
+                    .unwrap(),
+                chain[2]
+            );
+        }
+        assert_eq!(get_blocks_calls(&service), 1);
+
+        // Lower blocks are not kept
+        cache
+            .get_or_fetch(&mut service, &chain[1].block_id)
+            .unwrap();
+        assert!(!cache.contains(&chain[1].block_id));
+
+        // Committing a later block moves the window up
+        let next = build_chain(&service, &chain[3].block_id, b"a", 1).remove(0);
+        cache.update(&Update::BlockNew(next.clone()));
+        cache.update(&Update::BlockCommit(next.block_id.clone()));
+        assert!(!cache.contains(&chain[2].block_id));
+        assert!(cache.contains(&chain[3].block_id));
+    }
+}
@@ -32,6 +32,16 @@ pub enum Update {
     BlockValid(BlockId),
     BlockInvalid(BlockId),
     BlockCommit(BlockId),
+    /// The connection to the validator was lost and re-established; the engine should resync
+    /// its state from the new startup state, since updates may have been missed meanwhile
+    Reconnected(StartupState),
+    /// The validator activated the engine again after deactivating it. An engine which returned
+    /// from `start` when it was deactivated is started again instead of receiving this update.
+    Activated(StartupState),
+    /// The validator deactivated the engine, typically because the network switched to another
+    /// consensus algorithm. The engine may return from `start`, in which case the driver starts it
+    /// again if it is reactivated, or keep running until it receives `Update::Activated`.
+    Deactivated,
     Shutdown,
 }
 
@@ -66,7 +76,7 @@ impl fmt::Debug for Block {
 pub type PeerId = Vec<u8>;
 
 /// Information about a peer that is relevant to consensus
-#[derive(Default, Debug, PartialEq, Hash)]
+#[derive(Clone, Default, Debug, PartialEq, Hash)]
 pub struct PeerInfo {
     pub peer_id: PeerId,
 }
@@ -142,7 +152,7 @@ pub trait Engine {
 }
 
 /// State provided to an engine when it is started
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct StartupState {
     pub chain_head: Block,
     pub peers: Vec<PeerInfo>,
@@ -159,6 +169,14 @@ pub enum Error {
     UnknownPeer(String),
     NoChainHead,
     BlockNotReady,
+    /// The validator is not ready to handle the request yet; it may be retried later
+    NotReady,
+    /// The engine is not the validator's active consensus engine
+    NotActiveEngine,
+    /// The validator rejected the request as malformed
+    BadRequest(String),
+    /// The validator failed to handle the request
+    ServiceError(String),
 }
 
 impl error::Error for Error {}
@@ -175,6 +193,10 @@ impl fmt::Display for Error {
             UnknownPeer(ref s) => write!(f, "UnknownPeer: {}", s),
             NoChainHead => write!(f, "NoChainHead"),
             BlockNotReady => write!(f, "BlockNotReady"),
+            NotReady => write!(f, "NotReady"),
+            NotActiveEngine => write!(f, "NotActiveEngine"),
+            BadRequest(ref s) => write!(f, "BadRequest: {}", s),
+            ServiceError(ref s) => write!(f, "ServiceError: {}", s),
         }
     }
 }
@@ -250,6 +272,16 @@ pub mod tests {
                             Update::BlockCommit(_) => {
                                 (*self.calls.lock().unwrap()).push("BlockCommit".into())
                             }
+                            Update::Reconnected(_) => {
+                                (*self.calls.lock().unwrap()).push("Reconnected".into())
+                            }
+                            Update::Activated(_) => {
+                                (*self.calls.lock().unwrap()).push("Activated".into())
+                            }
+                            Update::Deactivated => {
+                                (*self.calls.lock().unwrap()).push("Deactivated".into());
+                                break;
+                            }
                             Update::Shutdown => {
                                 println!("shutdown");
                                 break;
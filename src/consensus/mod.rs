@@ -15,8 +15,12 @@
  * ------------------------------------------------------------------------------
  */
 
+pub mod block_cache;
 pub mod engine;
+pub mod peer_message;
 pub mod service;
+pub mod simulation;
+pub mod testing;
 
 pub mod zmq_driver;
 pub mod zmq_service;
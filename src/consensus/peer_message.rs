@@ -0,0 +1,345 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Verification of peer messages, and signed envelopes for an engine's own message types.
+//!
+//! The validator wraps every message sent with `Service::send_to` or `Service::broadcast` in a
+//! header signed with its key. `verify_peer_message` checks that signature and the digest of the
+//! content; `ZmqDriver::set_peer_message_verifier` makes the driver drop messages which fail the
+//! check.
+//!
+//! An engine which relays messages, or which must prove the origin of a message independently of
+//! the validator that forwarded it, can sign its messages itself: an `EnvelopeCodec` encodes a
+//! `EnvelopeMessage` into a signed envelope, in the same format as the validator's, and
+//! `decode_envelope` verifies and decodes it.
+
+use std::error;
+use std::fmt;
+
+use protobuf::Message as ProtobufMessage;
+use sha2::{Digest, Sha512};
+
+use crate::consensus::engine::{Error, PeerId, PeerMessage, PeerMessageHeader};
+use crate::consensus::service::Service;
+use crate::consensus::zmq_driver::from_consensus_peer_message;
+use crate::messages::consensus::{ConsensusPeerMessage, ConsensusPeerMessageHeader};
+use crate::signing::{self, Context, PublicKey, Signer};
+
+/// The reason a peer message failed verification
+#[derive(Debug)]
+pub enum VerificationError {
+    /// The envelope could not be decoded
+    DecodeError(String),
+    /// The SHA-512 digest of the content does not match the one in the header
+    ContentDigestMismatch,
+    /// The header signature was not produced by the signer's key
+    InvalidSignature,
+    /// The signer's key or the signature could not be parsed
+    SigningError(signing::Error),
+}
+
+impl error::Error for VerificationError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            VerificationError::SigningError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VerificationError::DecodeError(ref s) => write!(f, "DecodeError: {}", s),
+            VerificationError::ContentDigestMismatch => write!(f, "ContentDigestMismatch"),
+            VerificationError::InvalidSignature => write!(f, "InvalidSignature"),
+            VerificationError::SigningError(ref err) => write!(f, "SigningError: {}", err),
+        }
+    }
+}
+
+impl From<signing::Error> for VerificationError {
+    fn from(err: signing::Error) -> Self {
+        VerificationError::SigningError(err)
+    }
+}
+
+/// A public key given as raw bytes, such as a message's `signer_id`
+struct SignerKey<'a> {
+    algorithm_name: &'a str,
+    bytes: &'a [u8],
+}
+
+impl<'a> PublicKey for SignerKey<'a> {
+    fn get_algorithm_name(&self) -> &str {
+        self.algorithm_name
+    }
+
+    fn as_hex(&self) -> String {
+        hex::encode(self.bytes)
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        self.bytes
+    }
+}
+
+/// Check that a peer message's header was signed by its `signer_id` and that its content matches
+/// the digest in the header
+pub fn verify_peer_message(
+    context: &dyn Context,
+    message: &PeerMessage,
+) -> Result<(), VerificationError> {
+    if Sha512::digest(&message.content).as_slice() != message.header.content_sha512.as_slice() {
+        return Err(VerificationError::ContentDigestMismatch);
+    }
+
+    let signer_key = SignerKey {
+        algorithm_name: context.get_algorithm_name(),
+        bytes: &message.header.signer_id,
+    };
+    if context.verify(
+        &hex::encode(&message.header_signature),
+        &message.header_bytes,
+        &signer_key,
+    )? {
+        Ok(())
+    } else {
+        Err(VerificationError::InvalidSignature)
+    }
+}
+
+/// A message type of an engine, which can be carried in a signed envelope
+pub trait EnvelopeMessage: Sized {
+    /// The message type of the envelope, used to select how its content is decoded
+    fn message_type(&self) -> String;
+
+    /// Serialize the message into the envelope's content
+    fn to_bytes(&self) -> Result<Vec<u8>, Error>;
+
+    /// Deserialize a message from the content of an envelope of the given message type
+    fn from_bytes(message_type: &str, bytes: &[u8]) -> Result<Self, Error>;
+}
+
+/// A verified envelope and the message it carried
+#[derive(Debug)]
+pub struct Envelope<M> {
+    pub header: PeerMessageHeader,
+    pub message: M,
+}
+
+/// Encodes an engine's messages into envelopes signed with the engine's key
+pub struct EnvelopeCodec<'a> {
+    signer: Signer<'a>,
+    signer_id: PeerId,
+    name: String,
+    version: String,
+}
+
+impl<'a> EnvelopeCodec<'a> {
+    /// Create a codec signing envelopes with `signer`, and recording the engine's `name` and
+    /// `version` in their headers
+    pub fn new(signer: Signer<'a>, name: &str, version: &str) -> Result<Self, signing::Error> {
+        let signer_id = signer.get_public_key()?.as_slice().to_vec();
+        Ok(EnvelopeCodec {
+            signer,
+            signer_id,
+            name: name.into(),
+            version: version.into(),
+        })
+    }
+
+    /// Returns the id of the envelopes' signer, which is its public key
+    pub fn signer_id(&self) -> &PeerId {
+        &self.signer_id
+    }
+
+    /// Encode a message into a signed envelope, returning the message type and payload to send
+    /// with `Service::send_to` or `Service::broadcast`
+    pub fn encode<M: EnvelopeMessage>(&self, message: &M) -> Result<(String, Vec<u8>), Error> {
+        let message_type = message.message_type();
+        let content = message.to_bytes()?;
+
+        let mut header = ConsensusPeerMessageHeader::new();
+        header.set_signer_id(self.signer_id.clone());
+        header.set_content_sha512(Sha512::digest(&content).to_vec());
+        header.set_message_type(message_type.clone());
+        header.set_name(self.name.clone());
+        header.set_version(self.version.clone());
+        let header_bytes = header.write_to_bytes()?;
+
+        let signature = self
+            .signer
+            .sign(&header_bytes)
+            .map_err(|err| Error::EncodingError(format!("Failed to sign envelope: {}", err)))?;
+
+        let mut envelope = ConsensusPeerMessage::new();
+        envelope.set_header(header_bytes);
+        envelope.set_header_signature(
+            hex::decode(signature)
+                .map_err(|err| Error::EncodingError(format!("Invalid signature: {}", err)))?,
+        );
+        envelope.set_content(content);
+
+        Ok((message_type, envelope.write_to_bytes()?))
+    }
+
+    /// Send a message in a signed envelope to a peer
+    pub fn send_to<M: EnvelopeMessage>(
+        &self,
+        service: &mut dyn Service,
+        peer: &PeerId,
+        message: &M,
+    ) -> Result<(), Error> {
+        let (message_type, payload) = self.encode(message)?;
+        service.send_to(peer, &message_type, payload)
+    }
+
+    /// Broadcast a message in a signed envelope to all peers
+    pub fn broadcast<M: EnvelopeMessage>(
+        &self,
+        service: &mut dyn Service,
+        message: &M,
+    ) -> Result<(), Error> {
+        let (message_type, payload) = self.encode(message)?;
+        service.broadcast(&message_type, payload)
+    }
+}
+
+/// Verify a signed envelope, such as the content of a received `PeerMessage`, and decode the
+/// message it carries
+pub fn decode_envelope<M: EnvelopeMessage>(
+    context: &dyn Context,
+    payload: &[u8],
+) -> Result<Envelope<M>, VerificationError> {
+    let decode_error =
+        |err: protobuf::ProtobufError| VerificationError::DecodeError(err.to_string());
+    let envelope: ConsensusPeerMessage =
+        ProtobufMessage::parse_from_bytes(payload).map_err(decode_error)?;
+    let header: ConsensusPeerMessageHeader =
+        ProtobufMessage::parse_from_bytes(envelope.get_header()).map_err(decode_error)?;
+
+    let peer_message = from_consensus_peer_message(envelope, header);
+    verify_peer_message(context, &peer_message)?;
+
+    let message = M::from_bytes(&peer_message.header.message_type, &peer_message.content)
+        .map_err(|err| VerificationError::DecodeError(err.to_string()))?;
+    Ok(Envelope {
+        header: peer_message.header,
+        message,
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    use crate::signing::secp256k1::{Secp256k1Context, Secp256k1PrivateKey};
+    use crate::signing::test_keys::KEY1_PRIV_HEX;
+
+    #[derive(Debug, PartialEq)]
+    pub enum Vote {
+        Yes(u64),
+        No(u64),
+    }
+
+    impl EnvelopeMessage for Vote {
+        fn message_type(&self) -> String {
+            match self {
+                Vote::Yes(_) => "yes".into(),
+                Vote::No(_) => "no".into(),
+            }
+        }
+
+        fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+            match self {
+                Vote::Yes(block_num) | Vote::No(block_num) => Ok(block_num.to_be_bytes().to_vec()),
+            }
+        }
+
+        fn from_bytes(message_type: &str, bytes: &[u8]) -> Result<Self, Error> {
+            let mut block_num = [0; 8];
+            if bytes.len() != block_num.len() {
+                return Err(Error::EncodingError("Invalid vote".into()));
+            }
+            block_num.copy_from_slice(bytes);
+            match message_type {
+                "yes" => Ok(Vote::Yes(u64::from_be_bytes(block_num))),
+                "no" => Ok(Vote::No(u64::from_be_bytes(block_num))),
+                _ => Err(Error::EncodingError(format!(
+                    "Unknown message type {}",
+                    message_type
+                ))),
+            }
+        }
+    }
+
+    pub fn codec() -> EnvelopeCodec<'static> {
+        let key = Secp256k1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap();
+        EnvelopeCodec::new(
+            Signer::new_boxed(Box::new(Secp256k1Context::new()), Box::new(key)),
+            "test",
+            "0.1",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn envelope_round_trip() {
+        let context = Secp256k1Context::new();
+        let codec = codec();
+
+        let (message_type, payload) = codec.encode(&Vote::Yes(7)).unwrap();
+        assert_eq!(message_type, "yes");
+
+        let envelope: Envelope<Vote> = decode_envelope(&context, &payload).unwrap();
+        assert_eq!(envelope.message, Vote::Yes(7));
+        assert_eq!(&envelope.header.signer_id, codec.signer_id());
+        assert_eq!(envelope.header.name, "test");
+        assert_eq!(envelope.header.version, "0.1");
+    }
+
+    #[test]
+    fn tampered_envelopes_are_rejected() {
+        let context = Secp256k1Context::new();
+        let codec = codec();
+        let (_, payload) = codec.encode(&Vote::No(3)).unwrap();
+        let envelope: ConsensusPeerMessage = ProtobufMessage::parse_from_bytes(&payload).unwrap();
+
+        let mut tampered = envelope.clone();
+        tampered.set_content(4u64.to_be_bytes().to_vec());
+        match decode_envelope::<Vote>(&context, &tampered.write_to_bytes().unwrap()) {
+            Err(VerificationError::ContentDigestMismatch) => (),
+            res => panic!("Expected ContentDigestMismatch, got {:?}", res),
+        }
+
+        let mut header: ConsensusPeerMessageHeader =
+            ProtobufMessage::parse_from_bytes(envelope.get_header()).unwrap();
+        header.set_message_type("yes".into());
+        let mut tampered = envelope.clone();
+        tampered.set_header(header.write_to_bytes().unwrap());
+        match decode_envelope::<Vote>(&context, &tampered.write_to_bytes().unwrap()) {
+            Err(VerificationError::InvalidSignature) => (),
+            res => panic!("Expected InvalidSignature, got {:?}", res),
+        }
+
+        match decode_envelope::<Vote>(&context, b"garbage") {
+            Err(VerificationError::DecodeError(_)) => (),
+            res => panic!("Expected DecodeError, got {:?}", res),
+        }
+    }
+}
@@ -17,6 +17,8 @@
 
 use crate::consensus::engine::{Block, BlockId, Error, PeerId};
 use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
 
 /// Provides methods that allow the consensus engine to issue commands and requests.
 pub trait Service {
@@ -85,6 +87,30 @@ pub trait Service {
     ) -> Result<HashMap<String, Vec<u8>>, Error>;
 }
 
+/// Summarize the block in progress, polling `summarize_block` every `poll_interval` while it
+/// returns `Error::BlockNotReady`.
+///
+/// Returns `Error::BlockNotReady` if the block still cannot be summarized once `deadline` has
+/// passed; other errors are returned immediately.
+pub fn summarize_block_by(
+    service: &mut dyn Service,
+    deadline: Instant,
+    poll_interval: Duration,
+) -> Result<Vec<u8>, Error> {
+    loop {
+        match service.summarize_block() {
+            Err(Error::BlockNotReady) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(Error::BlockNotReady);
+                }
+                thread::sleep(poll_interval.min(deadline - now));
+            }
+            result => return result,
+        }
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -152,4 +178,76 @@ pub mod tests {
             Ok(Default::default())
         }
     }
+
+    /// A service whose block can be summarized after a number of attempts
+    struct SlowBlockService {
+        inner: MockService,
+        not_ready: usize,
+    }
+
+    macro_rules! delegate {
+        ($($name:ident($($arg:ident: $ty:ty),*) -> $ret:ty;)*) => {
+            $(fn $name(&mut self, $($arg: $ty),*) -> Result<$ret, Error> {
+                self.inner.$name($($arg),*)
+            })*
+        };
+    }
+
+    impl Service for SlowBlockService {
+        fn summarize_block(&mut self) -> Result<Vec<u8>, Error> {
+            if self.not_ready > 0 {
+                self.not_ready -= 1;
+                Err(Error::BlockNotReady)
+            } else {
+                Ok(b"summary".to_vec())
+            }
+        }
+
+        delegate! {
+            send_to(peer: &PeerId, message_type: &str, payload: Vec<u8>) -> ();
+            broadcast(message_type: &str, payload: Vec<u8>) -> ();
+            initialize_block(previous_id: Option<BlockId>) -> ();
+            finalize_block(data: Vec<u8>) -> BlockId;
+            cancel_block() -> ();
+            check_blocks(priority: Vec<BlockId>) -> ();
+            commit_block(block_id: BlockId) -> ();
+            ignore_block(block_id: BlockId) -> ();
+            fail_block(block_id: BlockId) -> ();
+            get_blocks(block_ids: Vec<BlockId>) -> HashMap<BlockId, Block>;
+            get_chain_head() -> Block;
+            get_settings(block_id: BlockId, keys: Vec<String>) -> HashMap<String, String>;
+            get_state(block_id: BlockId, addresses: Vec<String>) -> HashMap<String, Vec<u8>>;
+        }
+    }
+
+    #[test]
+    fn summarize_block_by_polls() {
+        let mut service = SlowBlockService {
+            inner: MockService {},
+            not_ready: 3,
+        };
+        let summary = summarize_block_by(
+            &mut service,
+            Instant::now() + Duration::from_secs(5),
+            Duration::from_millis(1),
+        )
+        .unwrap();
+        assert_eq!(summary, b"summary");
+    }
+
+    #[test]
+    fn summarize_block_by_deadline() {
+        let mut service = SlowBlockService {
+            inner: MockService {},
+            not_ready: usize::MAX,
+        };
+        match summarize_block_by(
+            &mut service,
+            Instant::now() + Duration::from_millis(20),
+            Duration::from_millis(5),
+        ) {
+            Err(Error::BlockNotReady) => (),
+            res => panic!("Expected BlockNotReady, got {:?}", res),
+        }
+    }
 }
@@ -0,0 +1,1142 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! An in-process network of consensus engines.
+//!
+//! A `Simulator` runs one instance of an `Engine` per node, each on its own thread with its own
+//! `Service`. Peer messages sent with `send_to` and `broadcast`, and the blocks published by each
+//! node, are routed to the other nodes through a simulated network which delays them, drops peer
+//! messages and enforces partitions; nodes may be crashed and restarted. Every random decision is
+//! made by an RNG seeded from `SimulationConfig::seed`, although the scheduling of the engine
+//! threads is left to the operating system.
+//!
+//! Every commit is checked for safety: no two nodes may commit different blocks at the same
+//! height.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::error;
+use std::fmt;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use sha2::{Digest, Sha512};
+
+use crate::consensus::engine::*;
+use crate::consensus::service::Service;
+
+/// The parameters of the simulated network
+#[derive(Clone, Debug)]
+pub struct SimulationConfig {
+    /// Seed of the RNG making every random decision of the simulation
+    pub seed: u64,
+    /// Minimum delay of a message or block between two nodes
+    pub min_delay: Duration,
+    /// Maximum delay of a message or block between two nodes
+    pub max_delay: Duration,
+    /// Probability, between 0 and 1, that a peer message is dropped
+    pub drop_rate: f64,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            seed: 0,
+            min_delay: Duration::from_millis(0),
+            max_delay: Duration::from_millis(10),
+            drop_rate: 0.0,
+        }
+    }
+}
+
+/// A fault injected in the simulated network
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fault {
+    Crash(usize),
+    Restart(usize),
+    Partition(Vec<Vec<usize>>),
+    Heal,
+}
+
+/// Two nodes committed different blocks at the same height
+#[derive(Clone, Debug, PartialEq)]
+pub struct SafetyViolation {
+    pub block_num: u64,
+    /// The node which committed first and its block
+    pub first: (usize, BlockId),
+    /// The node which committed a different block and its block
+    pub second: (usize, BlockId),
+}
+
+impl error::Error for SafetyViolation {}
+
+impl fmt::Display for SafetyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Node {} committed block {} at height {}, but node {} committed block {}",
+            self.second.0,
+            hex::encode(&self.second.1),
+            self.block_num,
+            self.first.0,
+            hex::encode(&self.first.1)
+        )
+    }
+}
+
+/// Returns the peer id of a node
+pub fn peer_id(node: usize) -> PeerId {
+    format!("node-{}", node).into_bytes()
+}
+
+/// An update waiting to be delivered to a node
+struct Delivery {
+    at: Instant,
+    seq: u64,
+    to: usize,
+    update: Update,
+}
+
+// Deliveries are ordered so that the earliest is at the top of the heap
+impl Ord for Delivery {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+impl PartialOrd for Delivery {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Delivery {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for Delivery {}
+
+struct Node {
+    updates: Option<Sender<Update>>,
+    seen: HashSet<BlockId>,
+    chain_head: BlockId,
+    // The parent of the block in progress, if any
+    block_in_progress: Option<BlockId>,
+    committed: Vec<BlockId>,
+}
+
+impl Node {
+    fn is_alive(&self) -> bool {
+        self.updates.is_some()
+    }
+
+    fn send(&self, update: Update) {
+        if let Some(ref updates) = self.updates {
+            // The engine may have exited; the simulation goes on without it
+            updates.send(update).ok();
+        }
+    }
+}
+
+struct NetworkState {
+    config: SimulationConfig,
+    rng: StdRng,
+    nodes: Vec<Node>,
+    blocks: HashMap<BlockId, Block>,
+    // The partition group of each node, if the network is partitioned
+    groups: Option<Vec<usize>>,
+    queue: BinaryHeap<Delivery>,
+    seq: u64,
+    commits: HashMap<u64, (usize, BlockId)>,
+    violations: Vec<SafetyViolation>,
+    settings: HashMap<String, String>,
+    stopped: bool,
+}
+
+impl NetworkState {
+    fn connected(&self, from: usize, to: usize) -> bool {
+        self.nodes[from].is_alive()
+            && self.nodes[to].is_alive()
+            && self
+                .groups
+                .as_ref()
+                .map(|groups| groups[from] == groups[to])
+                .unwrap_or(true)
+    }
+
+    /// Schedule the delivery of an update to a node after a random delay
+    fn schedule(&mut self, to: usize, update: Update) {
+        let delay = if self.config.max_delay > self.config.min_delay {
+            self.rng
+                .gen_range(self.config.min_delay..=self.config.max_delay)
+        } else {
+            self.config.min_delay
+        };
+        self.seq += 1;
+        self.queue.push(Delivery {
+            at: Instant::now() + delay,
+            seq: self.seq,
+            to,
+            update,
+        });
+    }
+
+    /// Send a peer message from one node to another, unless it is dropped
+    fn send_message(&mut self, from: usize, to: usize, message_type: &str, payload: Vec<u8>) {
+        if !self.connected(from, to) || self.rng.gen_bool(self.config.drop_rate) {
+            return;
+        }
+        let message = PeerMessage {
+            header: PeerMessageHeader {
+                signer_id: peer_id(from),
+                content_sha512: Sha512::digest(&payload).to_vec(),
+                message_type: message_type.into(),
+                name: String::new(),
+                version: String::new(),
+            },
+            header_bytes: vec![],
+            header_signature: vec![],
+            content: payload,
+        };
+        self.schedule(to, Update::PeerMessage(message, peer_id(from)));
+    }
+
+    /// Deliver to a node the blocks it has not seen yet which a node it can reach has seen
+    fn sync_blocks(&mut self, to: usize) {
+        let mut missing = self
+            .blocks
+            .values()
+            .filter(|block| !self.nodes[to].seen.contains(&block.block_id))
+            .filter(|block| {
+                (0..self.nodes.len()).any(|from| {
+                    from != to
+                        && self.connected(from, to)
+                        && self.nodes[from].seen.contains(&block.block_id)
+                })
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        missing.sort_by_key(|block| block.block_num);
+        for block in missing {
+            self.schedule(to, Update::BlockNew(block));
+        }
+    }
+
+    /// Record a block committed by a node. Committing a block commits its ancestors too, so they
+    /// are checked against the blocks committed at their heights, down to the first ancestor
+    /// already recorded.
+    fn commit(&mut self, node: usize, block_id: BlockId) {
+        let mut ancestor_id = block_id.clone();
+        while let Some(block) = self.blocks.get(&ancestor_id) {
+            let block_num = block.block_num;
+            let previous_id = block.previous_id.clone();
+            match self.commits.get(&block_num) {
+                Some((_, first_id)) if *first_id == ancestor_id => break,
+                Some((first, first_id)) => {
+                    let violation = SafetyViolation {
+                        block_num,
+                        first: (*first, first_id.clone()),
+                        second: (node, ancestor_id),
+                    };
+                    // Later commits on the same fork walk past this block again
+                    if !self.violations.contains(&violation) {
+                        error!("Safety violation: {}", violation);
+                        self.violations.push(violation);
+                    }
+                }
+                None => {
+                    self.commits.insert(block_num, (node, ancestor_id));
+                }
+            }
+            ancestor_id = previous_id;
+        }
+
+        let node = &mut self.nodes[node];
+        node.chain_head = block_id.clone();
+        node.committed.push(block_id.clone());
+        node.send(Update::BlockCommit(block_id));
+    }
+}
+
+fn signer_node(signer_id: &[u8], nodes: usize) -> Option<usize> {
+    (0..nodes).find(|node| peer_id(*node) == signer_id)
+}
+
+struct Network {
+    state: Mutex<NetworkState>,
+    // Signaled when a delivery is scheduled or the simulation stops
+    wakeup: Condvar,
+}
+
+impl Network {
+    fn lock(&self) -> MutexGuard<'_, NetworkState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Deliver the scheduled updates as they become due, until the simulation stops
+    fn run_deliveries(&self) {
+        let mut state = self.lock();
+        while !state.stopped {
+            let now = Instant::now();
+            while state
+                .queue
+                .peek()
+                .map(|delivery| delivery.at <= now)
+                .unwrap_or(false)
+            {
+                let delivery = state.queue.pop().unwrap();
+                let node = &mut state.nodes[delivery.to];
+                if let Update::BlockNew(ref block) = delivery.update {
+                    if !node.seen.insert(block.block_id.clone()) {
+                        continue;
+                    }
+                }
+                node.send(delivery.update);
+            }
+
+            let timeout = state
+                .queue
+                .peek()
+                .map(|delivery| delivery.at.saturating_duration_since(now))
+                .unwrap_or_else(|| Duration::from_millis(100));
+            state = self.wakeup.wait_timeout(state, timeout).unwrap().0;
+        }
+    }
+}
+
+/// The service of a node, which acts on the simulated network
+struct NodeService {
+    network: Arc<Network>,
+    node: usize,
+}
+
+impl NodeService {
+    fn lock(&self) -> Result<MutexGuard<'_, NetworkState>, Error> {
+        let state = self.network.lock();
+        if state.nodes[self.node].is_alive() {
+            Ok(state)
+        } else {
+            Err(Error::SendError("Node has crashed".into()))
+        }
+    }
+
+    fn seen_block(&self, state: &NetworkState, block_id: &[u8]) -> Result<Block, Error> {
+        if state.nodes[self.node].seen.contains(block_id) {
+            Ok(state.blocks[block_id].clone())
+        } else {
+            Err(Error::UnknownBlock(hex::encode(block_id)))
+        }
+    }
+}
+
+impl Service for NodeService {
+    fn send_to(
+        &mut self,
+        peer: &PeerId,
+        message_type: &str,
+        payload: Vec<u8>,
+    ) -> Result<(), Error> {
+        let mut state = self.lock()?;
+        let to = signer_node(peer, state.nodes.len())
+            .ok_or_else(|| Error::UnknownPeer(hex::encode(peer)))?;
+        state.send_message(self.node, to, message_type, payload);
+        self.network.wakeup.notify_all();
+        Ok(())
+    }
+
+    fn broadcast(&mut self, message_type: &str, payload: Vec<u8>) -> Result<(), Error> {
+        let mut state = self.lock()?;
+        for to in (0..state.nodes.len()).filter(|to| *to != self.node) {
+            state.send_message(self.node, to, message_type, payload.clone());
+        }
+        self.network.wakeup.notify_all();
+        Ok(())
+    }
+
+    fn initialize_block(&mut self, previous_id: Option<BlockId>) -> Result<(), Error> {
+        let mut state = self.lock()?;
+        if state.nodes[self.node].block_in_progress.is_some() {
+            return Err(Error::InvalidState(
+                "Cannot initialize block in current state".into(),
+            ));
+        }
+        let previous_id = previous_id.unwrap_or_else(|| state.nodes[self.node].chain_head.clone());
+        self.seen_block(&state, &previous_id)?;
+        state.nodes[self.node].block_in_progress = Some(previous_id);
+        Ok(())
+    }
+
+    fn summarize_block(&mut self) -> Result<Vec<u8>, Error> {
+        let state = self.lock()?;
+        match state.nodes[self.node].block_in_progress {
+            Some(ref previous_id) => Ok(Sha512::digest(previous_id).to_vec()),
+            None => Err(Error::InvalidState(
+                "Cannot summarize block in current state".into(),
+            )),
+        }
+    }
+
+    fn finalize_block(&mut self, data: Vec<u8>) -> Result<BlockId, Error> {
+        let mut state = self.lock()?;
+        let previous_id = state.nodes[self.node]
+            .block_in_progress
+            .take()
+            .ok_or_else(|| Error::InvalidState("Cannot finalize block in current state".into()))?;
+        let previous = self.seen_block(&state, &previous_id)?;
+
+        let signer_id = peer_id(self.node);
+        let block_num = previous.block_num + 1;
+        let mut hasher = Sha512::new();
+        hasher.update(&previous_id);
+        hasher.update(block_num.to_be_bytes());
+        hasher.update(&signer_id);
+        hasher.update(&data);
+        let block = Block {
+            block_id: hasher.finalize().to_vec(),
+            summary: Sha512::digest(&previous_id).to_vec(),
+            previous_id,
+            signer_id,
+            block_num,
+            payload: data,
+        };
+        let block_id = block.block_id.clone();
+        state.blocks.insert(block_id.clone(), block.clone());
+
+        // The publishing node receives its block at once, the others through the network
+        let node = &mut state.nodes[self.node];
+        node.seen.insert(block_id.clone());
+        node.send(Update::BlockNew(block.clone()));
+        for to in (0..state.nodes.len()).filter(|to| *to != self.node) {
+            if state.connected(self.node, to) {
+                state.schedule(to, Update::BlockNew(block.clone()));
+            }
+        }
+        self.network.wakeup.notify_all();
+
+        Ok(block_id)
+    }
+
+    fn cancel_block(&mut self) -> Result<(), Error> {
+        let mut state = self.lock()?;
+        match state.nodes[self.node].block_in_progress.take() {
+            Some(_) => Ok(()),
+            None => Err(Error::InvalidState(
+                "Cannot cancel block in current state".into(),
+            )),
+        }
+    }
+
+    fn check_blocks(&mut self, priority: Vec<BlockId>) -> Result<(), Error> {
+        let state = self.lock()?;
+        for block_id in &priority {
+            self.seen_block(&state, block_id)?;
+        }
+        for block_id in priority {
+            state.nodes[self.node].send(Update::BlockValid(block_id));
+        }
+        Ok(())
+    }
+
+    fn commit_block(&mut self, block_id: BlockId) -> Result<(), Error> {
+        let mut state = self.lock()?;
+        self.seen_block(&state, &block_id)?;
+        state.commit(self.node, block_id);
+        Ok(())
+    }
+
+    fn ignore_block(&mut self, block_id: BlockId) -> Result<(), Error> {
+        let state = self.lock()?;
+        self.seen_block(&state, &block_id).map(|_| ())
+    }
+
+    fn fail_block(&mut self, block_id: BlockId) -> Result<(), Error> {
+        let state = self.lock()?;
+        self.seen_block(&state, &block_id).map(|_| ())
+    }
+
+    fn get_blocks(&mut self, block_ids: Vec<BlockId>) -> Result<HashMap<BlockId, Block>, Error> {
+        let state = self.lock()?;
+        block_ids
+            .into_iter()
+            .map(|block_id| {
+                let block = self.seen_block(&state, &block_id)?;
+                Ok((block_id, block))
+            })
+            .collect()
+    }
+
+    fn get_chain_head(&mut self) -> Result<Block, Error> {
+        let state = self.lock()?;
+        Ok(state.blocks[&state.nodes[self.node].chain_head].clone())
+    }
+
+    fn get_settings(
+        &mut self,
+        block_id: BlockId,
+        keys: Vec<String>,
+    ) -> Result<HashMap<String, String>, Error> {
+        let state = self.lock()?;
+        self.seen_block(&state, &block_id)?;
+        Ok(keys
+            .into_iter()
+            .filter_map(|key| state.settings.get(&key).map(|value| (key, value.clone())))
+            .collect())
+    }
+
+    fn get_state(
+        &mut self,
+        block_id: BlockId,
+        _addresses: Vec<String>,
+    ) -> Result<HashMap<String, Vec<u8>>, Error> {
+        let state = self.lock()?;
+        self.seen_block(&state, &block_id)?;
+        Ok(HashMap::new())
+    }
+}
+
+type EngineFactory<E> = Box<dyn Fn(usize) -> E + Send>;
+
+/// Runs several instances of an engine on a simulated network
+pub struct Simulator<E: Engine + Send + 'static> {
+    network: Arc<Network>,
+    factory: EngineFactory<E>,
+    engines: Vec<Option<JoinHandle<Result<(), Error>>>>,
+    delivery: Option<JoinHandle<()>>,
+}
+
+impl<E: Engine + Send + 'static> Simulator<E> {
+    /// Start `nodes` engines, created by `factory` from their node index, on a network with a
+    /// common genesis block
+    pub fn new<F>(nodes: usize, config: SimulationConfig, factory: F) -> Self
+    where
+        F: Fn(usize) -> E + Send + 'static,
+    {
+        let genesis = Block {
+            block_id: Sha512::digest(b"genesis").to_vec(),
+            previous_id: vec![0; 8],
+            signer_id: vec![],
+            block_num: 0,
+            payload: vec![],
+            summary: vec![],
+        };
+
+        let state = NetworkState {
+            rng: StdRng::seed_from_u64(config.seed),
+            config,
+            nodes: (0..nodes)
+                .map(|_| Node {
+                    updates: None,
+                    seen: vec![genesis.block_id.clone()].into_iter().collect(),
+                    chain_head: genesis.block_id.clone(),
+                    block_in_progress: None,
+                    committed: vec![],
+                })
+                .collect(),
+            blocks: vec![(genesis.block_id.clone(), genesis)]
+                .into_iter()
+                .collect(),
+            groups: None,
+            queue: BinaryHeap::new(),
+            seq: 0,
+            commits: HashMap::new(),
+            violations: vec![],
+            settings: HashMap::new(),
+            stopped: false,
+        };
+        let network = Arc::new(Network {
+            state: Mutex::new(state),
+            wakeup: Condvar::new(),
+        });
+
+        let delivery_network = network.clone();
+        let delivery = thread::Builder::new()
+            .name("SimulatedNetwork".into())
+            .spawn(move || delivery_network.run_deliveries())
+            .expect("Failed to spawn the network thread");
+
+        let mut simulator = Simulator {
+            network,
+            factory: Box::new(factory),
+            engines: (0..nodes).map(|_| None).collect(),
+            delivery: Some(delivery),
+        };
+        for node in 0..nodes {
+            simulator.start_engine(node);
+        }
+        simulator
+    }
+
+    fn start_engine(&mut self, node: usize) {
+        let (updates, receiver) = channel();
+        let startup_state = {
+            let mut state = self.network.lock();
+            let nodes = state.nodes.len();
+            state.nodes[node].updates = Some(updates);
+            StartupState {
+                chain_head: state.blocks[&state.nodes[node].chain_head].clone(),
+                peers: (0..nodes)
+                    .filter(|peer| *peer != node)
+                    .map(|peer| PeerInfo {
+                        peer_id: peer_id(peer),
+                    })
+                    .collect(),
+                local_peer_info: PeerInfo {
+                    peer_id: peer_id(node),
+                },
+            }
+        };
+
+        let mut engine = (self.factory)(node);
+        let service = NodeService {
+            network: self.network.clone(),
+            node,
+        };
+        self.engines[node] = Some(
+            thread::Builder::new()
+                .name(format!("SimulatedEngine-{}", node))
+                .spawn(move || engine.start(receiver, Box::new(service), startup_state))
+                .expect("Failed to spawn an engine thread"),
+        );
+    }
+
+    /// Returns the number of nodes
+    pub fn nodes(&self) -> usize {
+        self.engines.len()
+    }
+
+    /// Set the value returned by `get_settings` for `key` on every node
+    pub fn set_setting(&self, key: &str, value: &str) {
+        self.network
+            .lock()
+            .settings
+            .insert(key.into(), value.into());
+    }
+
+    /// Crash a node: its engine's update channel is closed, its service fails and messages to
+    /// it are lost. The other nodes are sent `Update::PeerDisconnected`.
+    pub fn crash(&mut self, node: usize) {
+        let mut state = self.network.lock();
+        if state.nodes[node].updates.take().is_none() {
+            return;
+        }
+        info!("Crashing node {}", node);
+        state.nodes[node].block_in_progress = None;
+        for peer in 0..state.nodes.len() {
+            state.nodes[peer].send(Update::PeerDisconnected(peer_id(node)));
+        }
+    }
+
+    /// Restart a crashed node with a new engine. The node keeps its blocks and commits, and is
+    /// sent the blocks published while it was down; the other nodes are sent
+    /// `Update::PeerConnected`.
+    pub fn restart(&mut self, node: usize) {
+        if self.network.lock().nodes[node].is_alive() {
+            return;
+        }
+        info!("Restarting node {}", node);
+        if let Some(engine) = self.engines[node].take() {
+            if let Ok(Err(err)) = engine.join() {
+                warn!("Engine of node {} failed: {}", node, err);
+            }
+        }
+        self.start_engine(node);
+
+        let mut state = self.network.lock();
+        for peer in (0..state.nodes.len()).filter(|peer| *peer != node) {
+            state.nodes[peer].send(Update::PeerConnected(PeerInfo {
+                peer_id: peer_id(node),
+            }));
+        }
+        state.sync_blocks(node);
+        self.network.wakeup.notify_all();
+    }
+
+    /// Partition the network into groups of nodes; nodes in different groups cannot reach each
+    /// other. Nodes not listed in any group are isolated.
+    pub fn partition(&self, groups: &[&[usize]]) {
+        let mut state = self.network.lock();
+        let nodes = state.nodes.len();
+        let mut node_groups = (0..nodes)
+            .map(|node| groups.len() + node)
+            .collect::<Vec<_>>();
+        for (group, members) in groups.iter().enumerate() {
+            for node in members.iter() {
+                node_groups[*node] = group;
+            }
+        }
+        info!("Partitioning the network into {:?}", groups);
+        state.groups = Some(node_groups);
+    }
+
+    /// Reconnect all nodes, sending them the blocks they missed while partitioned
+    pub fn heal(&self) {
+        let mut state = self.network.lock();
+        info!("Healing the network");
+        state.groups = None;
+        for node in 0..state.nodes.len() {
+            state.sync_blocks(node);
+        }
+        self.network.wakeup.notify_all();
+    }
+
+    /// Inject a fault chosen by the simulation's RNG: crash a node (keeping at least one alive),
+    /// restart a crashed node, split the network in two, or heal it
+    pub fn inject_random_fault(&mut self) -> Fault {
+        let fault = {
+            let mut guard = self.network.lock();
+            let state = &mut *guard;
+            let (alive, crashed): (Vec<usize>, Vec<usize>) =
+                (0..state.nodes.len()).partition(|node| state.nodes[*node].is_alive());
+
+            let mut choices = vec![];
+            if alive.len() > 1 {
+                choices.push(0);
+            }
+            if !crashed.is_empty() {
+                choices.push(1);
+            }
+            if state.nodes.len() > 1 {
+                choices.push(2);
+            }
+            if state.groups.is_some() {
+                choices.push(3);
+            }
+
+            match choices.choose(&mut state.rng) {
+                Some(0) => Fault::Crash(*alive.choose(&mut state.rng).unwrap()),
+                Some(1) => Fault::Restart(*crashed.choose(&mut state.rng).unwrap()),
+                Some(2) => {
+                    let mut nodes = (0..state.nodes.len()).collect::<Vec<_>>();
+                    nodes.shuffle(&mut state.rng);
+                    let split = state.rng.gen_range(1..nodes.len());
+                    let mut second = nodes.split_off(split);
+                    nodes.sort_unstable();
+                    second.sort_unstable();
+                    Fault::Partition(vec![nodes, second])
+                }
+                _ => Fault::Heal,
+            }
+        };
+        self.inject(&fault);
+        fault
+    }
+
+    /// Inject the given fault
+    pub fn inject(&mut self, fault: &Fault) {
+        match fault {
+            Fault::Crash(node) => self.crash(*node),
+            Fault::Restart(node) => self.restart(*node),
+            Fault::Partition(groups) => self.partition(
+                &groups
+                    .iter()
+                    .map(|group| group.as_slice())
+                    .collect::<Vec<_>>(),
+            ),
+            Fault::Heal => self.heal(),
+        }
+    }
+
+    /// Returns the ids of the blocks committed by a node, in order
+    pub fn committed(&self, node: usize) -> Vec<BlockId> {
+        self.network.lock().nodes[node].committed.clone()
+    }
+
+    /// Returns the block with the given id, if any node published it
+    pub fn block(&self, block_id: &[u8]) -> Option<Block> {
+        self.network.lock().blocks.get(block_id).cloned()
+    }
+
+    /// Wait until every running node has committed a block at `block_num` or above. Returns
+    /// false if they have not within `timeout`.
+    pub fn wait_for_height(&self, block_num: u64, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let reached = {
+                let state = self.network.lock();
+                state
+                    .nodes
+                    .iter()
+                    .filter(|node| node.is_alive())
+                    .all(|node| {
+                        node.committed
+                            .last()
+                            .map(|block_id| state.blocks[block_id].block_num >= block_num)
+                            .unwrap_or(false)
+                    })
+            };
+            if reached {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// Check that no two nodes have committed different blocks at the same height
+    pub fn check_safety(&self) -> Result<(), SafetyViolation> {
+        match self.network.lock().violations.first() {
+            Some(violation) => Err(violation.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// Send `Update::Shutdown` to every running engine and wait for them to exit, returning the
+    /// result of each node's engine
+    pub fn shutdown(mut self) -> Vec<Result<(), Error>> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> Vec<Result<(), Error>> {
+        {
+            let mut state = self.network.lock();
+            for node in state.nodes.iter_mut() {
+                node.send(Update::Shutdown);
+                node.updates = None;
+            }
+            state.stopped = true;
+            self.network.wakeup.notify_all();
+        }
+
+        let results = self
+            .engines
+            .iter_mut()
+            .map(|engine| match engine.take() {
+                Some(engine) => engine
+                    .join()
+                    .unwrap_or_else(|_| Err(Error::InvalidState("Engine panicked".into()))),
+                None => Ok(()),
+            })
+            .collect();
+        if let Some(delivery) = self.delivery.take() {
+            delivery.join().ok();
+        }
+        results
+    }
+}
+
+impl<E: Engine + Send + 'static> Drop for Simulator<E> {
+    fn drop(&mut self) {
+        if self.delivery.is_some() {
+            self.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::{Receiver, RecvTimeoutError};
+
+    /// Returns the node that publishes the block at `block_num`
+    fn leader(block_num: u64, nodes: usize) -> usize {
+        (block_num % nodes as u64) as usize
+    }
+
+    /// An engine in which nodes take turns publishing blocks, and commit the block of the leader
+    /// of each height once it extends their chain head
+    struct RoundRobinEngine {
+        nodes: usize,
+    }
+
+    impl Engine for RoundRobinEngine {
+        fn start(
+            &mut self,
+            updates: Receiver<Update>,
+            mut service: Box<dyn Service>,
+            startup_state: StartupState,
+        ) -> Result<(), Error> {
+            let local_id = startup_state.local_peer_info.peer_id;
+            let mut head = startup_state.chain_head;
+            let mut pending: HashMap<BlockId, Block> = HashMap::new();
+
+            loop {
+                if peer_id(leader(head.block_num + 1, self.nodes)) == local_id
+                    && pending
+                        .values()
+                        .all(|block| block.previous_id != head.block_id)
+                {
+                    service.initialize_block(None)?;
+                    service.finalize_block(vec![])?;
+                }
+
+                match updates.recv_timeout(Duration::from_millis(10)) {
+                    Ok(Update::BlockNew(block)) => {
+                        if block.signer_id == peer_id(leader(block.block_num, self.nodes)) {
+                            service.check_blocks(vec![block.block_id.clone()])?;
+                            pending.insert(block.block_id.clone(), block);
+                        }
+                    }
+                    Ok(Update::BlockValid(block_id)) => {
+                        if let Some(block) = pending.get(&block_id) {
+                            if block.previous_id == head.block_id {
+                                service.commit_block(block_id)?;
+                            }
+                        }
+                    }
+                    Ok(Update::BlockCommit(block_id)) => {
+                        head = pending.remove(&block_id).expect("Unknown block committed");
+                        // A block received before its parent was committed can be committed now
+                        let next = pending
+                            .values()
+                            .find(|block| block.previous_id == head.block_id)
+                            .map(|block| block.block_id.clone());
+                        if let Some(next) = next {
+                            service.commit_block(next)?;
+                        }
+                    }
+                    Ok(Update::Shutdown) | Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                    Ok(_) | Err(RecvTimeoutError::Timeout) => (),
+                }
+            }
+        }
+
+        fn version(&self) -> String {
+            "0.1".into()
+        }
+
+        fn name(&self) -> String {
+            "round-robin".into()
+        }
+
+        fn additional_protocols(&self) -> Vec<(String, String)> {
+            vec![]
+        }
+    }
+
+    /// An engine which publishes a chain of its own blocks and commits the last one, without
+    /// agreeing with its peers
+    struct SelfishEngine {
+        blocks: usize,
+    }
+
+    impl Engine for SelfishEngine {
+        fn start(
+            &mut self,
+            updates: Receiver<Update>,
+            mut service: Box<dyn Service>,
+            _startup_state: StartupState,
+        ) -> Result<(), Error> {
+            let mut block_id = None;
+            for _ in 0..self.blocks {
+                service.initialize_block(block_id.take())?;
+                block_id = Some(service.finalize_block(vec![])?);
+            }
+            service.commit_block(block_id.expect("No block published"))?;
+            loop {
+                match updates.recv() {
+                    Ok(Update::Shutdown) | Err(_) => return Ok(()),
+                    Ok(_) => (),
+                }
+            }
+        }
+
+        fn version(&self) -> String {
+            "0.1".into()
+        }
+
+        fn name(&self) -> String {
+            "selfish".into()
+        }
+
+        fn additional_protocols(&self) -> Vec<(String, String)> {
+            vec![]
+        }
+    }
+
+    /// An engine which broadcasts a message when it starts or a peer connects, and records the
+    /// messages it receives
+    struct GossipEngine {
+        received: Arc<Mutex<Vec<(usize, PeerId)>>>,
+        node: usize,
+    }
+
+    impl Engine for GossipEngine {
+        fn start(
+            &mut self,
+            updates: Receiver<Update>,
+            mut service: Box<dyn Service>,
+            _startup_state: StartupState,
+        ) -> Result<(), Error> {
+            service.broadcast("hello", vec![])?;
+            loop {
+                match updates.recv() {
+                    Ok(Update::PeerMessage(message, sender)) => {
+                        assert_eq!(message.header.message_type, "hello");
+                        self.received.lock().unwrap().push((self.node, sender));
+                    }
+                    Ok(Update::PeerConnected(_)) => service.broadcast("hello", vec![])?,
+                    Ok(Update::Shutdown) | Err(_) => return Ok(()),
+                    Ok(_) => (),
+                }
+            }
+        }
+
+        fn version(&self) -> String {
+            "0.1".into()
+        }
+
+        fn name(&self) -> String {
+            "gossip".into()
+        }
+
+        fn additional_protocols(&self) -> Vec<(String, String)> {
+            vec![]
+        }
+    }
+
+    #[test]
+    fn round_robin_progress() {
+        let simulator = Simulator::new(4, SimulationConfig::default(), |_| RoundRobinEngine {
+            nodes: 4,
+        });
+
+        assert!(simulator.wait_for_height(8, Duration::from_secs(10)));
+        simulator.check_safety().unwrap();
+
+        let committed = simulator.committed(0);
+        for node in 1..4 {
+            let other = simulator.committed(node);
+            let common = committed.len().min(other.len());
+            assert_eq!(committed[..common], other[..common]);
+        }
+
+        for result in simulator.shutdown() {
+            result.unwrap();
+        }
+    }
+
+    #[test]
+    fn crash_and_restart() {
+        let mut simulator = Simulator::new(3, SimulationConfig::default(), |_| RoundRobinEngine {
+            nodes: 3,
+        });
+        assert!(simulator.wait_for_height(2, Duration::from_secs(10)));
+
+        // Node 2 publishes block 5, so the others stall at height 4 while it is down
+        simulator.crash(2);
+        assert!(!simulator.wait_for_height(5, Duration::from_millis(200)));
+
+        simulator.restart(2);
+        assert!(simulator.wait_for_height(6, Duration::from_secs(10)));
+        simulator.check_safety().unwrap();
+    }
+
+    #[test]
+    fn detect_safety_violation() {
+        let simulator = Simulator::new(2, SimulationConfig::default(), |_| SelfishEngine {
+            blocks: 1,
+        });
+        wait_for_commits(&simulator, 2);
+
+        let violation = simulator.check_safety().unwrap_err();
+        assert_eq!(violation.block_num, 1);
+        assert_ne!(violation.first.0, violation.second.0);
+    }
+
+    #[test]
+    fn detect_safety_violation_below_commit() {
+        // Node 1 commits only block 2 of its fork, which conflicts with node 0's block 1
+        let simulator = Simulator::new(2, SimulationConfig::default(), |node| SelfishEngine {
+            blocks: node + 1,
+        });
+        wait_for_commits(&simulator, 2);
+
+        let violation = simulator.check_safety().unwrap_err();
+        assert_eq!(violation.block_num, 1);
+        assert_ne!(violation.first.0, violation.second.0);
+    }
+
+    /// Wait until each of the `nodes` nodes has committed a block
+    fn wait_for_commits(simulator: &Simulator<SelfishEngine>, nodes: usize) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while (0..nodes).any(|node| simulator.committed(node).is_empty()) {
+            assert!(Instant::now() < deadline, "Nodes did not commit");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn partitioned_gossip() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let factory_received = received.clone();
+        let mut simulator =
+            Simulator::new(3, SimulationConfig::default(), move |node| GossipEngine {
+                received: factory_received.clone(),
+                node,
+            });
+        thread::sleep(Duration::from_millis(100));
+        received.lock().unwrap().clear();
+
+        // Restarted nodes greet their peers again, but only within their group
+        simulator.partition(&[&[0, 1], &[2]]);
+        for node in 0..3 {
+            simulator.crash(node);
+        }
+        for node in 0..3 {
+            simulator.restart(node);
+        }
+        thread::sleep(Duration::from_millis(100));
+        simulator.shutdown();
+
+        let mut received = received.lock().unwrap().clone();
+        received.sort();
+        received.dedup();
+        assert_eq!(received, vec![(0, peer_id(1)), (1, peer_id(0))]);
+    }
+
+    #[test]
+    fn dropped_messages() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let factory_received = received.clone();
+        let config = SimulationConfig {
+            drop_rate: 1.0,
+            ..Default::default()
+        };
+        let simulator = Simulator::new(3, config, move |node| GossipEngine {
+            received: factory_received.clone(),
+            node,
+        });
+        thread::sleep(Duration::from_millis(100));
+        simulator.shutdown();
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn random_faults_are_seeded() {
+        let faults = |seed| {
+            let config = SimulationConfig {
+                seed,
+                ..Default::default()
+            };
+            let mut simulator = Simulator::new(4, config, |_| SelfishEngine);
+            (0..10)
+                .map(|_| simulator.inject_random_fault())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(faults(7), faults(7));
+    }
+}
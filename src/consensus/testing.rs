@@ -0,0 +1,679 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Tools for testing consensus engines without a validator.
+//!
+//! `TestService` is a `Service` which records every call made by the engine and simulates the
+//! validator's block tree: blocks built with `initialize_block`/`finalize_block` are announced
+//! with `Update::BlockNew`, checked blocks are reported with `Update::BlockValid` and committed
+//! blocks with `Update::BlockCommit`, on the update channel given to the engine.
+//!
+//! ```no_run
+//! # use sawtooth_sdk::consensus::engine::Engine;
+//! # use sawtooth_sdk::consensus::testing::{ServiceCall, TestService};
+//! # use std::time::Duration;
+//! # fn test<E: Engine + Send + 'static>(mut engine: E) {
+//! let (service, updates) = TestService::new(b"local".to_vec());
+//! let startup_state = service.startup_state();
+//!
+//! let engine_service = service.clone();
+//! std::thread::spawn(move || engine.start(updates, Box::new(engine_service), startup_state));
+//!
+//! let block = service.wait_for_block(1, Duration::from_secs(1));
+//! service.wait_for_call(
+//!     |call| *call == ServiceCall::CommitBlock(block.block_id.clone()),
+//!     Duration::from_secs(1),
+//! );
+//! service.assert_committed(&block.block_id);
+//! # }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use sha2::{Digest, Sha512};
+
+use crate::consensus::engine::{Block, BlockId, Error, PeerId, PeerInfo, StartupState, Update};
+use crate::consensus::service::Service;
+
+/// A call made to a `TestService`
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServiceCall {
+    SendTo {
+        peer: PeerId,
+        message_type: String,
+        payload: Vec<u8>,
+    },
+    Broadcast {
+        message_type: String,
+        payload: Vec<u8>,
+    },
+    InitializeBlock(Option<BlockId>),
+    SummarizeBlock,
+    FinalizeBlock(Vec<u8>),
+    CancelBlock,
+    CheckBlocks(Vec<BlockId>),
+    CommitBlock(BlockId),
+    IgnoreBlock(BlockId),
+    FailBlock(BlockId),
+    GetBlocks(Vec<BlockId>),
+    GetChainHead,
+    GetSettings(BlockId, Vec<String>),
+    GetState(BlockId, Vec<String>),
+}
+
+/// Create the id of a block from its contents
+fn make_block_id(previous_id: &[u8], block_num: u64, signer_id: &[u8], payload: &[u8]) -> BlockId {
+    let mut hasher = Sha512::new();
+    hasher.update(previous_id);
+    hasher.update(block_num.to_be_bytes());
+    hasher.update(signer_id);
+    hasher.update(payload);
+    hasher.finalize().to_vec()
+}
+
+struct State {
+    local_peer_id: PeerId,
+    updates: Sender<Update>,
+    calls: Vec<ServiceCall>,
+    blocks: HashMap<BlockId, Block>,
+    chain_head: BlockId,
+    committed: Vec<BlockId>,
+    // The parent of the block in progress, if any
+    block_in_progress: Option<BlockId>,
+    summaries_not_ready: usize,
+    invalid_blocks: HashSet<BlockId>,
+    settings: HashMap<String, String>,
+    state: HashMap<String, Vec<u8>>,
+}
+
+impl State {
+    fn send_update(&self, update: Update) {
+        // The engine may have stopped listening; the call is still recorded
+        self.updates.send(update).ok();
+    }
+
+    fn block(&self, block_id: &[u8]) -> Result<&Block, Error> {
+        self.blocks
+            .get(block_id)
+            .ok_or_else(|| Error::UnknownBlock(hex::encode(block_id)))
+    }
+
+    fn summary(&self, previous_id: &[u8]) -> Vec<u8> {
+        Sha512::digest(previous_id).to_vec()
+    }
+}
+
+/// A `Service` which records calls and simulates the validator's block tree.
+///
+/// Clones share the same state, so a test can keep a clone to inspect the calls of the engine it
+/// gave the service to.
+#[derive(Clone)]
+pub struct TestService {
+    state: Arc<(Mutex<State>, Condvar)>,
+}
+
+impl TestService {
+    /// Create a service for the engine of the peer `local_peer_id`, with a chain made of a
+    /// genesis block, and the receiver for the updates to give to the engine
+    pub fn new(local_peer_id: PeerId) -> (Self, Receiver<Update>) {
+        let (updates, receiver) = channel();
+        let genesis = Block {
+            block_id: make_block_id(&[], 0, &[], &[]),
+            previous_id: vec![0; 8],
+            signer_id: vec![],
+            block_num: 0,
+            payload: vec![],
+            summary: vec![],
+        };
+
+        let state = State {
+            local_peer_id,
+            updates,
+            calls: vec![],
+            chain_head: genesis.block_id.clone(),
+            committed: vec![genesis.block_id.clone()],
+            blocks: vec![(genesis.block_id.clone(), genesis)]
+                .into_iter()
+                .collect(),
+            block_in_progress: None,
+            summaries_not_ready: 0,
+            invalid_blocks: HashSet::new(),
+            settings: HashMap::new(),
+            state: HashMap::new(),
+        };
+
+        (
+            TestService {
+                state: Arc::new((Mutex::new(state), Condvar::new())),
+            },
+            receiver,
+        )
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.0.lock().unwrap()
+    }
+
+    /// Record a call, waking up the threads waiting for calls
+    fn record(&self, call: ServiceCall) -> MutexGuard<'_, State> {
+        let mut state = self.lock();
+        state.calls.push(call);
+        self.state.1.notify_all();
+        state
+    }
+
+    /// Returns the startup state to start the engine with
+    pub fn startup_state(&self) -> StartupState {
+        let state = self.lock();
+        StartupState {
+            chain_head: state.blocks[&state.chain_head].clone(),
+            peers: vec![],
+            local_peer_info: PeerInfo {
+                peer_id: state.local_peer_id.clone(),
+            },
+        }
+    }
+
+    /// Send an update to the engine, such as a `PeerMessage`
+    pub fn send_update(&self, update: Update) {
+        self.lock().send_update(update);
+    }
+
+    /// Add a block built by another peer to the block tree and announce it with
+    /// `Update::BlockNew`
+    pub fn receive_block(&self, block: Block) {
+        let mut state = self.lock();
+        state.blocks.insert(block.block_id.clone(), block.clone());
+        state.send_update(Update::BlockNew(block));
+    }
+
+    /// Build a block on `previous_id` as if it had been published by `signer_id`, without
+    /// announcing it
+    pub fn build_block(&self, previous_id: &[u8], signer_id: &[u8], payload: &[u8]) -> Block {
+        let state = self.lock();
+        let block_num = state
+            .blocks
+            .get(previous_id)
+            .map(|previous| previous.block_num + 1)
+            .expect("Unknown previous block");
+        Block {
+            block_id: make_block_id(previous_id, block_num, signer_id, payload),
+            previous_id: previous_id.to_vec(),
+            signer_id: signer_id.to_vec(),
+            block_num,
+            payload: payload.to_vec(),
+            summary: state.summary(previous_id),
+        }
+    }
+
+    /// Make the next `count` calls to `summarize_block` fail with `Error::BlockNotReady`
+    pub fn set_summaries_not_ready(&self, count: usize) {
+        self.lock().summaries_not_ready = count;
+    }
+
+    /// Make `check_blocks` report the block as invalid
+    pub fn set_invalid(&self, block_id: &[u8]) {
+        self.lock().invalid_blocks.insert(block_id.to_vec());
+    }
+
+    /// Set the value returned by `get_settings` for `key`
+    pub fn set_setting(&self, key: &str, value: &str) {
+        self.lock().settings.insert(key.into(), value.into());
+    }
+
+    /// Set the value returned by `get_state` for `address`
+    pub fn set_state(&self, address: &str, value: &[u8]) {
+        self.lock().state.insert(address.into(), value.to_vec());
+    }
+
+    /// Returns the calls made so far, in order
+    pub fn calls(&self) -> Vec<ServiceCall> {
+        self.lock().calls.clone()
+    }
+
+    /// Returns the block with the given id, if it is in the block tree
+    pub fn block(&self, block_id: &[u8]) -> Option<Block> {
+        self.lock().blocks.get(block_id).cloned()
+    }
+
+    /// Returns the current chain head
+    pub fn chain_head(&self) -> Block {
+        let state = self.lock();
+        state.blocks[&state.chain_head].clone()
+    }
+
+    /// Returns the ids of the committed blocks, starting with the genesis block
+    pub fn committed(&self) -> Vec<BlockId> {
+        self.lock().committed.clone()
+    }
+
+    /// Wait until a call matching `predicate` has been made and return it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no matching call is made within `timeout`.
+    pub fn wait_for_call<F>(&self, predicate: F, timeout: Duration) -> ServiceCall
+    where
+        F: Fn(&ServiceCall) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.lock();
+        loop {
+            if let Some(call) = state.calls.iter().find(|call| predicate(call)) {
+                return call.clone();
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                panic!(
+                    "No matching call within {:?}; calls were: {:#?}",
+                    timeout, state.calls
+                );
+            }
+            state = self.state.1.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Wait until the engine has finalized a block at `block_num` and return it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no such block is finalized within `timeout`.
+    pub fn wait_for_block(&self, block_num: u64, timeout: Duration) -> Block {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.lock();
+        loop {
+            let local_peer_id = &state.local_peer_id;
+            if let Some(block) = state
+                .blocks
+                .values()
+                .find(|block| block.block_num == block_num && &block.signer_id == local_peer_id)
+            {
+                return block.clone();
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                panic!(
+                    "No block {} finalized within {:?}; calls were: {:#?}",
+                    block_num, timeout, state.calls
+                );
+            }
+            state = self.state.1.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Assert that a call equal to `expected` has been made
+    pub fn assert_called(&self, expected: &ServiceCall) {
+        let calls = self.calls();
+        assert!(
+            calls.contains(expected),
+            "Expected call {:?}; calls were: {:#?}",
+            expected,
+            calls
+        );
+    }
+
+    /// Assert that no call matching `predicate` has been made
+    pub fn assert_not_called<F: Fn(&ServiceCall) -> bool>(&self, predicate: F) {
+        let calls = self.calls();
+        if let Some(call) = calls.iter().find(|call| predicate(call)) {
+            panic!("Unexpected call {:?}; calls were: {:#?}", call, calls);
+        }
+    }
+
+    /// Assert that the block with the given id has been committed
+    pub fn assert_committed(&self, block_id: &[u8]) {
+        let committed = self.committed();
+        assert!(
+            committed.iter().any(|id| id.as_slice() == block_id),
+            "Block {} was not committed; committed blocks are {:?}",
+            hex::encode(block_id),
+            committed.iter().map(hex::encode).collect::<Vec<_>>()
+        );
+    }
+}
+
+impl Service for TestService {
+    fn send_to(
+        &mut self,
+        peer: &PeerId,
+        message_type: &str,
+        payload: Vec<u8>,
+    ) -> Result<(), Error> {
+        drop(self.record(ServiceCall::SendTo {
+            peer: peer.clone(),
+            message_type: message_type.into(),
+            payload,
+        }));
+        Ok(())
+    }
+
+    fn broadcast(&mut self, message_type: &str, payload: Vec<u8>) -> Result<(), Error> {
+        drop(self.record(ServiceCall::Broadcast {
+            message_type: message_type.into(),
+            payload,
+        }));
+        Ok(())
+    }
+
+    fn initialize_block(&mut self, previous_id: Option<BlockId>) -> Result<(), Error> {
+        let mut state = self.record(ServiceCall::InitializeBlock(previous_id.clone()));
+        if state.block_in_progress.is_some() {
+            return Err(Error::InvalidState(
+                "Cannot initialize block in current state".into(),
+            ));
+        }
+        let previous_id = previous_id.unwrap_or_else(|| state.chain_head.clone());
+        state.block(&previous_id)?;
+        state.block_in_progress = Some(previous_id);
+        Ok(())
+    }
+
+    fn summarize_block(&mut self) -> Result<Vec<u8>, Error> {
+        let mut state = self.record(ServiceCall::SummarizeBlock);
+        let previous_id = state
+            .block_in_progress
+            .clone()
+            .ok_or_else(|| Error::InvalidState("Cannot summarize block in current state".into()))?;
+        if state.summaries_not_ready > 0 {
+            state.summaries_not_ready -= 1;
+            return Err(Error::BlockNotReady);
+        }
+        Ok(state.summary(&previous_id))
+    }
+
+    fn finalize_block(&mut self, data: Vec<u8>) -> Result<BlockId, Error> {
+        let mut state = self.record(ServiceCall::FinalizeBlock(data.clone()));
+        let previous_id = state
+            .block_in_progress
+            .take()
+            .ok_or_else(|| Error::InvalidState("Cannot finalize block in current state".into()))?;
+
+        let block_num = state.block(&previous_id)?.block_num + 1;
+        let signer_id = state.local_peer_id.clone();
+        let block = Block {
+            block_id: make_block_id(&previous_id, block_num, &signer_id, &data),
+            summary: state.summary(&previous_id),
+            previous_id,
+            signer_id,
+            block_num,
+            payload: data,
+        };
+
+        let block_id = block.block_id.clone();
+        state.blocks.insert(block_id.clone(), block.clone());
+        state.send_update(Update::BlockNew(block));
+        Ok(block_id)
+    }
+
+    fn cancel_block(&mut self) -> Result<(), Error> {
+        let mut state = self.record(ServiceCall::CancelBlock);
+        match state.block_in_progress.take() {
+            Some(_) => Ok(()),
+            None => Err(Error::InvalidState(
+                "Cannot cancel block in current state".into(),
+            )),
+        }
+    }
+
+    fn check_blocks(&mut self, priority: Vec<BlockId>) -> Result<(), Error> {
+        let state = self.record(ServiceCall::CheckBlocks(priority.clone()));
+        for block_id in &priority {
+            state.block(block_id)?;
+        }
+        for block_id in priority {
+            if state.invalid_blocks.contains(&block_id) {
+                state.send_update(Update::BlockInvalid(block_id));
+            } else {
+                state.send_update(Update::BlockValid(block_id));
+            }
+        }
+        Ok(())
+    }
+
+    fn commit_block(&mut self, block_id: BlockId) -> Result<(), Error> {
+        let mut state = self.record(ServiceCall::CommitBlock(block_id.clone()));
+        state.block(&block_id)?;
+        state.chain_head = block_id.clone();
+        state.committed.push(block_id.clone());
+        state.send_update(Update::BlockCommit(block_id));
+        Ok(())
+    }
+
+    fn ignore_block(&mut self, block_id: BlockId) -> Result<(), Error> {
+        let state = self.record(ServiceCall::IgnoreBlock(block_id.clone()));
+        state.block(&block_id).map(|_| ())
+    }
+
+    fn fail_block(&mut self, block_id: BlockId) -> Result<(), Error> {
+        let state = self.record(ServiceCall::FailBlock(block_id.clone()));
+        state.block(&block_id).map(|_| ())
+    }
+
+    fn get_blocks(&mut self, block_ids: Vec<BlockId>) -> Result<HashMap<BlockId, Block>, Error> {
+        let state = self.record(ServiceCall::GetBlocks(block_ids.clone()));
+        block_ids
+            .into_iter()
+            .map(|block_id| {
+                let block = state.block(&block_id)?.clone();
+                Ok((block_id, block))
+            })
+            .collect()
+    }
+
+    fn get_chain_head(&mut self) -> Result<Block, Error> {
+        let state = self.record(ServiceCall::GetChainHead);
+        Ok(state.blocks[&state.chain_head].clone())
+    }
+
+    fn get_settings(
+        &mut self,
+        block_id: BlockId,
+        keys: Vec<String>,
+    ) -> Result<HashMap<String, String>, Error> {
+        let state = self.record(ServiceCall::GetSettings(block_id.clone(), keys.clone()));
+        state.block(&block_id)?;
+        Ok(keys
+            .into_iter()
+            .filter_map(|key| state.settings.get(&key).map(|value| (key, value.clone())))
+            .collect())
+    }
+
+    fn get_state(
+        &mut self,
+        block_id: BlockId,
+        addresses: Vec<String>,
+    ) -> Result<HashMap<String, Vec<u8>>, Error> {
+        let state = self.record(ServiceCall::GetState(block_id.clone(), addresses.clone()));
+        state.block(&block_id)?;
+        Ok(addresses
+            .into_iter()
+            .filter_map(|address| {
+                state
+                    .state
+                    .get(&address)
+                    .map(|value| (address, value.clone()))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::RecvTimeoutError;
+    use std::thread;
+
+    use crate::consensus::engine::Engine;
+
+    fn next_update(updates: &Receiver<Update>) -> Update {
+        updates
+            .recv_timeout(Duration::from_secs(1))
+            .expect("No update received")
+    }
+
+    #[test]
+    fn build_and_commit_block() {
+        let (mut service, updates) = TestService::new(b"local".to_vec());
+        let genesis = service.chain_head();
+
+        service.initialize_block(None).unwrap();
+        let summary = service.summarize_block().unwrap();
+        let block_id = service.finalize_block(b"consensus".to_vec()).unwrap();
+
+        let block = match next_update(&updates) {
+            Update::BlockNew(block) => block,
+            update => panic!("Unexpected update {:?}", update),
+        };
+        assert_eq!(block.block_id, block_id);
+        assert_eq!(block.previous_id, genesis.block_id);
+        assert_eq!(block.block_num, 1);
+        assert_eq!(block.signer_id, b"local");
+        assert_eq!(block.payload, b"consensus");
+        assert_eq!(block.summary, summary);
+
+        service.check_blocks(vec![block_id.clone()]).unwrap();
+        match next_update(&updates) {
+            Update::BlockValid(id) => assert_eq!(id, block_id),
+            update => panic!("Unexpected update {:?}", update),
+        }
+
+        service.commit_block(block_id.clone()).unwrap();
+        match next_update(&updates) {
+            Update::BlockCommit(id) => assert_eq!(id, block_id),
+            update => panic!("Unexpected update {:?}", update),
+        }
+
+        assert_eq!(service.chain_head(), block);
+        service.assert_committed(&block_id);
+        assert_eq!(
+            service.calls(),
+            vec![
+                ServiceCall::InitializeBlock(None),
+                ServiceCall::SummarizeBlock,
+                ServiceCall::FinalizeBlock(b"consensus".to_vec()),
+                ServiceCall::CheckBlocks(vec![block_id.clone()]),
+                ServiceCall::CommitBlock(block_id),
+            ]
+        );
+    }
+
+    #[test]
+    fn simulated_errors() {
+        let (mut service, updates) = TestService::new(b"local".to_vec());
+
+        match service.summarize_block() {
+            Err(Error::InvalidState(_)) => (),
+            res => panic!("Expected InvalidState, got {:?}", res),
+        }
+        match service.initialize_block(Some(b"unknown".to_vec())) {
+            Err(Error::UnknownBlock(_)) => (),
+            res => panic!("Expected UnknownBlock, got {:?}", res),
+        }
+
+        service.set_summaries_not_ready(1);
+        service.initialize_block(None).unwrap();
+        match service.initialize_block(None) {
+            Err(Error::InvalidState(_)) => (),
+            res => panic!("Expected InvalidState, got {:?}", res),
+        }
+        match service.summarize_block() {
+            Err(Error::BlockNotReady) => (),
+            res => panic!("Expected BlockNotReady, got {:?}", res),
+        }
+        service.summarize_block().unwrap();
+        service.cancel_block().unwrap();
+
+        let genesis_id = service.chain_head().block_id;
+        let block = service.build_block(&genesis_id, b"peer", b"");
+        service.receive_block(block.clone());
+        service.set_invalid(&block.block_id);
+        service.check_blocks(vec![block.block_id.clone()]).unwrap();
+
+        match next_update(&updates) {
+            Update::BlockNew(new) => assert_eq!(new, block),
+            update => panic!("Unexpected update {:?}", update),
+        }
+        match next_update(&updates) {
+            Update::BlockInvalid(id) => assert_eq!(id, block.block_id),
+            update => panic!("Unexpected update {:?}", update),
+        }
+        service.assert_not_called(|call| matches!(call, ServiceCall::CommitBlock(_)));
+    }
+
+    /// An engine which publishes and commits one block
+    struct OneBlockEngine;
+
+    impl Engine for OneBlockEngine {
+        fn start(
+            &mut self,
+            updates: Receiver<Update>,
+            mut service: Box<dyn Service>,
+            _startup_state: StartupState,
+        ) -> Result<(), Error> {
+            service.initialize_block(None)?;
+            service.summarize_block()?;
+            service.finalize_block(b"one".to_vec())?;
+            loop {
+                match updates.recv_timeout(Duration::from_secs(1)) {
+                    Ok(Update::BlockNew(block)) => service.check_blocks(vec![block.block_id])?,
+                    Ok(Update::BlockValid(block_id)) => service.commit_block(block_id)?,
+                    Ok(Update::BlockCommit(_)) | Ok(Update::Shutdown) => return Ok(()),
+                    Ok(_) => (),
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
+            }
+        }
+
+        fn version(&self) -> String {
+            "0.1".into()
+        }
+
+        fn name(&self) -> String {
+            "one-block".into()
+        }
+
+        fn additional_protocols(&self) -> Vec<(String, String)> {
+            vec![]
+        }
+    }
+
+    #[test]
+    fn drive_engine() {
+        let (service, updates) = TestService::new(b"local".to_vec());
+        let startup_state = service.startup_state();
+
+        let engine_service = service.clone();
+        let engine_thread = thread::spawn(move || {
+            OneBlockEngine.start(updates, Box::new(engine_service), startup_state)
+        });
+
+        let block = service.wait_for_block(1, Duration::from_secs(1));
+        service.wait_for_call(
+            |call| *call == ServiceCall::CommitBlock(block.block_id.clone()),
+            Duration::from_secs(1),
+        );
+        engine_thread.join().unwrap().unwrap();
+
+        service.assert_called(&ServiceCall::CheckBlocks(vec![block.block_id.clone()]));
+        service.assert_committed(&block.block_id);
+    }
+}
@@ -19,9 +19,15 @@ use protobuf::{Message as ProtobufMessage, ProtobufError, RepeatedField};
 use rand::{distributions::Alphanumeric, Rng};
 
 use crate::consensus::engine::*;
-use crate::consensus::zmq_service::ZmqService;
+use crate::consensus::peer_message::verify_peer_message;
+use crate::consensus::zmq_service::{RetryPolicy, ZmqService};
 
+use crate::messaging::failover::{Backoff, Endpoints};
+use crate::messaging::metrics::ConnectionMetrics;
+use crate::messaging::recording::Recorder;
 use crate::messaging::stream::MessageConnection;
+use crate::messaging::stream::MessageFuture;
+use crate::messaging::stream::MessageReceiver;
 use crate::messaging::stream::MessageSender;
 use crate::messaging::stream::ReceiveError;
 use crate::messaging::stream::SendError;
@@ -30,12 +36,18 @@ use crate::messaging::zmq_stream::{ZmqMessageConnection, ZmqMessageSender};
 use crate::messages::consensus::*;
 use crate::messages::network::PingResponse;
 use crate::messages::validator::{Message, Message_MessageType};
+use crate::signing::Context;
 
+use std::mem;
 use std::sync::mpsc::{self, channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
 use std::thread;
 use std::time::Duration;
 
 const REGISTER_TIMEOUT: u64 = 300;
+/// The registration timeout used when there is another endpoint to fail over to; a connection to
+/// an unreachable endpoint does not fail, so registration only fails once this elapses
+const FAILOVER_REGISTER_TIMEOUT: Duration = Duration::from_secs(10);
 const SERVICE_TIMEOUT: u64 = 300;
 const INITAL_RETRY_DELAY: Duration = Duration::from_millis(100);
 const MAX_RETRY_DELAY: Duration = Duration::from_secs(3);
@@ -53,6 +65,13 @@ fn generate_correlation_id() -> String {
 
 pub struct ZmqDriver {
     stop_receiver: Receiver<()>,
+    reconnect_backoff: Backoff,
+    metrics: ConnectionMetrics,
+    service_retry_policy: RetryPolicy,
+    peer_message_verifier: Option<Box<dyn Context + Send>>,
+    reconnect: bool,
+    failover_register_timeout: Duration,
+    recorder: Option<Recorder>,
 }
 
 impl ZmqDriver {
@@ -62,23 +81,159 @@ impl ZmqDriver {
         let stop = Stop {
             sender: stop_sender,
         };
-        let driver = ZmqDriver { stop_receiver };
+        let driver = ZmqDriver {
+            stop_receiver,
+            reconnect_backoff: Backoff::default(),
+            metrics: ConnectionMetrics::new(),
+            service_retry_policy: RetryPolicy::default(),
+            peer_message_verifier: None,
+            reconnect: false,
+            failover_register_timeout: FAILOVER_REGISTER_TIMEOUT,
+            recorder: None,
+        };
         (driver, stop)
     }
 
+    /// Sets the policy the engine's service uses to retry requests the validator is not ready to
+    /// handle
+    pub fn set_service_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.service_retry_policy = retry_policy;
+    }
+
+    /// Verify the signature and content digest of every peer message with `context` before it is
+    /// passed to the engine; messages which fail verification are dropped
+    pub fn set_peer_message_verifier(&mut self, context: Box<dyn Context + Send>) {
+        self.peer_message_verifier = Some(context);
+    }
+
+    /// Returns the metrics of the driver's connections to the validator, which may be read
+    /// while the driver is running
+    pub fn connection_metrics(&self) -> ConnectionMetrics {
+        self.metrics.clone()
+    }
+
+    /// Sets the delays to wait before failing over to the next validator endpoint
+    pub fn set_reconnect_backoff(&mut self, backoff: Backoff) {
+        self.reconnect_backoff = backoff;
+    }
+
+    /// Sets whether the driver reconnects when the connection to the validator is lost
+    ///
+    /// When enabled, the driver connects to the validator endpoints in turn, waiting for the
+    /// reconnect backoff between attempts, until it registers again; the engine is then sent
+    /// `Update::Reconnected` with the new startup state and its service uses the new connection.
+    /// Otherwise, which is the default, the driver stops with an error. Connections given to
+    /// `start_with_connection` are never re-established.
+    pub fn set_reconnect(&mut self, reconnect: bool) {
+        self.reconnect = reconnect;
+    }
+
+    /// Sets how long to wait for each endpoint to respond to registration before failing over to
+    /// the next one, when more than one endpoint is given; the default is 10 seconds
+    pub fn set_failover_register_timeout(&mut self, timeout: Duration) {
+        self.failover_register_timeout = timeout;
+    }
+
+    /// Records every message exchanged with the validator with `recorder`, on every connection
+    /// the driver makes, including those made when reconnecting or failing over
+    pub fn set_recorder(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
     /// Start the driver with the given engine, consuming both
     ///
     /// The engine's start method will be run from the current thread and this method should block
     /// until the engine shutsdown.
-    pub fn start<T: AsRef<str>, E: Engine>(self, endpoint: T, mut engine: E) -> Result<(), Error> {
-        let validator_connection = ZmqMessageConnection::new(endpoint.as_ref());
-        let (mut validator_sender, validator_receiver) = validator_connection.create();
+    pub fn start<T: AsRef<str>, E: Engine>(self, endpoint: T, engine: E) -> Result<(), Error> {
+        self.start_with_endpoints(&[endpoint], engine)
+    }
 
-        let validator_sender_clone = validator_sender.clone();
-        let (update_sender, update_receiver) = channel();
+    /// Start the driver with the given engine, registering with the first of the given validator
+    /// endpoints that accepts the registration
+    ///
+    /// The endpoints are tried in order; if registration fails on every endpoint, the error from
+    /// the last attempt is returned. The engine's start method will be run from the current
+    /// thread and this method should block until the engine shutsdown.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `endpoints` is empty.
+    pub fn start_with_endpoints<T: AsRef<str>, E: Engine>(
+        mut self,
+        endpoints: &[T],
+        engine: E,
+    ) -> Result<(), Error> {
+        let mut endpoints = Endpoints::new(endpoints);
+        let registration = Registration::of(&engine, self.register_timeout(&endpoints));
+
+        let mut attempts = 0;
+        let (validator_sender, validator_receiver, startup_state) = loop {
+            attempts += 1;
+            info!("Connecting to validator at {}", endpoints.active());
+            match connect(
+                endpoints.active(),
+                &registration,
+                &self.metrics,
+                self.recorder.as_ref(),
+            ) {
+                Ok(connected) => break connected,
+                Err(err) if attempts < endpoints.len() => {
+                    let delay = self.reconnect_backoff.next_delay();
+                    let failed = endpoints.active().to_string();
+                    let next = endpoints.failover();
+                    warn!(
+                        "Failed to register with {}: {}; failing over to {} in {:?}",
+                        failed, err, next, delay
+                    );
+                    thread::sleep(delay);
+                }
+                Err(err) => return Err(err),
+            }
+        };
+        info!("Registered with validator at {}", endpoints.active());
+
+        if self.reconnect {
+            self.reconnect_backoff.reset();
+            let validator_sender = SharedSender::new(validator_sender);
+            let reconnect = Reconnect {
+                endpoints,
+                backoff: self.reconnect_backoff.clone(),
+                metrics: self.metrics.clone(),
+                recorder: self.recorder.clone(),
+                registration,
+                validator_sender: validator_sender.clone(),
+            };
+            self.run(
+                engine,
+                validator_sender,
+                validator_receiver,
+                startup_state,
+                Some(reconnect),
+            )
+        } else {
+            self.run(
+                engine,
+                validator_sender,
+                validator_receiver,
+                startup_state,
+                None,
+            )
+        }
+    }
+
+    /// Start the driver with the given engine over an existing connection, such as a
+    /// `ReplayConnection`, consuming both
+    ///
+    /// The engine is registered over the connection and the engine's start method will be run
+    /// from the current thread; this method should block until the engine shutsdown.
+    pub fn start_with_connection<C, S, E>(self, connection: &C, engine: E) -> Result<(), Error>
+    where
+        C: MessageConnection<S>,
+        S: MessageSender + Clone + Send + 'static,
+        E: Engine,
+    {
+        let (mut validator_sender, validator_receiver) = connection.create();
 
-        // Validators version 1.1 send startup info with the registration response; newer versions
-        // will send an activation message with the startup info
         let startup_state = match register(
             &mut validator_sender,
             Duration::from_secs(REGISTER_TIMEOUT),
@@ -90,28 +245,378 @@ impl ZmqDriver {
             None => wait_until_active(&validator_sender, &validator_receiver)?,
         };
 
+        self.run(
+            engine,
+            validator_sender,
+            validator_receiver,
+            startup_state,
+            None,
+        )
+    }
+
+    /// The registration timeout for `endpoints`: short enough to fail over past an unreachable
+    /// endpoint when there are several, or the full timeout for a single endpoint
+    fn register_timeout(&self, endpoints: &Endpoints) -> Duration {
+        if endpoints.len() > 1 {
+            self.failover_register_timeout
+        } else {
+            Duration::from_secs(REGISTER_TIMEOUT)
+        }
+    }
+
+    fn run<S, E>(
+        self,
+        mut engine: E,
+        validator_sender: S,
+        validator_receiver: MessageReceiver,
+        startup_state: StartupState,
+        reconnect: Option<Reconnect>,
+    ) -> Result<(), Error>
+    where
+        S: MessageSender + Clone + Send + 'static,
+        E: Engine,
+    {
+        let validator_sender_clone = validator_sender.clone();
+        let service_retry_policy = self.service_retry_policy.clone();
+        let (lifecycle, updates) = Lifecycle::new();
+        let lifecycle = Arc::new(lifecycle);
+        let driver_lifecycle = lifecycle.clone();
+
         let driver_thread = thread::spawn(move || {
-            driver_loop(
-                update_sender,
+            let result = drive(
+                &driver_lifecycle,
                 &self.stop_receiver,
                 validator_sender,
-                &validator_receiver,
-            )
+                validator_receiver,
+                self.peer_message_verifier.as_deref(),
+                reconnect,
+            );
+            driver_lifecycle.stop();
+            result
         });
 
-        engine.start(
-            update_receiver,
-            Box::new(ZmqService::new(
-                validator_sender_clone,
-                Duration::from_secs(SERVICE_TIMEOUT),
-            )),
-            startup_state,
-        )?;
+        // The engine is started again each time it returns from `start` after being deactivated,
+        // once the validator activates it again
+        let mut run = Some((updates, startup_state));
+        while let Some((updates, startup_state)) = run {
+            engine.start(
+                updates,
+                Box::new(
+                    ZmqService::new(
+                        validator_sender_clone.clone(),
+                        Duration::from_secs(SERVICE_TIMEOUT),
+                    )
+                    .with_retry_policy(service_retry_policy.clone()),
+                ),
+                startup_state,
+            )?;
+            run = lifecycle.wait_for_restart();
+        }
 
         driver_thread.join().expect("Driver panicked")
     }
 }
 
+/// The name, version and protocols the engine registers with, and how long to wait for the
+/// registration response
+struct Registration {
+    name: String,
+    version: String,
+    additional_protocols: Vec<(String, String)>,
+    timeout: Duration,
+}
+
+impl Registration {
+    fn of<E: Engine>(engine: &E, timeout: Duration) -> Self {
+        Registration {
+            name: engine.name(),
+            version: engine.version(),
+            additional_protocols: engine.additional_protocols(),
+            timeout,
+        }
+    }
+}
+
+/// Connect to the validator at `endpoint` and register the engine, waiting for it to be activated
+/// if necessary.
+fn connect(
+    endpoint: &str,
+    registration: &Registration,
+    metrics: &ConnectionMetrics,
+    recorder: Option<&Recorder>,
+) -> Result<(ZmqMessageSender, MessageReceiver, StartupState), Error> {
+    let mut validator_connection =
+        ZmqMessageConnection::new(endpoint).with_metrics(metrics.clone());
+    if let Some(recorder) = recorder {
+        validator_connection = validator_connection.with_recorder(recorder.clone());
+    }
+    let (mut validator_sender, validator_receiver) = validator_connection.create();
+
+    // Validators version 1.1 send startup info with the registration response; newer versions
+    // will send an activation message with the startup info
+    let result = register(
+        &mut validator_sender,
+        registration.timeout,
+        registration.name.clone(),
+        registration.version.clone(),
+        registration.additional_protocols.clone(),
+    )
+    .and_then(|startup_state| match startup_state {
+        Some(state) => Ok(state),
+        None => wait_until_active(&validator_sender, &validator_receiver),
+    });
+
+    match result {
+        Ok(startup_state) => Ok((validator_sender, validator_receiver, startup_state)),
+        Err(err) => {
+            validator_sender.close();
+            Err(err)
+        }
+    }
+}
+
+/// A sender to the validator which is replaced when the driver reconnects, so that the engine's
+/// service sends its requests over the new connection
+#[derive(Clone)]
+struct SharedSender {
+    sender: Arc<Mutex<ZmqMessageSender>>,
+}
+
+impl SharedSender {
+    fn new(sender: ZmqMessageSender) -> Self {
+        SharedSender {
+            sender: Arc::new(Mutex::new(sender)),
+        }
+    }
+
+    /// Use the given sender from now on, closing the previous one
+    fn replace(&self, sender: ZmqMessageSender) {
+        let mut previous = mem::replace(&mut *self.sender.lock().unwrap(), sender);
+        previous.close();
+    }
+}
+
+impl MessageSender for SharedSender {
+    fn send(
+        &self,
+        destination: Message_MessageType,
+        correlation_id: &str,
+        contents: &[u8],
+    ) -> Result<MessageFuture, SendError> {
+        self.sender
+            .lock()
+            .unwrap()
+            .send(destination, correlation_id, contents)
+    }
+
+    fn reply(
+        &self,
+        destination: Message_MessageType,
+        correlation_id: &str,
+        contents: &[u8],
+    ) -> Result<(), SendError> {
+        self.sender
+            .lock()
+            .unwrap()
+            .reply(destination, correlation_id, contents)
+    }
+
+    fn close(&mut self) {
+        self.sender.lock().unwrap().close();
+    }
+}
+
+/// Re-establishes the driver's connection after it is lost
+struct Reconnect {
+    endpoints: Endpoints,
+    backoff: Backoff,
+    metrics: ConnectionMetrics,
+    recorder: Option<Recorder>,
+    registration: Registration,
+    validator_sender: SharedSender,
+}
+
+impl Reconnect {
+    /// Connect to the validator endpoints in turn until the engine is registered again, returning
+    /// the new connection's receiver and startup state, or `None` if the driver is stopped first
+    fn reconnect(
+        &mut self,
+        stop_receiver: &Receiver<()>,
+    ) -> Option<(MessageReceiver, StartupState)> {
+        loop {
+            let delay = self.backoff.next_delay();
+            if self.endpoints.len() > 1 {
+                self.endpoints.failover();
+            }
+            info!(
+                "Reconnecting to validator at {} in {:?}",
+                self.endpoints.active(),
+                delay
+            );
+            if stop_receiver.recv_timeout(delay).is_ok() {
+                return None;
+            }
+
+            match connect(
+                self.endpoints.active(),
+                &self.registration,
+                &self.metrics,
+                self.recorder.as_ref(),
+            ) {
+                Ok((validator_sender, validator_receiver, startup_state)) => {
+                    info!("Registered with validator at {}", self.endpoints.active());
+                    self.backoff.reset();
+                    self.validator_sender.replace(validator_sender);
+                    return Some((validator_receiver, startup_state));
+                }
+                Err(err) => warn!(
+                    "Failed to register with {}: {}",
+                    self.endpoints.active(),
+                    err
+                ),
+            }
+        }
+    }
+}
+
+/// The activation state of the engine, shared by the driver's thread and the thread running the
+/// engine
+struct Lifecycle {
+    state: Mutex<LifecycleState>,
+    changed: Condvar,
+}
+
+struct LifecycleState {
+    updates: Sender<Update>,
+    // The receiver of the updates for the next run of the engine, once a deactivated engine has
+    // returned from `start`
+    next_updates: Option<Receiver<Update>>,
+    // Whether the engine was deactivated since it was last started
+    deactivated: bool,
+    // The startup state of the engine's latest reactivation
+    reactivation: Option<StartupState>,
+    stopped: bool,
+}
+
+impl LifecycleState {
+    /// Send the following updates to a new channel, to be received by the next run of the engine
+    fn redirect(&mut self) {
+        let (sender, receiver) = channel();
+        self.updates = sender;
+        self.next_updates = Some(receiver);
+    }
+
+    fn send(&mut self, update: Update) -> Result<(), Error> {
+        match self.updates.send(update) {
+            Ok(()) => Ok(()),
+            // A deactivated engine may have returned from `start`; keep the updates for its next
+            // run
+            Err(mpsc::SendError(update)) if self.deactivated => {
+                self.redirect();
+                self.updates.send(update).map_err(Error::from)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl Lifecycle {
+    /// Create the lifecycle of an active engine, and the receiver of the updates of its first run
+    fn new() -> (Self, Receiver<Update>) {
+        let (updates, receiver) = channel();
+        let lifecycle = Lifecycle {
+            state: Mutex::new(LifecycleState {
+                updates,
+                next_updates: None,
+                deactivated: false,
+                reactivation: None,
+                stopped: false,
+            }),
+            changed: Condvar::new(),
+        };
+        (lifecycle, receiver)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LifecycleState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Called when the engine has returned from `start`. If the engine was deactivated, waits
+    /// until it is reactivated and returns the receiver of its updates and the startup state to
+    /// start it again with; returns `None` if the engine should not be started again.
+    fn wait_for_restart(&self) -> Option<(Receiver<Update>, StartupState)> {
+        let mut state = self.lock();
+        if state.deactivated && state.next_updates.is_none() {
+            state.redirect();
+        }
+        loop {
+            if state.stopped || !state.deactivated {
+                return None;
+            }
+            if let Some(startup_state) = state.reactivation.take() {
+                state.deactivated = false;
+                return state
+                    .next_updates
+                    .take()
+                    .map(|updates| (updates, startup_state));
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Send an update to the engine
+    fn send(&self, update: Update) -> Result<(), Error> {
+        self.lock().send(update)
+    }
+
+    fn deactivate(&self) -> Result<(), Error> {
+        let mut state = self.lock();
+        state.deactivated = true;
+        state.send(Update::Deactivated)
+    }
+
+    fn activate(&self, startup_state: StartupState) -> Result<(), Error> {
+        let mut state = self.lock();
+        if !state.deactivated {
+            return state.send(Update::Activated(startup_state));
+        }
+
+        // An engine which is still running is sent the update and carries on as an active engine
+        if state.next_updates.is_none() {
+            if state
+                .updates
+                .send(Update::Activated(startup_state.clone()))
+                .is_ok()
+            {
+                state.deactivated = false;
+                state.reactivation = None;
+                return Ok(());
+            }
+            state.redirect();
+        }
+        state.reactivation = Some(startup_state);
+
+        // An engine which has returned from `start` is given the startup state when it is started
+        // again, rather than this update
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    /// Called when the driver's thread stops
+    fn stop(&self) {
+        self.lock().stopped = true;
+        self.changed.notify_all();
+    }
+}
+
+/// How the connection to the validator ended
+enum Served {
+    /// The connection was lost
+    Disconnected,
+    /// The driver was stopped
+    Stopped,
+}
+
 /// Utility class for signaling that the driver should be shutdown
 #[derive(Clone)]
 pub struct Stop {
@@ -126,22 +631,63 @@ impl Stop {
     }
 }
 
-fn driver_loop(
-    mut update_sender: Sender<Update>,
+/// Pass the validator's notifications to the engine until the driver is stopped, reconnecting
+/// with `reconnect` when the connection is lost
+fn drive<S: MessageSender>(
+    lifecycle: &Lifecycle,
     stop_receiver: &Receiver<()>,
-    mut validator_sender: ZmqMessageSender,
-    validator_receiver: &Receiver<Result<Message, ReceiveError>>,
+    mut validator_sender: S,
+    mut validator_receiver: MessageReceiver,
+    peer_message_verifier: Option<&(dyn Context + Send)>,
+    mut reconnect: Option<Reconnect>,
 ) -> Result<(), Error> {
+    loop {
+        let served = driver_loop(
+            lifecycle,
+            stop_receiver,
+            &mut validator_sender,
+            &validator_receiver,
+            peer_message_verifier,
+        )?;
+        let reconnect = match (served, reconnect.as_mut()) {
+            (Served::Stopped, _) => return Ok(()),
+            (Served::Disconnected, Some(reconnect)) => reconnect,
+            (Served::Disconnected, None) => {
+                return Err(Error::ReceiveError("Sender disconnected".into()))
+            }
+        };
+
+        match reconnect.reconnect(stop_receiver) {
+            Some((receiver, startup_state)) => {
+                validator_receiver = receiver;
+                lifecycle.send(Update::Reconnected(startup_state))?;
+            }
+            None => {
+                lifecycle.send(Update::Shutdown)?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn driver_loop<S: MessageSender>(
+    lifecycle: &Lifecycle,
+    stop_receiver: &Receiver<()>,
+    validator_sender: &mut S,
+    validator_receiver: &Receiver<Result<Message, ReceiveError>>,
+    peer_message_verifier: Option<&(dyn Context + Send)>,
+) -> Result<Served, Error> {
     loop {
         match validator_receiver.recv_timeout(Duration::from_millis(100)) {
             Err(RecvTimeoutError::Timeout) => {
                 if stop_receiver.try_recv().is_ok() {
-                    update_sender.send(Update::Shutdown)?;
-                    break Ok(());
+                    lifecycle.send(Update::Shutdown)?;
+                    break Ok(Served::Stopped);
                 }
             }
-            Err(RecvTimeoutError::Disconnected) => {
-                break Err(Error::ReceiveError("Sender disconnected".into()));
+            Err(RecvTimeoutError::Disconnected) | Ok(Err(ReceiveError::DisconnectedError)) => {
+                warn!("Lost the connection to the validator");
+                break Ok(Served::Disconnected);
             }
             Ok(Err(err)) => {
                 break Err(Error::ReceiveError(format!(
@@ -150,15 +696,17 @@ fn driver_loop(
                 )));
             }
             Ok(Ok(msg)) if msg.get_message_type() == Message_MessageType::PING_REQUEST => {
-                send_ping_reply(&mut validator_sender, msg.get_correlation_id())?;
+                send_ping_reply(validator_sender, msg.get_correlation_id())?;
             }
             Ok(Ok(msg)) => {
-                if let Err(err) = handle_update(&msg, &mut validator_sender, &mut update_sender) {
+                if let Err(err) =
+                    handle_update(&msg, validator_sender, lifecycle, peer_message_verifier)
+                {
                     break Err(err);
                 }
                 if stop_receiver.try_recv().is_ok() {
-                    update_sender.send(Update::Shutdown)?;
-                    break Ok(());
+                    lifecycle.send(Update::Shutdown)?;
+                    break Ok(Served::Stopped);
                 }
             }
         }
@@ -193,7 +741,7 @@ pub fn register(
     // Keep trying to register until the response is something other
     // than NOT_READY.
 
-    let mut retry_delay = INITAL_RETRY_DELAY;
+    let mut backoff = Backoff::new(INITAL_RETRY_DELAY, MAX_RETRY_DELAY);
     loop {
         match msg.get_message_type() {
             Message_MessageType::CONSENSUS_REGISTER_RESPONSE => {
@@ -220,13 +768,7 @@ pub fn register(
                         break;
                     }
                     ConsensusRegisterResponse_Status::NOT_READY => {
-                        thread::sleep(retry_delay);
-                        if retry_delay < MAX_RETRY_DELAY {
-                            retry_delay *= 2;
-                            if retry_delay > MAX_RETRY_DELAY {
-                                retry_delay = MAX_RETRY_DELAY;
-                            }
-                        }
+                        thread::sleep(backoff.next_delay());
                         msg = sender
                             .send(
                                 Message_MessageType::CONSENSUS_REGISTER_REQUEST,
@@ -262,7 +804,7 @@ pub fn register(
 }
 
 fn wait_until_active(
-    validator_sender: &ZmqMessageSender,
+    validator_sender: &dyn MessageSender,
     validator_receiver: &Receiver<Result<Message, ReceiveError>>,
 ) -> Result<StartupState, Error> {
     use self::Message_MessageType::*;
@@ -316,7 +858,8 @@ fn wait_until_active(
 fn handle_update(
     msg: &Message,
     validator_sender: &mut dyn MessageSender,
-    update_sender: &mut Sender<Update>,
+    lifecycle: &Lifecycle,
+    peer_message_verifier: Option<&(dyn Context + Send)>,
 ) -> Result<(), Error> {
     use self::Message_MessageType::*;
 
@@ -336,11 +879,24 @@ fn handle_update(
                 ProtobufMessage::parse_from_bytes(msg.get_content())?;
             let header: ConsensusPeerMessageHeader =
                 ProtobufMessage::parse_from_bytes(request.get_message().get_header())?;
-            let message = request.take_message();
-            Update::PeerMessage(
-                from_consensus_peer_message(message, header),
-                request.take_sender_id(),
-            )
+            let message = from_consensus_peer_message(request.take_message(), header);
+            let sender_id = request.take_sender_id();
+            if let Some(context) = peer_message_verifier {
+                if let Err(err) = verify_peer_message(context, &message) {
+                    warn!(
+                        "Dropping peer message from {} which failed verification: {}",
+                        hex::encode(&sender_id),
+                        err
+                    );
+                    validator_sender.reply(
+                        Message_MessageType::CONSENSUS_NOTIFY_ACK,
+                        msg.get_correlation_id(),
+                        &[],
+                    )?;
+                    return Ok(());
+                }
+            }
+            Update::PeerMessage(message, sender_id)
         }
         CONSENSUS_NOTIFY_BLOCK_NEW => {
             let mut request: ConsensusNotifyBlockNew =
@@ -362,7 +918,24 @@ fn handle_update(
                 ProtobufMessage::parse_from_bytes(msg.get_content())?;
             Update::BlockCommit(request.take_block_id())
         }
-        CONSENSUS_NOTIFY_ENGINE_DEACTIVATED => Update::Shutdown,
+        CONSENSUS_NOTIFY_ENGINE_ACTIVATED => {
+            let mut request: ConsensusNotifyEngineActivated =
+                ProtobufMessage::parse_from_bytes(msg.get_content())?;
+            lifecycle.activate(StartupState {
+                chain_head: request.take_chain_head().into(),
+                peers: request
+                    .take_peers()
+                    .into_iter()
+                    .map(|info| info.into())
+                    .collect(),
+                local_peer_info: request.take_local_peer_info().into(),
+            })?;
+            return ack(msg, validator_sender);
+        }
+        CONSENSUS_NOTIFY_ENGINE_DEACTIVATED => {
+            lifecycle.deactivate()?;
+            return ack(msg, validator_sender);
+        }
         unexpected => {
             warn!(
                 "Received unexpected message type: {:?}; ignoring",
@@ -372,7 +945,11 @@ fn handle_update(
         }
     };
 
-    update_sender.send(update)?;
+    lifecycle.send(update)?;
+    ack(msg, validator_sender)
+}
+
+fn ack(msg: &Message, validator_sender: &mut dyn MessageSender) -> Result<(), Error> {
     validator_sender.reply(
         Message_MessageType::CONSENSUS_NOTIFY_ACK,
         msg.get_correlation_id(),
@@ -429,7 +1006,7 @@ impl From<ConsensusPeerInfo> for PeerInfo {
     }
 }
 
-fn from_consensus_peer_message(
+pub(crate) fn from_consensus_peer_message(
     mut c_msg: ConsensusPeerMessage,
     mut c_msg_header: ConsensusPeerMessageHeader,
 ) -> PeerMessage {
@@ -481,7 +1058,10 @@ impl From<ReceiveError> for Error {
 mod tests {
     use super::*;
     use crate::consensus::engine::tests::MockEngine;
+    use crate::consensus::peer_message::tests::{codec, Vote};
     use crate::messages::network::PingRequest;
+    use crate::messaging::recording::{read_recording, Direction};
+    use crate::signing::secp256k1::Secp256k1Context;
     use std::sync::{Arc, Mutex};
     use zmq;
 
@@ -658,6 +1238,402 @@ mod tests {
         assert!(contains(&*final_calls, "BlockCommit"));
     }
 
+    #[test]
+    fn test_zmq_driver_failover() {
+        let ctx = zmq::Context::new();
+        let rejecting_socket = ctx.socket(zmq::ROUTER).expect("Failed to create context");
+        rejecting_socket
+            .bind("tcp://127.0.0.1:*")
+            .expect("Failed to bind socket");
+        let rejecting_addr = rejecting_socket.get_last_endpoint().unwrap().unwrap();
+
+        let socket = ctx.socket(zmq::ROUTER).expect("Failed to create context");
+        socket
+            .bind("tcp://127.0.0.1:*")
+            .expect("Failed to bind socket");
+        let addr = socket.get_last_endpoint().unwrap().unwrap();
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mock_engine = MockEngine::with(calls.clone());
+
+        let (driver, stop) = ZmqDriver::new();
+        let metrics = driver.connection_metrics();
+
+        let driver_thread = thread::spawn(move || {
+            driver.start_with_endpoints(&[rejecting_addr, addr], mock_engine)
+        });
+
+        let mut response = ConsensusRegisterResponse::new();
+        response.set_status(ConsensusRegisterResponse_Status::SERVICE_ERROR);
+        let (_, _): (_, ConsensusRegisterRequest) = recv_rep(
+            &rejecting_socket,
+            Message_MessageType::CONSENSUS_REGISTER_REQUEST,
+            response,
+            Message_MessageType::CONSENSUS_REGISTER_RESPONSE,
+        );
+
+        let mut response = ConsensusRegisterResponse::new();
+        response.set_status(ConsensusRegisterResponse_Status::OK);
+        let (connection_id, _): (_, ConsensusRegisterRequest) = recv_rep(
+            &socket,
+            Message_MessageType::CONSENSUS_REGISTER_REQUEST,
+            response,
+            Message_MessageType::CONSENSUS_REGISTER_RESPONSE,
+        );
+
+        let _: ConsensusNotifyAck = send_req_rep(
+            &connection_id,
+            &socket,
+            ConsensusNotifyEngineActivated::new(),
+            Message_MessageType::CONSENSUS_NOTIFY_ENGINE_ACTIVATED,
+            Message_MessageType::CONSENSUS_NOTIFY_ACK,
+        );
+
+        stop.stop();
+        driver_thread
+            .join()
+            .expect("Driver thread panicked")
+            .expect("Driver thread returned an error");
+
+        assert!(contains(&calls.lock().unwrap(), "start"));
+
+        let metrics = metrics.snapshot();
+        assert_eq!(metrics.reconnects, 1);
+        assert_eq!(
+            metrics.messages_sent[&Message_MessageType::CONSENSUS_REGISTER_REQUEST],
+            2
+        );
+        assert_eq!(
+            metrics.reply_latency[&Message_MessageType::CONSENSUS_REGISTER_REQUEST].count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_zmq_driver_reactivation() {
+        let ctx = zmq::Context::new();
+        let socket = ctx.socket(zmq::ROUTER).expect("Failed to create context");
+        socket
+            .bind("tcp://127.0.0.1:*")
+            .expect("Failed to bind socket");
+        let addr = socket.get_last_endpoint().unwrap().unwrap();
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mock_engine = MockEngine::with(calls.clone());
+
+        let (driver, stop) = ZmqDriver::new();
+        let driver_thread = thread::spawn(move || driver.start(&addr, mock_engine));
+
+        let mut response = ConsensusRegisterResponse::new();
+        response.set_status(ConsensusRegisterResponse_Status::OK);
+        let (connection_id, _): (_, ConsensusRegisterRequest) = recv_rep(
+            &socket,
+            Message_MessageType::CONSENSUS_REGISTER_REQUEST,
+            response,
+            Message_MessageType::CONSENSUS_REGISTER_RESPONSE,
+        );
+        let _: ConsensusNotifyAck = send_req_rep(
+            &connection_id,
+            &socket,
+            ConsensusNotifyEngineActivated::new(),
+            Message_MessageType::CONSENSUS_NOTIFY_ENGINE_ACTIVATED,
+            Message_MessageType::CONSENSUS_NOTIFY_ACK,
+        );
+
+        // The mock engine returns from start when it is deactivated
+        let _: ConsensusNotifyAck = send_req_rep(
+            &connection_id,
+            &socket,
+            ConsensusNotifyEngineDeactivated::new(),
+            Message_MessageType::CONSENSUS_NOTIFY_ENGINE_DEACTIVATED,
+            Message_MessageType::CONSENSUS_NOTIFY_ACK,
+        );
+        let _: ConsensusNotifyAck = send_req_rep(
+            &connection_id,
+            &socket,
+            ConsensusNotifyEngineActivated::new(),
+            Message_MessageType::CONSENSUS_NOTIFY_ENGINE_ACTIVATED,
+            Message_MessageType::CONSENSUS_NOTIFY_ACK,
+        );
+        let _: ConsensusNotifyAck = send_req_rep(
+            &connection_id,
+            &socket,
+            ConsensusNotifyBlockNew::new(),
+            Message_MessageType::CONSENSUS_NOTIFY_BLOCK_NEW,
+            Message_MessageType::CONSENSUS_NOTIFY_ACK,
+        );
+
+        stop.stop();
+        driver_thread
+            .join()
+            .expect("Driver thread panicked")
+            .expect("Driver thread returned an error");
+
+        let final_calls = calls.lock().unwrap();
+        assert_eq!(
+            final_calls
+                .iter()
+                .filter(|call| call.as_str() == "start")
+                .count(),
+            2
+        );
+        assert!(contains(&*final_calls, "Deactivated"));
+        assert!(contains(&*final_calls, "BlockNew"));
+        assert!(!contains(&*final_calls, "Activated"));
+    }
+
+    #[test]
+    fn test_zmq_driver_recorder() {
+        let ctx = zmq::Context::new();
+        let socket = ctx.socket(zmq::ROUTER).expect("Failed to create context");
+        socket
+            .bind("tcp://127.0.0.1:*")
+            .expect("Failed to bind socket");
+        let addr = socket.get_last_endpoint().unwrap().unwrap();
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("recording");
+
+        let (mut driver, stop) = ZmqDriver::new();
+        driver.set_recorder(Recorder::create(&path).unwrap());
+        let driver_thread = thread::spawn(move || driver.start(&addr, MockEngine::new()));
+
+        let mut response = ConsensusRegisterResponse::new();
+        response.set_status(ConsensusRegisterResponse_Status::OK);
+        let (connection_id, _): (_, ConsensusRegisterRequest) = recv_rep(
+            &socket,
+            Message_MessageType::CONSENSUS_REGISTER_REQUEST,
+            response,
+            Message_MessageType::CONSENSUS_REGISTER_RESPONSE,
+        );
+        let _: ConsensusNotifyAck = send_req_rep(
+            &connection_id,
+            &socket,
+            ConsensusNotifyEngineActivated::new(),
+            Message_MessageType::CONSENSUS_NOTIFY_ENGINE_ACTIVATED,
+            Message_MessageType::CONSENSUS_NOTIFY_ACK,
+        );
+
+        stop.stop();
+        driver_thread
+            .join()
+            .expect("Driver thread panicked")
+            .expect("Driver thread returned an error");
+
+        let records = read_recording(&path)
+            .unwrap()
+            .into_iter()
+            .map(|record| (record.direction, record.message.get_message_type()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            &records[..3],
+            &[
+                (
+                    Direction::Outbound,
+                    Message_MessageType::CONSENSUS_REGISTER_REQUEST
+                ),
+                (
+                    Direction::Inbound,
+                    Message_MessageType::CONSENSUS_REGISTER_RESPONSE
+                ),
+                (
+                    Direction::Inbound,
+                    Message_MessageType::CONSENSUS_NOTIFY_ENGINE_ACTIVATED
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lifecycle_engine_running_through_deactivation() {
+        let (lifecycle, updates) = Lifecycle::new();
+        let lifecycle = Arc::new(lifecycle);
+
+        // An engine which keeps running when it is deactivated, and returns on shutdown
+        let engine_lifecycle = lifecycle.clone();
+        let (done_sender, done_receiver) = channel();
+        thread::spawn(move || {
+            let mut received = vec![];
+            let mut starts = 0;
+            let mut run = Some((updates, StartupState::default()));
+            while let Some((updates, _)) = run {
+                starts += 1;
+                while let Ok(update) = updates.recv() {
+                    match update {
+                        Update::Shutdown => break,
+                        Update::Deactivated => received.push("Deactivated"),
+                        Update::Activated(_) => received.push("Activated"),
+                        _ => (),
+                    }
+                }
+                run = engine_lifecycle.wait_for_restart();
+            }
+            done_sender.send((starts, received)).unwrap();
+        });
+
+        lifecycle.deactivate().unwrap();
+        lifecycle.activate(StartupState::default()).unwrap();
+        lifecycle.send(Update::Shutdown).unwrap();
+
+        // The engine is not started again after it returns on shutdown
+        let (starts, received) = done_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("Engine was restarted after shutdown");
+        lifecycle.stop();
+        assert_eq!(starts, 1);
+        assert_eq!(received, vec!["Deactivated", "Activated"]);
+    }
+
+    #[test]
+    fn test_zmq_driver_reconnect() {
+        let ctx = zmq::Context::new();
+        let socket = ctx.socket(zmq::ROUTER).expect("Failed to create context");
+        socket.set_linger(0).unwrap();
+        socket
+            .bind("tcp://127.0.0.1:*")
+            .expect("Failed to bind socket");
+        let addr = socket.get_last_endpoint().unwrap().unwrap();
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mock_engine = MockEngine::with(calls.clone());
+
+        let (mut driver, stop) = ZmqDriver::new();
+        driver.set_reconnect(true);
+        driver.set_reconnect_backoff(Backoff::new(
+            Duration::from_millis(10),
+            Duration::from_millis(100),
+        ));
+        let metrics = driver.connection_metrics();
+
+        let driver_addr = addr.clone();
+        let driver_thread = thread::spawn(move || driver.start(&driver_addr, mock_engine));
+
+        let mut response = ConsensusRegisterResponse::new();
+        response.set_status(ConsensusRegisterResponse_Status::OK);
+        let (connection_id, _): (_, ConsensusRegisterRequest) = recv_rep(
+            &socket,
+            Message_MessageType::CONSENSUS_REGISTER_REQUEST,
+            response,
+            Message_MessageType::CONSENSUS_REGISTER_RESPONSE,
+        );
+        let _: ConsensusNotifyAck = send_req_rep(
+            &connection_id,
+            &socket,
+            ConsensusNotifyEngineActivated::new(),
+            Message_MessageType::CONSENSUS_NOTIFY_ENGINE_ACTIVATED,
+            Message_MessageType::CONSENSUS_NOTIFY_ACK,
+        );
+
+        // Restart the validator
+        drop(socket);
+        let socket = ctx.socket(zmq::ROUTER).expect("Failed to create context");
+        socket.bind(&addr).expect("Failed to bind socket");
+
+        let mut response = ConsensusRegisterResponse::new();
+        response.set_status(ConsensusRegisterResponse_Status::OK);
+        let (connection_id, _): (_, ConsensusRegisterRequest) = recv_rep(
+            &socket,
+            Message_MessageType::CONSENSUS_REGISTER_REQUEST,
+            response,
+            Message_MessageType::CONSENSUS_REGISTER_RESPONSE,
+        );
+        let _: ConsensusNotifyAck = send_req_rep(
+            &connection_id,
+            &socket,
+            ConsensusNotifyEngineActivated::new(),
+            Message_MessageType::CONSENSUS_NOTIFY_ENGINE_ACTIVATED,
+            Message_MessageType::CONSENSUS_NOTIFY_ACK,
+        );
+        let _: ConsensusNotifyAck = send_req_rep(
+            &connection_id,
+            &socket,
+            ConsensusNotifyBlockNew::new(),
+            Message_MessageType::CONSENSUS_NOTIFY_BLOCK_NEW,
+            Message_MessageType::CONSENSUS_NOTIFY_ACK,
+        );
+
+        stop.stop();
+        driver_thread
+            .join()
+            .expect("Driver thread panicked")
+            .expect("Driver thread returned an error");
+
+        let final_calls = calls.lock().unwrap();
+        assert!(contains(&*final_calls, "Reconnected"));
+        assert!(contains(&*final_calls, "BlockNew"));
+        assert_eq!(metrics.snapshot().reconnects, 1);
+    }
+
+    #[test]
+    fn test_zmq_driver_peer_message_verification() {
+        let ctx = zmq::Context::new();
+        let socket = ctx.socket(zmq::ROUTER).expect("Failed to create context");
+        socket
+            .bind("tcp://127.0.0.1:*")
+            .expect("Failed to bind socket");
+        let addr = socket.get_last_endpoint().unwrap().unwrap();
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mock_engine = MockEngine::with(calls.clone());
+
+        let (mut driver, stop) = ZmqDriver::new();
+        driver.set_peer_message_verifier(Box::new(Secp256k1Context::new()));
+
+        let driver_thread = thread::spawn(move || driver.start(&addr, mock_engine));
+
+        let mut response = ConsensusRegisterResponse::new();
+        response.set_status(ConsensusRegisterResponse_Status::OK);
+        let (connection_id, _): (_, ConsensusRegisterRequest) = recv_rep(
+            &socket,
+            Message_MessageType::CONSENSUS_REGISTER_REQUEST,
+            response,
+            Message_MessageType::CONSENSUS_REGISTER_RESPONSE,
+        );
+
+        let _: ConsensusNotifyAck = send_req_rep(
+            &connection_id,
+            &socket,
+            ConsensusNotifyEngineActivated::new(),
+            Message_MessageType::CONSENSUS_NOTIFY_ENGINE_ACTIVATED,
+            Message_MessageType::CONSENSUS_NOTIFY_ACK,
+        );
+
+        // An unsigned message is acknowledged but not passed to the engine
+        let _: ConsensusNotifyAck = send_req_rep(
+            &connection_id,
+            &socket,
+            ConsensusNotifyPeerMessage::new(),
+            Message_MessageType::CONSENSUS_NOTIFY_PEER_MESSAGE,
+            Message_MessageType::CONSENSUS_NOTIFY_ACK,
+        );
+
+        let (_, payload) = codec().encode(&Vote::Yes(1)).unwrap();
+        let mut request = ConsensusNotifyPeerMessage::new();
+        request.set_message(ProtobufMessage::parse_from_bytes(&payload).unwrap());
+        let _: ConsensusNotifyAck = send_req_rep(
+            &connection_id,
+            &socket,
+            request,
+            Message_MessageType::CONSENSUS_NOTIFY_PEER_MESSAGE,
+            Message_MessageType::CONSENSUS_NOTIFY_ACK,
+        );
+
+        stop.stop();
+        driver_thread
+            .join()
+            .expect("Driver thread panicked")
+            .expect("Driver thread returned an error");
+
+        let final_calls = calls.lock().unwrap();
+        assert_eq!(
+            final_calls
+                .iter()
+                .filter(|call| call.as_str() == "PeerMessage")
+                .count(),
+            1
+        );
+    }
+
     fn contains(calls: &Vec<String>, expected: &str) -> bool {
         for call in calls {
             if expected == call.as_str() {
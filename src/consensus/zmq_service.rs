@@ -21,13 +21,15 @@ use rand::{distributions::Alphanumeric, Rng};
 use crate::consensus::engine::*;
 use crate::consensus::service::Service;
 
+use crate::messaging::failover::Backoff;
 use crate::messaging::stream::MessageSender;
 use crate::messaging::zmq_stream::ZmqMessageSender;
 
 use crate::messages::consensus::*;
 use crate::messages::validator::Message_MessageType;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::thread;
 use std::time::Duration;
 
 /// Generates a random correlation id for use in Message
@@ -41,14 +43,75 @@ fn generate_correlation_id() -> String {
         .collect::<String>()
 }
 
-pub struct ZmqService {
-    sender: ZmqMessageSender,
+/// How a ZmqService retries requests which the validator answers with `NOT_READY`.
+///
+/// By default requests are not retried.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    backoff: Backoff,
+    excluded: HashSet<Message_MessageType>,
+}
+
+impl RetryPolicy {
+    /// Create a policy which makes up to `max_attempts` attempts at each request, waiting
+    /// between attempts with the default backoff.
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts,
+            backoff: Backoff::default(),
+            excluded: HashSet::new(),
+        }
+    }
+
+    /// Set the delays to wait between attempts
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Never retry requests of the given type, such as `CONSENSUS_INITIALIZE_BLOCK_REQUEST`
+    pub fn without_retry(mut self, request_type: Message_MessageType) -> Self {
+        self.excluded.insert(request_type);
+        self
+    }
+
+    fn max_attempts(&self, request_type: Message_MessageType) -> u32 {
+        if self.excluded.contains(&request_type) {
+            1
+        } else {
+            self.max_attempts.max(1)
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(1)
+    }
+}
+
+/// A Service which sends its requests to the validator with a MessageSender, a
+/// `ZmqMessageSender` by default.
+pub struct ZmqService<S: MessageSender = ZmqMessageSender> {
+    sender: S,
     timeout: Duration,
+    retry_policy: RetryPolicy,
 }
 
-impl ZmqService {
-    pub fn new(sender: ZmqMessageSender, timeout: Duration) -> Self {
-        ZmqService { sender, timeout }
+impl<S: MessageSender> ZmqService<S> {
+    pub fn new(sender: S, timeout: Duration) -> Self {
+        ZmqService {
+            sender,
+            timeout,
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    /// Set the policy for retrying requests the validator is not ready to handle
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
     }
 
     /// Serialize and send a request, wait for the default timeout, and receive and parse an
@@ -76,22 +139,250 @@ impl ZmqService {
             )))
         }
     }
+
+    /// Send a request and check the status of its response, retrying according to the retry
+    /// policy while the validator is not ready.
+    fn request<I: ProtobufMessage, O: ConsensusResponse>(
+        &mut self,
+        request: &I,
+        request_type: Message_MessageType,
+        response_type: Message_MessageType,
+        action: &str,
+    ) -> Result<O, Error> {
+        let max_attempts = self.retry_policy.max_attempts(request_type);
+        let mut backoff = self.retry_policy.backoff.clone();
+        backoff.reset();
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let response: O = self.rpc(request, request_type, response_type)?;
+            match response.check_status(action) {
+                Err(Error::NotReady) if attempts < max_attempts => {
+                    let delay = backoff.next_delay();
+                    debug!("Validator not ready to {}, retrying in {:?}", action, delay);
+                    thread::sleep(delay);
+                }
+                result => return result.map(|_| response),
+            }
+        }
+    }
+}
+
+/// A validator response with a status
+trait ConsensusResponse: ProtobufMessage {
+    /// Return Ok(()) if the status is OK, or the error for the status. `action` describes the
+    /// request, as in "initialize block".
+    fn check_status(&self, action: &str) -> Result<(), Error>;
+}
+
+/// Build the error for a non-OK response status
+macro_rules! status_error {
+    (STATUS_UNSET, $action:expr) => {
+        Error::ReceiveError(format!("Response to {} has no status", $action))
+    };
+    (BAD_REQUEST, $action:expr) => {
+        Error::BadRequest(format!("Validator rejected {} request", $action))
+    };
+    (SERVICE_ERROR, $action:expr) => {
+        Error::ServiceError(format!("Validator failed to {}", $action))
+    };
+    (NOT_READY, $action:expr) => {
+        Error::NotReady
+    };
+    (NOT_ACTIVE_ENGINE, $action:expr) => {
+        Error::NotActiveEngine
+    };
+    (INVALID_STATE, $action:expr) => {
+        Error::InvalidState(format!("Cannot {} in current state", $action))
+    };
+    (UNKNOWN_BLOCK, $action:expr) => {
+        Error::UnknownBlock("Block not found".into())
+    };
+    (UNKNOWN_PEER, $action:expr) => {
+        Error::UnknownPeer("Peer not found".into())
+    };
+    (BLOCK_NOT_READY, $action:expr) => {
+        Error::BlockNotReady
+    };
+    (NO_CHAIN_HEAD, $action:expr) => {
+        Error::NoChainHead
+    };
 }
 
-/// Return Ok(()) if $r.get_status() matches $ok
-macro_rules! check_ok {
-    ($r:expr, $ok:pat) => {
-        match $r.get_status() {
-            $ok => Ok(()),
-            status => Err(Error::ReceiveError(format!(
-                "Failed with status {:?}",
-                status
-            ))),
+/// Implement ConsensusResponse for a response, listing all of the non-OK variants of its status
+/// so that every status is mapped
+macro_rules! response_status {
+    ($response:ident, $status:ident { $($variant:ident),* }) => {
+        impl ConsensusResponse for $response {
+            fn check_status(&self, action: &str) -> Result<(), Error> {
+                match self.get_status() {
+                    $status::OK => Ok(()),
+                    $($status::$variant => Err(status_error!($variant, action)),)*
+                }
+            }
         }
     };
 }
 
-impl Service for ZmqService {
+response_status!(
+    ConsensusSendToResponse,
+    ConsensusSendToResponse_Status {
+        STATUS_UNSET,
+        BAD_REQUEST,
+        SERVICE_ERROR,
+        NOT_READY,
+        UNKNOWN_PEER,
+        NOT_ACTIVE_ENGINE
+    }
+);
+response_status!(
+    ConsensusBroadcastResponse,
+    ConsensusBroadcastResponse_Status {
+        STATUS_UNSET,
+        BAD_REQUEST,
+        SERVICE_ERROR,
+        NOT_READY,
+        NOT_ACTIVE_ENGINE
+    }
+);
+response_status!(
+    ConsensusInitializeBlockResponse,
+    ConsensusInitializeBlockResponse_Status {
+        STATUS_UNSET,
+        BAD_REQUEST,
+        SERVICE_ERROR,
+        NOT_READY,
+        INVALID_STATE,
+        UNKNOWN_BLOCK,
+        NOT_ACTIVE_ENGINE
+    }
+);
+response_status!(
+    ConsensusSummarizeBlockResponse,
+    ConsensusSummarizeBlockResponse_Status {
+        STATUS_UNSET,
+        BAD_REQUEST,
+        SERVICE_ERROR,
+        NOT_READY,
+        INVALID_STATE,
+        BLOCK_NOT_READY,
+        NOT_ACTIVE_ENGINE
+    }
+);
+response_status!(
+    ConsensusFinalizeBlockResponse,
+    ConsensusFinalizeBlockResponse_Status {
+        STATUS_UNSET,
+        BAD_REQUEST,
+        SERVICE_ERROR,
+        NOT_READY,
+        INVALID_STATE,
+        BLOCK_NOT_READY,
+        NOT_ACTIVE_ENGINE
+    }
+);
+response_status!(
+    ConsensusCancelBlockResponse,
+    ConsensusCancelBlockResponse_Status {
+        STATUS_UNSET,
+        BAD_REQUEST,
+        SERVICE_ERROR,
+        NOT_READY,
+        INVALID_STATE,
+        NOT_ACTIVE_ENGINE
+    }
+);
+response_status!(
+    ConsensusCheckBlocksResponse,
+    ConsensusCheckBlocksResponse_Status {
+        STATUS_UNSET,
+        BAD_REQUEST,
+        SERVICE_ERROR,
+        NOT_READY,
+        UNKNOWN_BLOCK,
+        NOT_ACTIVE_ENGINE
+    }
+);
+response_status!(
+    ConsensusCommitBlockResponse,
+    ConsensusCommitBlockResponse_Status {
+        STATUS_UNSET,
+        BAD_REQUEST,
+        SERVICE_ERROR,
+        NOT_READY,
+        UNKNOWN_BLOCK,
+        NOT_ACTIVE_ENGINE
+    }
+);
+response_status!(
+    ConsensusIgnoreBlockResponse,
+    ConsensusIgnoreBlockResponse_Status {
+        STATUS_UNSET,
+        BAD_REQUEST,
+        SERVICE_ERROR,
+        NOT_READY,
+        UNKNOWN_BLOCK,
+        NOT_ACTIVE_ENGINE
+    }
+);
+response_status!(
+    ConsensusFailBlockResponse,
+    ConsensusFailBlockResponse_Status {
+        STATUS_UNSET,
+        BAD_REQUEST,
+        SERVICE_ERROR,
+        NOT_READY,
+        UNKNOWN_BLOCK,
+        NOT_ACTIVE_ENGINE
+    }
+);
+response_status!(
+    ConsensusBlocksGetResponse,
+    ConsensusBlocksGetResponse_Status {
+        STATUS_UNSET,
+        BAD_REQUEST,
+        SERVICE_ERROR,
+        NOT_READY,
+        UNKNOWN_BLOCK,
+        NOT_ACTIVE_ENGINE
+    }
+);
+response_status!(
+    ConsensusChainHeadGetResponse,
+    ConsensusChainHeadGetResponse_Status {
+        STATUS_UNSET,
+        BAD_REQUEST,
+        SERVICE_ERROR,
+        NOT_READY,
+        NO_CHAIN_HEAD,
+        NOT_ACTIVE_ENGINE
+    }
+);
+response_status!(
+    ConsensusSettingsGetResponse,
+    ConsensusSettingsGetResponse_Status {
+        STATUS_UNSET,
+        BAD_REQUEST,
+        SERVICE_ERROR,
+        NOT_READY,
+        UNKNOWN_BLOCK,
+        NOT_ACTIVE_ENGINE
+    }
+);
+response_status!(
+    ConsensusStateGetResponse,
+    ConsensusStateGetResponse_Status {
+        STATUS_UNSET,
+        BAD_REQUEST,
+        SERVICE_ERROR,
+        NOT_READY,
+        UNKNOWN_BLOCK,
+        NOT_ACTIVE_ENGINE
+    }
+);
+
+impl<S: MessageSender> Service for ZmqService<S> {
     fn send_to(
         &mut self,
         peer: &PeerId,
@@ -103,13 +394,14 @@ impl Service for ZmqService {
         request.set_message_type(message_type.into());
         request.set_receiver_id((*peer).clone());
 
-        let response: ConsensusSendToResponse = self.rpc(
+        let _: ConsensusSendToResponse = self.request(
             &request,
             Message_MessageType::CONSENSUS_SEND_TO_REQUEST,
             Message_MessageType::CONSENSUS_SEND_TO_RESPONSE,
+            "send message",
         )?;
 
-        check_ok!(response, ConsensusSendToResponse_Status::OK)
+        Ok(())
     }
 
     fn broadcast(&mut self, message_type: &str, payload: Vec<u8>) -> Result<(), Error> {
@@ -117,13 +409,14 @@ impl Service for ZmqService {
         request.set_content(payload);
         request.set_message_type(message_type.into());
 
-        let response: ConsensusBroadcastResponse = self.rpc(
+        let _: ConsensusBroadcastResponse = self.request(
             &request,
             Message_MessageType::CONSENSUS_BROADCAST_REQUEST,
             Message_MessageType::CONSENSUS_BROADCAST_RESPONSE,
+            "broadcast message",
         )?;
 
-        check_ok!(response, ConsensusBroadcastResponse_Status::OK)
+        Ok(())
     }
 
     fn initialize_block(&mut self, previous_id: Option<BlockId>) -> Result<(), Error> {
@@ -132,42 +425,26 @@ impl Service for ZmqService {
             request.set_previous_id(previous_id);
         }
 
-        let response: ConsensusInitializeBlockResponse = self.rpc(
+        let _: ConsensusInitializeBlockResponse = self.request(
             &request,
             Message_MessageType::CONSENSUS_INITIALIZE_BLOCK_REQUEST,
             Message_MessageType::CONSENSUS_INITIALIZE_BLOCK_RESPONSE,
+            "initialize block",
         )?;
 
-        if response.get_status() == ConsensusInitializeBlockResponse_Status::INVALID_STATE {
-            return Err(Error::InvalidState(
-                "Cannot initialize block in current state".into(),
-            ));
-        }
-
-        if response.get_status() == ConsensusInitializeBlockResponse_Status::UNKNOWN_BLOCK {
-            return Err(Error::UnknownBlock("Block not found".into()));
-        }
-
-        check_ok!(response, ConsensusInitializeBlockResponse_Status::OK)
+        Ok(())
     }
 
     fn summarize_block(&mut self) -> Result<Vec<u8>, Error> {
         let request = ConsensusSummarizeBlockRequest::new();
 
-        let mut response: ConsensusSummarizeBlockResponse = self.rpc(
+        let mut response: ConsensusSummarizeBlockResponse = self.request(
             &request,
             Message_MessageType::CONSENSUS_SUMMARIZE_BLOCK_REQUEST,
             Message_MessageType::CONSENSUS_SUMMARIZE_BLOCK_RESPONSE,
+            "summarize block",
         )?;
 
-        match response.get_status() {
-            ConsensusSummarizeBlockResponse_Status::INVALID_STATE => Err(Error::InvalidState(
-                "Cannot summarize block in current state".into(),
-            )),
-            ConsensusSummarizeBlockResponse_Status::BLOCK_NOT_READY => Err(Error::BlockNotReady),
-            _ => check_ok!(response, ConsensusSummarizeBlockResponse_Status::OK),
-        }?;
-
         Ok(response.take_summary())
     }
 
@@ -175,39 +452,27 @@ impl Service for ZmqService {
         let mut request = ConsensusFinalizeBlockRequest::new();
         request.set_data(data);
 
-        let mut response: ConsensusFinalizeBlockResponse = self.rpc(
+        let mut response: ConsensusFinalizeBlockResponse = self.request(
             &request,
             Message_MessageType::CONSENSUS_FINALIZE_BLOCK_REQUEST,
             Message_MessageType::CONSENSUS_FINALIZE_BLOCK_RESPONSE,
+            "finalize block",
         )?;
 
-        match response.get_status() {
-            ConsensusFinalizeBlockResponse_Status::INVALID_STATE => Err(Error::InvalidState(
-                "Cannot finalize block in current state".into(),
-            )),
-            ConsensusFinalizeBlockResponse_Status::BLOCK_NOT_READY => Err(Error::BlockNotReady),
-            _ => check_ok!(response, ConsensusFinalizeBlockResponse_Status::OK),
-        }?;
-
         Ok(response.take_block_id())
     }
 
     fn cancel_block(&mut self) -> Result<(), Error> {
         let request = ConsensusCancelBlockRequest::new();
 
-        let response: ConsensusCancelBlockResponse = self.rpc(
+        let _: ConsensusCancelBlockResponse = self.request(
             &request,
             Message_MessageType::CONSENSUS_CANCEL_BLOCK_REQUEST,
             Message_MessageType::CONSENSUS_CANCEL_BLOCK_RESPONSE,
+            "cancel block",
         )?;
 
-        if response.get_status() == ConsensusCancelBlockResponse_Status::INVALID_STATE {
-            Err(Error::InvalidState(
-                "Cannot cancel block in current state".into(),
-            ))
-        } else {
-            check_ok!(response, ConsensusCancelBlockResponse_Status::OK)
-        }
+        Ok(())
     }
 
     fn check_blocks(&mut self, priority: Vec<BlockId>) -> Result<(), Error> {
@@ -216,68 +481,56 @@ impl Service for ZmqService {
             priority.into_iter().map(Vec::from).collect(),
         ));
 
-        let response: ConsensusCheckBlocksResponse = self.rpc(
+        let _: ConsensusCheckBlocksResponse = self.request(
             &request,
             Message_MessageType::CONSENSUS_CHECK_BLOCKS_REQUEST,
             Message_MessageType::CONSENSUS_CHECK_BLOCKS_RESPONSE,
+            "check blocks",
         )?;
 
-        if response.get_status() == ConsensusCheckBlocksResponse_Status::UNKNOWN_BLOCK {
-            Err(Error::UnknownBlock("Block not found".into()))
-        } else {
-            check_ok!(response, ConsensusCheckBlocksResponse_Status::OK)
-        }
+        Ok(())
     }
 
     fn commit_block(&mut self, block_id: BlockId) -> Result<(), Error> {
         let mut request = ConsensusCommitBlockRequest::new();
         request.set_block_id(block_id);
 
-        let response: ConsensusCommitBlockResponse = self.rpc(
+        let _: ConsensusCommitBlockResponse = self.request(
             &request,
             Message_MessageType::CONSENSUS_COMMIT_BLOCK_REQUEST,
             Message_MessageType::CONSENSUS_COMMIT_BLOCK_RESPONSE,
+            "commit block",
         )?;
 
-        if response.get_status() == ConsensusCommitBlockResponse_Status::UNKNOWN_BLOCK {
-            Err(Error::UnknownBlock("Block not found".into()))
-        } else {
-            check_ok!(response, ConsensusCommitBlockResponse_Status::OK)
-        }
+        Ok(())
     }
 
     fn ignore_block(&mut self, block_id: BlockId) -> Result<(), Error> {
         let mut request = ConsensusIgnoreBlockRequest::new();
         request.set_block_id(block_id);
 
-        let response: ConsensusIgnoreBlockResponse = self.rpc(
+        let _: ConsensusIgnoreBlockResponse = self.request(
             &request,
             Message_MessageType::CONSENSUS_IGNORE_BLOCK_REQUEST,
             Message_MessageType::CONSENSUS_IGNORE_BLOCK_RESPONSE,
+            "ignore block",
         )?;
 
-        if response.get_status() == ConsensusIgnoreBlockResponse_Status::UNKNOWN_BLOCK {
-            Err(Error::UnknownBlock("Block not found".into()))
-        } else {
-            check_ok!(response, ConsensusIgnoreBlockResponse_Status::OK)
-        }
+        Ok(())
     }
 
     fn fail_block(&mut self, block_id: BlockId) -> Result<(), Error> {
         let mut request = ConsensusFailBlockRequest::new();
         request.set_block_id(block_id);
 
-        let response: ConsensusFailBlockResponse = self.rpc(
+        let _: ConsensusFailBlockResponse = self.request(
             &request,
             Message_MessageType::CONSENSUS_FAIL_BLOCK_REQUEST,
             Message_MessageType::CONSENSUS_FAIL_BLOCK_RESPONSE,
+            "fail block",
         )?;
 
-        if response.get_status() == ConsensusFailBlockResponse_Status::UNKNOWN_BLOCK {
-            Err(Error::UnknownBlock("Block not found".into()))
-        } else {
-            check_ok!(response, ConsensusFailBlockResponse_Status::OK)
-        }
+        Ok(())
     }
 
     fn get_blocks(&mut self, block_ids: Vec<BlockId>) -> Result<HashMap<BlockId, Block>, Error> {
@@ -286,18 +539,13 @@ impl Service for ZmqService {
             block_ids.into_iter().map(Vec::from).collect(),
         ));
 
-        let mut response: ConsensusBlocksGetResponse = self.rpc(
+        let mut response: ConsensusBlocksGetResponse = self.request(
             &request,
             Message_MessageType::CONSENSUS_BLOCKS_GET_REQUEST,
             Message_MessageType::CONSENSUS_BLOCKS_GET_RESPONSE,
+            "get blocks",
         )?;
 
-        if response.get_status() == ConsensusBlocksGetResponse_Status::UNKNOWN_BLOCK {
-            Err(Error::UnknownBlock("Block not found".into()))
-        } else {
-            check_ok!(response, ConsensusBlocksGetResponse_Status::OK)
-        }?;
-
         Ok(response
             .take_blocks()
             .into_iter()
@@ -308,18 +556,13 @@ impl Service for ZmqService {
     fn get_chain_head(&mut self) -> Result<Block, Error> {
         let request = ConsensusChainHeadGetRequest::new();
 
-        let mut response: ConsensusChainHeadGetResponse = self.rpc(
+        let mut response: ConsensusChainHeadGetResponse = self.request(
             &request,
             Message_MessageType::CONSENSUS_CHAIN_HEAD_GET_REQUEST,
             Message_MessageType::CONSENSUS_CHAIN_HEAD_GET_RESPONSE,
+            "get chain head",
         )?;
 
-        if response.get_status() == ConsensusChainHeadGetResponse_Status::NO_CHAIN_HEAD {
-            Err(Error::NoChainHead)
-        } else {
-            check_ok!(response, ConsensusChainHeadGetResponse_Status::OK)
-        }?;
-
         Ok(Block::from(response.take_block()))
     }
 
@@ -332,18 +575,13 @@ impl Service for ZmqService {
         request.set_block_id(block_id);
         request.set_keys(protobuf::RepeatedField::from_vec(keys));
 
-        let mut response: ConsensusSettingsGetResponse = self.rpc(
+        let mut response: ConsensusSettingsGetResponse = self.request(
             &request,
             Message_MessageType::CONSENSUS_SETTINGS_GET_REQUEST,
             Message_MessageType::CONSENSUS_SETTINGS_GET_RESPONSE,
+            "get settings",
         )?;
 
-        if response.get_status() == ConsensusSettingsGetResponse_Status::UNKNOWN_BLOCK {
-            Err(Error::UnknownBlock("Block not found".into()))
-        } else {
-            check_ok!(response, ConsensusSettingsGetResponse_Status::OK)
-        }?;
-
         Ok(response
             .take_entries()
             .into_iter()
@@ -360,18 +598,13 @@ impl Service for ZmqService {
         request.set_block_id(block_id);
         request.set_addresses(protobuf::RepeatedField::from_vec(addresses));
 
-        let mut response: ConsensusStateGetResponse = self.rpc(
+        let mut response: ConsensusStateGetResponse = self.request(
             &request,
             Message_MessageType::CONSENSUS_STATE_GET_REQUEST,
             Message_MessageType::CONSENSUS_STATE_GET_RESPONSE,
+            "get state",
         )?;
 
-        if response.get_status() == ConsensusStateGetResponse_Status::UNKNOWN_BLOCK {
-            Err(Error::UnknownBlock("Block not found".into()))
-        } else {
-            check_ok!(response, ConsensusStateGetResponse_Status::OK)
-        }?;
-
         Ok(response
             .take_entries()
             .into_iter()
@@ -384,10 +617,15 @@ impl Service for ZmqService {
 mod tests {
     use super::*;
     use crate::messages::validator::Message;
-    use crate::messaging::stream::MessageConnection;
+    use crate::messaging::stream::{MessageConnection, MessageFuture, SendError};
     use crate::messaging::zmq_stream::ZmqMessageConnection;
     use protobuf::Message as ProtobufMessage;
+    use protobuf::ProtobufEnum;
+    use std::collections::VecDeque;
     use std::default::Default;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::channel;
+    use std::sync::Mutex;
     use std::thread;
     use zmq;
 
@@ -597,4 +835,318 @@ mod tests {
 
         svc_thread.join().unwrap();
     }
+
+    /// A MessageSender which answers requests with its responses in order, repeating the last one
+    struct MockSender {
+        responses: Mutex<VecDeque<Message>>,
+        sent: AtomicUsize,
+    }
+
+    impl MessageSender for MockSender {
+        fn send(
+            &self,
+            _destination: Message_MessageType,
+            correlation_id: &str,
+            _contents: &[u8],
+        ) -> Result<MessageFuture, SendError> {
+            self.sent.fetch_add(1, Ordering::SeqCst);
+            let mut responses = self.responses.lock().unwrap();
+            let mut response = if responses.len() > 1 {
+                responses.pop_front().unwrap()
+            } else {
+                responses[0].clone()
+            };
+
+            let (reply_tx, reply_rx) = channel();
+            response.set_correlation_id(correlation_id.into());
+            reply_tx.send(Ok(response)).unwrap();
+            Ok(MessageFuture::new(reply_rx))
+        }
+
+        fn reply(
+            &self,
+            _destination: Message_MessageType,
+            _correlation_id: &str,
+            _contents: &[u8],
+        ) -> Result<(), SendError> {
+            Ok(())
+        }
+
+        fn close(&mut self) {}
+    }
+
+    fn mock_service<R: protobuf::Message>(
+        response: R,
+        response_type: Message_MessageType,
+    ) -> ZmqService<MockSender> {
+        mock_service_with(vec![response], response_type)
+    }
+
+    fn mock_service_with<R: protobuf::Message>(
+        responses: Vec<R>,
+        response_type: Message_MessageType,
+    ) -> ZmqService<MockSender> {
+        let responses = responses
+            .into_iter()
+            .map(|response| {
+                let mut msg = Message::new();
+                msg.set_message_type(response_type);
+                msg.set_content(response.write_to_bytes().unwrap());
+                msg
+            })
+            .collect();
+        ZmqService::new(
+            MockSender {
+                responses: Mutex::new(responses),
+                sent: AtomicUsize::new(0),
+            },
+            Duration::from_secs(1),
+        )
+    }
+
+    /// Check that the result of a request matches the error expected for the response status
+    fn assert_status<T: std::fmt::Debug>(status: &str, result: Result<T, Error>) {
+        match (status, result) {
+            ("OK", Ok(_))
+            | ("STATUS_UNSET", Err(Error::ReceiveError(_)))
+            | ("BAD_REQUEST", Err(Error::BadRequest(_)))
+            | ("SERVICE_ERROR", Err(Error::ServiceError(_)))
+            | ("NOT_READY", Err(Error::NotReady))
+            | ("NOT_ACTIVE_ENGINE", Err(Error::NotActiveEngine))
+            | ("INVALID_STATE", Err(Error::InvalidState(_)))
+            | ("UNKNOWN_BLOCK", Err(Error::UnknownBlock(_)))
+            | ("UNKNOWN_PEER", Err(Error::UnknownPeer(_)))
+            | ("BLOCK_NOT_READY", Err(Error::BlockNotReady))
+            | ("NO_CHAIN_HEAD", Err(Error::NoChainHead)) => (),
+            (status, result) => panic!("Unexpected result for {}: {:?}", status, result),
+        }
+    }
+
+    /// Call a Service method with a response of every status and check the results
+    macro_rules! status_test {
+        ($response:ident, $status:ident, $response_type:expr, |$svc:ident| $call:expr) => {
+            for status in <$status as ProtobufEnum>::values() {
+                let mut response = $response::new();
+                response.set_status(*status);
+                let mut $svc = mock_service(response, $response_type);
+                assert_status(&format!("{:?}", status), $call);
+            }
+        };
+    }
+
+    #[test]
+    fn test_send_to_status() {
+        status_test!(
+            ConsensusSendToResponse,
+            ConsensusSendToResponse_Status,
+            Message_MessageType::CONSENSUS_SEND_TO_RESPONSE,
+            |svc| svc.send_to(&Default::default(), Default::default(), Default::default())
+        );
+    }
+
+    #[test]
+    fn test_broadcast_status() {
+        status_test!(
+            ConsensusBroadcastResponse,
+            ConsensusBroadcastResponse_Status,
+            Message_MessageType::CONSENSUS_BROADCAST_RESPONSE,
+            |svc| svc.broadcast(Default::default(), Default::default())
+        );
+    }
+
+    #[test]
+    fn test_initialize_block_status() {
+        status_test!(
+            ConsensusInitializeBlockResponse,
+            ConsensusInitializeBlockResponse_Status,
+            Message_MessageType::CONSENSUS_INITIALIZE_BLOCK_RESPONSE,
+            |svc| svc.initialize_block(None)
+        );
+    }
+
+    #[test]
+    fn test_summarize_block_status() {
+        status_test!(
+            ConsensusSummarizeBlockResponse,
+            ConsensusSummarizeBlockResponse_Status,
+            Message_MessageType::CONSENSUS_SUMMARIZE_BLOCK_RESPONSE,
+            |svc| svc.summarize_block()
+        );
+    }
+
+    #[test]
+    fn test_finalize_block_status() {
+        status_test!(
+            ConsensusFinalizeBlockResponse,
+            ConsensusFinalizeBlockResponse_Status,
+            Message_MessageType::CONSENSUS_FINALIZE_BLOCK_RESPONSE,
+            |svc| svc.finalize_block(Default::default())
+        );
+    }
+
+    #[test]
+    fn test_cancel_block_status() {
+        status_test!(
+            ConsensusCancelBlockResponse,
+            ConsensusCancelBlockResponse_Status,
+            Message_MessageType::CONSENSUS_CANCEL_BLOCK_RESPONSE,
+            |svc| svc.cancel_block()
+        );
+    }
+
+    #[test]
+    fn test_check_blocks_status() {
+        status_test!(
+            ConsensusCheckBlocksResponse,
+            ConsensusCheckBlocksResponse_Status,
+            Message_MessageType::CONSENSUS_CHECK_BLOCKS_RESPONSE,
+            |svc| svc.check_blocks(Default::default())
+        );
+    }
+
+    #[test]
+    fn test_commit_block_status() {
+        status_test!(
+            ConsensusCommitBlockResponse,
+            ConsensusCommitBlockResponse_Status,
+            Message_MessageType::CONSENSUS_COMMIT_BLOCK_RESPONSE,
+            |svc| svc.commit_block(Default::default())
+        );
+    }
+
+    #[test]
+    fn test_ignore_block_status() {
+        status_test!(
+            ConsensusIgnoreBlockResponse,
+            ConsensusIgnoreBlockResponse_Status,
+            Message_MessageType::CONSENSUS_IGNORE_BLOCK_RESPONSE,
+            |svc| svc.ignore_block(Default::default())
+        );
+    }
+
+    #[test]
+    fn test_fail_block_status() {
+        status_test!(
+            ConsensusFailBlockResponse,
+            ConsensusFailBlockResponse_Status,
+            Message_MessageType::CONSENSUS_FAIL_BLOCK_RESPONSE,
+            |svc| svc.fail_block(Default::default())
+        );
+    }
+
+    #[test]
+    fn test_get_blocks_status() {
+        status_test!(
+            ConsensusBlocksGetResponse,
+            ConsensusBlocksGetResponse_Status,
+            Message_MessageType::CONSENSUS_BLOCKS_GET_RESPONSE,
+            |svc| svc.get_blocks(Default::default())
+        );
+    }
+
+    #[test]
+    fn test_get_chain_head_status() {
+        status_test!(
+            ConsensusChainHeadGetResponse,
+            ConsensusChainHeadGetResponse_Status,
+            Message_MessageType::CONSENSUS_CHAIN_HEAD_GET_RESPONSE,
+            |svc| svc.get_chain_head()
+        );
+    }
+
+    #[test]
+    fn test_get_settings_status() {
+        status_test!(
+            ConsensusSettingsGetResponse,
+            ConsensusSettingsGetResponse_Status,
+            Message_MessageType::CONSENSUS_SETTINGS_GET_RESPONSE,
+            |svc| svc.get_settings(Default::default(), Default::default())
+        );
+    }
+
+    #[test]
+    fn test_get_state_status() {
+        status_test!(
+            ConsensusStateGetResponse,
+            ConsensusStateGetResponse_Status,
+            Message_MessageType::CONSENSUS_STATE_GET_RESPONSE,
+            |svc| svc.get_state(Default::default(), Default::default())
+        );
+    }
+
+    fn summarize_responses(not_ready: usize) -> Vec<ConsensusSummarizeBlockResponse> {
+        let mut responses = vec![];
+        for _ in 0..not_ready {
+            let mut response = ConsensusSummarizeBlockResponse::new();
+            response.set_status(ConsensusSummarizeBlockResponse_Status::NOT_READY);
+            responses.push(response);
+        }
+        let mut response = ConsensusSummarizeBlockResponse::new();
+        response.set_status(ConsensusSummarizeBlockResponse_Status::OK);
+        response.set_summary(b"summary".to_vec());
+        responses.push(response);
+        responses
+    }
+
+    fn fast_retry(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy::new(max_attempts).with_backoff(Backoff::new(
+            Duration::from_millis(1),
+            Duration::from_millis(1),
+        ))
+    }
+
+    #[test]
+    fn test_retry_not_ready() {
+        // Not retried by default
+        let mut svc = mock_service_with(
+            summarize_responses(2),
+            Message_MessageType::CONSENSUS_SUMMARIZE_BLOCK_RESPONSE,
+        );
+        assert_status("NOT_READY", svc.summarize_block());
+        assert_eq!(svc.sender.sent.load(Ordering::SeqCst), 1);
+
+        // Retried until the validator is ready
+        let mut svc = mock_service_with(
+            summarize_responses(2),
+            Message_MessageType::CONSENSUS_SUMMARIZE_BLOCK_RESPONSE,
+        )
+        .with_retry_policy(fast_retry(3));
+        assert_eq!(svc.summarize_block().unwrap(), b"summary");
+        assert_eq!(svc.sender.sent.load(Ordering::SeqCst), 3);
+
+        // Gives up after the maximum number of attempts
+        let mut svc = mock_service_with(
+            summarize_responses(3),
+            Message_MessageType::CONSENSUS_SUMMARIZE_BLOCK_RESPONSE,
+        )
+        .with_retry_policy(fast_retry(3));
+        assert_status("NOT_READY", svc.summarize_block());
+        assert_eq!(svc.sender.sent.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_retry_opt_out() {
+        let mut svc = mock_service_with(
+            summarize_responses(2),
+            Message_MessageType::CONSENSUS_SUMMARIZE_BLOCK_RESPONSE,
+        )
+        .with_retry_policy(
+            fast_retry(3).without_retry(Message_MessageType::CONSENSUS_SUMMARIZE_BLOCK_REQUEST),
+        );
+        assert_status("NOT_READY", svc.summarize_block());
+        assert_eq!(svc.sender.sent.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_retry_other_errors() {
+        let mut response = ConsensusSummarizeBlockResponse::new();
+        response.set_status(ConsensusSummarizeBlockResponse_Status::BLOCK_NOT_READY);
+        let mut svc = mock_service(
+            response,
+            Message_MessageType::CONSENSUS_SUMMARIZE_BLOCK_RESPONSE,
+        )
+        .with_retry_policy(fast_retry(3));
+        assert_status("BLOCK_NOT_READY", svc.summarize_block());
+        assert_eq!(svc.sender.sent.load(Ordering::SeqCst), 1);
+    }
 }
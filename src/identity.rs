@@ -0,0 +1,579 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Identity policies and roles, as used by the validator for transactor permissioning.
+//!
+//! A `Policy` is an ordered list of `PERMIT_KEY` and `DENY_KEY` entries, each naming a public
+//! key or `*` for every key. The first entry matching a key decides whether it is permitted,
+//! and a key which matches no entry is denied. A `Role` names the policy that applies to it.
+//!
+//! Roles are looked up the way the validator does: a role which is not set falls back to its
+//! parent, so `transactor.transaction_signer.intkey` falls back to
+//! `transactor.transaction_signer` and then to `transactor`. When none of them is set, the
+//! policy named `default` applies. A key is permitted if the policy which applies is not set.
+//!
+//! `IdentityView` reads policies and roles from state in a transaction processor, and
+//! `create_policy_transaction` and `create_role_transaction` build transactions for the
+//! identity transaction family, which must be signed by a key listed in the
+//! `sawtooth.identity.allowed_keys` setting.
+
+use std::error;
+use std::fmt;
+
+use protobuf::Message as ProtobufMessage;
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::messages::identity::{
+    IdentityPayload, IdentityPayload_IdentityType, Policy, PolicyList, Policy_Entry,
+    Policy_EntryType, Role, RoleList,
+};
+use crate::messages::transaction::{Transaction, TransactionHeader};
+use crate::processor::handler::TransactionContext;
+use crate::settings::setting_address;
+use crate::signing;
+use crate::signing::Signer;
+
+/// The namespace of the identity transaction family's state
+pub const IDENTITY_NAMESPACE: &str = "00001d";
+pub const IDENTITY_FAMILY_NAME: &str = "sawtooth_identity";
+pub const IDENTITY_FAMILY_VERSION: &str = "1.0";
+
+/// The policy used when neither a role nor any of its parents is set
+pub const DEFAULT_POLICY: &str = "default";
+
+/// The setting listing the keys allowed to change policies and roles
+pub const ALLOWED_KEYS_SETTING: &str = "sawtooth.identity.allowed_keys";
+
+/// The key of policy entries which match every public key
+pub const WILDCARD_KEY: &str = "*";
+
+const POLICY_PREFIX: &str = "00";
+const ROLE_PREFIX: &str = "01";
+const POLICY_ADDRESS_SIZE: usize = 62;
+const MAX_ROLE_PARTS: usize = 4;
+const FIRST_ROLE_PART_SIZE: usize = 14;
+const ROLE_PART_SIZE: usize = 16;
+
+#[derive(Debug)]
+pub enum IdentityError {
+    /// Returned when identity state cannot be read
+    ReadError(String),
+    /// Returned when an identity state entry cannot be decoded
+    InvalidState(String),
+    /// Returned when a policy or role cannot be made into a transaction
+    InvalidPayload(String),
+    /// Returned when a transaction cannot be signed
+    SigningError(signing::Error),
+}
+
+impl error::Error for IdentityError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            IdentityError::SigningError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for IdentityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IdentityError::ReadError(ref s) => write!(f, "ReadError: {}", s),
+            IdentityError::InvalidState(ref s) => write!(f, "InvalidState: {}", s),
+            IdentityError::InvalidPayload(ref s) => write!(f, "InvalidPayload: {}", s),
+            IdentityError::SigningError(ref err) => write!(f, "SigningError: {}", err),
+        }
+    }
+}
+
+impl From<signing::Error> for IdentityError {
+    fn from(err: signing::Error) -> Self {
+        IdentityError::SigningError(err)
+    }
+}
+
+/// Returns the state address of a policy
+pub fn policy_address(name: &str) -> String {
+    let mut address = format!("{}{}", IDENTITY_NAMESPACE, POLICY_PREFIX);
+    address.push_str(&hex::encode(Sha256::digest(name.as_bytes()))[..POLICY_ADDRESS_SIZE]);
+    address
+}
+
+/// Returns the state address of a role
+pub fn role_address(name: &str) -> String {
+    let mut parts = name.splitn(MAX_ROLE_PARTS, '.').collect::<Vec<_>>();
+    parts.resize(MAX_ROLE_PARTS, "");
+
+    let mut address = format!("{}{}", IDENTITY_NAMESPACE, ROLE_PREFIX);
+    for (i, part) in parts.into_iter().enumerate() {
+        let size = if i == 0 {
+            FIRST_ROLE_PART_SIZE
+        } else {
+            ROLE_PART_SIZE
+        };
+        address.push_str(&hex::encode(Sha256::digest(part.as_bytes()))[..size]);
+    }
+    address
+}
+
+/// Build a policy from its entries, in the order they are checked
+pub fn new_policy(name: &str, entries: &[(Policy_EntryType, &str)]) -> Policy {
+    let mut policy = Policy::new();
+    policy.set_name(name.into());
+    for (entry_type, key) in entries {
+        let mut entry = Policy_Entry::new();
+        entry.set_field_type(*entry_type);
+        entry.set_key(key.to_string());
+        policy.mut_entries().push(entry);
+    }
+    policy
+}
+
+/// Returns whether a policy permits a public key.
+///
+/// The first `PERMIT_KEY` or `DENY_KEY` entry naming the key, or `*`, decides; a key which
+/// matches no entry is denied.
+pub fn is_permitted(policy: &Policy, public_key: &str) -> bool {
+    policy
+        .get_entries()
+        .iter()
+        .filter(|entry| entry.get_key() == public_key || entry.get_key() == WILDCARD_KEY)
+        .find_map(|entry| match entry.get_field_type() {
+            Policy_EntryType::PERMIT_KEY => Some(true),
+            Policy_EntryType::DENY_KEY => Some(false),
+            Policy_EntryType::ENTRY_TYPE_UNSET => None,
+        })
+        .unwrap_or(false)
+}
+
+/// Find a policy in the data of a policy state entry, which may hold several policies whose
+/// names share an address
+pub fn find_policy(data: &[u8], name: &str) -> Result<Option<Policy>, IdentityError> {
+    let policies: PolicyList = ProtobufMessage::parse_from_bytes(data)
+        .map_err(|err| IdentityError::InvalidState(format!("Invalid policy list: {}", err)))?;
+    Ok(policies
+        .get_policies()
+        .iter()
+        .find(|policy| policy.get_name() == name)
+        .cloned())
+}
+
+/// Find a role in the data of a role state entry, which may hold several roles whose names
+/// share an address
+pub fn find_role(data: &[u8], name: &str) -> Result<Option<Role>, IdentityError> {
+    let roles: RoleList = ProtobufMessage::parse_from_bytes(data)
+        .map_err(|err| IdentityError::InvalidState(format!("Invalid role list: {}", err)))?;
+    Ok(roles
+        .get_roles()
+        .iter()
+        .find(|role| role.get_name() == name)
+        .cloned())
+}
+
+/// Policies and roles read from state, as a transaction processor does
+pub struct IdentityView<'a> {
+    context: &'a dyn TransactionContext,
+}
+
+impl<'a> IdentityView<'a> {
+    pub fn new(context: &'a dyn TransactionContext) -> Self {
+        IdentityView { context }
+    }
+
+    /// Returns the policy with the given name, if it is set
+    pub fn get_policy(&self, name: &str) -> Result<Option<Policy>, IdentityError> {
+        match self.get_entry(&policy_address(name))? {
+            Some(data) => find_policy(&data, name),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the role with the given name, if it is set
+    pub fn get_role(&self, name: &str) -> Result<Option<Role>, IdentityError> {
+        match self.get_entry(&role_address(name))? {
+            Some(data) => find_role(&data, name),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the name of the policy set for a role, falling back to the role's parents when
+    /// it is not set. Returns `None` if neither the role nor any of its parents is set.
+    pub fn get_policy_name(&self, role_name: &str) -> Result<Option<String>, IdentityError> {
+        let mut name = role_name;
+        loop {
+            if let Some(role) = self.get_role(name)? {
+                return Ok(Some(role.get_policy_name().to_string()));
+            }
+            match name.rfind('.') {
+                Some(end) => name = &name[..end],
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Returns whether a public key is permitted in a role. The `default` policy applies if
+    /// no role is set, and a key is permitted if the policy which applies is not set.
+    pub fn is_permitted(&self, role_name: &str, public_key: &str) -> Result<bool, IdentityError> {
+        let policy_name = self
+            .get_policy_name(role_name)?
+            .unwrap_or_else(|| DEFAULT_POLICY.to_string());
+        match self.get_policy(&policy_name)? {
+            Some(policy) => Ok(is_permitted(&policy, public_key)),
+            None => Ok(true),
+        }
+    }
+
+    fn get_entry(&self, address: &str) -> Result<Option<Vec<u8>>, IdentityError> {
+        self.context
+            .get_state_entry(address)
+            .map_err(|err| IdentityError::ReadError(err.to_string()))
+    }
+}
+
+/// Build a transaction setting a policy, replacing any policy of the same name
+pub fn create_policy_transaction(
+    signer: &Signer,
+    policy: &Policy,
+) -> Result<Transaction, IdentityError> {
+    if policy.get_name().is_empty() {
+        return Err(IdentityError::InvalidPayload(
+            "Policy name must not be empty".into(),
+        ));
+    }
+    if policy.get_entries().is_empty() {
+        return Err(IdentityError::InvalidPayload(format!(
+            "Policy {} must have at least one entry",
+            policy.get_name()
+        )));
+    }
+
+    let address = policy_address(policy.get_name());
+    create_transaction(
+        signer,
+        IdentityPayload_IdentityType::POLICY,
+        policy,
+        vec![setting_address(ALLOWED_KEYS_SETTING), address.clone()],
+        vec![address],
+    )
+}
+
+/// Build a transaction setting a role, replacing any role of the same name. The role's policy
+/// must already be set.
+pub fn create_role_transaction(signer: &Signer, role: &Role) -> Result<Transaction, IdentityError> {
+    if role.get_name().is_empty() || role.get_policy_name().is_empty() {
+        return Err(IdentityError::InvalidPayload(
+            "Role name and policy name must not be empty".into(),
+        ));
+    }
+
+    let address = role_address(role.get_name());
+    create_transaction(
+        signer,
+        IdentityPayload_IdentityType::ROLE,
+        role,
+        vec![
+            setting_address(ALLOWED_KEYS_SETTING),
+            policy_address(role.get_policy_name()),
+            address.clone(),
+        ],
+        vec![address],
+    )
+}
+
+fn create_transaction(
+    signer: &Signer,
+    payload_type: IdentityPayload_IdentityType,
+    data: &dyn ProtobufMessage,
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+) -> Result<Transaction, IdentityError> {
+    let encoding_error = |err: protobuf::ProtobufError| {
+        IdentityError::InvalidPayload(format!("Unable to encode transaction: {}", err))
+    };
+
+    let mut payload = IdentityPayload::new();
+    payload.set_field_type(payload_type);
+    payload.set_data(data.write_to_bytes().map_err(encoding_error)?);
+    let payload_bytes = payload.write_to_bytes().map_err(encoding_error)?;
+
+    let public_key = signer.get_public_key()?.as_hex();
+    let mut header = TransactionHeader::new();
+    header.set_family_name(IDENTITY_FAMILY_NAME.into());
+    header.set_family_version(IDENTITY_FAMILY_VERSION.into());
+    header.set_inputs(protobuf::RepeatedField::from_vec(inputs));
+    header.set_outputs(protobuf::RepeatedField::from_vec(outputs));
+    header.set_nonce(format!("{:016x}", rand::random::<u64>()));
+    header.set_payload_sha512(hex::encode(Sha512::digest(&payload_bytes)));
+    header.set_signer_public_key(public_key.clone());
+    header.set_batcher_public_key(public_key);
+    let header_bytes = header.write_to_bytes().map_err(encoding_error)?;
+
+    let mut transaction = Transaction::new();
+    transaction.set_header_signature(signer.sign(&header_bytes)?);
+    transaction.set_header(header_bytes);
+    transaction.set_payload(payload_bytes);
+    Ok(transaction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    use crate::processor::handler::ContextError;
+    use crate::signing::create_context;
+    use crate::signing::secp256k1::Secp256k1PrivateKey;
+    use crate::signing::test_keys::{KEY1_PRIV_HEX, KEY1_PUB_HEX, KEY2_PUB_HEX};
+
+    #[test]
+    fn identity_addresses() {
+        assert_eq!(
+            policy_address("policy1"),
+            "00001d009e83663681c9d045295744e22adc913c85562348a6e8ad513c8ab737fab486"
+        );
+        assert_eq!(
+            role_address("transactor.transaction_signer"),
+            "00001d01d331cdbbea7fe34a4c8c38892ec60be3b0c44298fc1c14e3b0c44298fc1c14"
+        );
+        assert_eq!(role_address("a.b.c.d.e").len(), 70);
+    }
+
+    #[test]
+    fn first_match_wins() {
+        let policy = new_policy(
+            "policy1",
+            &[
+                (Policy_EntryType::DENY_KEY, KEY2_PUB_HEX),
+                (Policy_EntryType::PERMIT_KEY, WILDCARD_KEY),
+                (Policy_EntryType::DENY_KEY, KEY1_PUB_HEX),
+            ],
+        );
+        assert!(is_permitted(&policy, KEY1_PUB_HEX));
+        assert!(!is_permitted(&policy, KEY2_PUB_HEX));
+
+        let policy = new_policy("policy2", &[(Policy_EntryType::PERMIT_KEY, KEY1_PUB_HEX)]);
+        assert!(is_permitted(&policy, KEY1_PUB_HEX));
+        assert!(!is_permitted(&policy, KEY2_PUB_HEX));
+
+        assert!(!is_permitted(&new_policy("empty", &[]), KEY1_PUB_HEX));
+    }
+
+    /// A context holding the identity state
+    struct IdentityState {
+        state: RefCell<HashMap<String, Vec<u8>>>,
+    }
+
+    impl IdentityState {
+        fn new() -> Self {
+            IdentityState {
+                state: RefCell::new(HashMap::new()),
+            }
+        }
+
+        fn set_policy(&self, policy: Policy) {
+            let mut policies = PolicyList::new();
+            policies.mut_policies().push(policy.clone());
+            self.state.borrow_mut().insert(
+                policy_address(policy.get_name()),
+                policies.write_to_bytes().unwrap(),
+            );
+        }
+
+        fn set_role(&self, name: &str, policy_name: &str) {
+            let mut role = Role::new();
+            role.set_name(name.into());
+            role.set_policy_name(policy_name.into());
+            let mut roles = RoleList::new();
+            roles.mut_roles().push(role);
+            self.state
+                .borrow_mut()
+                .insert(role_address(name), roles.write_to_bytes().unwrap());
+        }
+    }
+
+    impl TransactionContext for IdentityState {
+        fn get_state_entries(
+            &self,
+            addresses: &[String],
+        ) -> Result<Vec<(String, Vec<u8>)>, ContextError> {
+            let state = self.state.borrow();
+            Ok(addresses
+                .iter()
+                .filter_map(|address| {
+                    state
+                        .get(address)
+                        .map(|data| (address.clone(), data.clone()))
+                })
+                .collect())
+        }
+
+        fn set_state_entries(&self, entries: Vec<(String, Vec<u8>)>) -> Result<(), ContextError> {
+            self.state.borrow_mut().extend(entries);
+            Ok(())
+        }
+
+        fn delete_state_entries(&self, addresses: &[String]) -> Result<Vec<String>, ContextError> {
+            let mut state = self.state.borrow_mut();
+            Ok(addresses
+                .iter()
+                .filter(|address| state.remove(*address).is_some())
+                .cloned()
+                .collect())
+        }
+
+        fn add_receipt_data(&self, _data: &[u8]) -> Result<(), ContextError> {
+            Ok(())
+        }
+
+        fn add_event(
+            &self,
+            _event_type: String,
+            _attributes: Vec<(String, String)>,
+            _data: &[u8],
+        ) -> Result<(), ContextError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn role_fallback() {
+        let context = IdentityState::new();
+        let view = IdentityView::new(&context);
+
+        // Nothing is set, so every key is permitted
+        assert_eq!(view.get_policy_name("transactor").unwrap(), None);
+        assert!(view.is_permitted("transactor", KEY2_PUB_HEX).unwrap());
+
+        // With no roles set, the default policy applies
+        context.set_policy(new_policy(
+            DEFAULT_POLICY,
+            &[(Policy_EntryType::PERMIT_KEY, KEY1_PUB_HEX)],
+        ));
+        assert_eq!(view.get_policy_name("transactor").unwrap(), None);
+        assert!(view.is_permitted("transactor", KEY1_PUB_HEX).unwrap());
+        assert!(!view.is_permitted("transactor", KEY2_PUB_HEX).unwrap());
+
+        context.set_policy(new_policy(
+            "only_key1",
+            &[(Policy_EntryType::PERMIT_KEY, KEY1_PUB_HEX)],
+        ));
+        context.set_policy(new_policy(
+            "everyone",
+            &[(Policy_EntryType::PERMIT_KEY, WILDCARD_KEY)],
+        ));
+        context.set_role("transactor.transaction_signer", "only_key1");
+
+        assert_eq!(
+            view.get_policy_name("transactor.transaction_signer.intkey")
+                .unwrap(),
+            Some("only_key1".to_string())
+        );
+        assert!(view
+            .is_permitted("transactor.transaction_signer.intkey", KEY1_PUB_HEX)
+            .unwrap());
+        assert!(!view
+            .is_permitted("transactor.transaction_signer.intkey", KEY2_PUB_HEX)
+            .unwrap());
+
+        // Neither transactor.batch_signer nor transactor is set
+        assert_eq!(
+            view.get_policy_name("transactor.batch_signer").unwrap(),
+            None
+        );
+        assert!(!view
+            .is_permitted("transactor.batch_signer", KEY2_PUB_HEX)
+            .unwrap());
+
+        context.set_role("transactor", "everyone");
+        assert_eq!(
+            view.get_policy_name("transactor.batch_signer").unwrap(),
+            Some("everyone".to_string())
+        );
+        assert!(view
+            .is_permitted("transactor.batch_signer", KEY2_PUB_HEX)
+            .unwrap());
+
+        // A role whose policy is not set permits every key
+        context.set_role("network", "missing");
+        assert!(view.is_permitted("network", KEY2_PUB_HEX).unwrap());
+    }
+
+    #[test]
+    fn policy_transaction() {
+        let context = create_context("secp256k1").unwrap();
+        let private_key = Secp256k1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap();
+        let signer = Signer::new(&*context, &private_key);
+
+        let policy = new_policy("policy1", &[(Policy_EntryType::PERMIT_KEY, KEY2_PUB_HEX)]);
+        let transaction = create_policy_transaction(&signer, &policy).unwrap();
+
+        let header: TransactionHeader =
+            ProtobufMessage::parse_from_bytes(transaction.get_header()).unwrap();
+        assert_eq!(header.get_family_name(), IDENTITY_FAMILY_NAME);
+        assert_eq!(header.get_family_version(), IDENTITY_FAMILY_VERSION);
+        assert_eq!(header.get_signer_public_key(), KEY1_PUB_HEX);
+        assert_eq!(header.get_outputs(), &[policy_address("policy1")]);
+        assert!(header
+            .get_inputs()
+            .contains(&setting_address(ALLOWED_KEYS_SETTING)));
+        assert_eq!(
+            header.get_payload_sha512(),
+            hex::encode(Sha512::digest(transaction.get_payload()))
+        );
+        assert!(context
+            .verify(
+                transaction.get_header_signature(),
+                transaction.get_header(),
+                &*signer.get_public_key().unwrap(),
+            )
+            .unwrap());
+
+        let payload: IdentityPayload =
+            ProtobufMessage::parse_from_bytes(transaction.get_payload()).unwrap();
+        assert_eq!(
+            payload.get_field_type(),
+            IdentityPayload_IdentityType::POLICY
+        );
+        let decoded: Policy = ProtobufMessage::parse_from_bytes(payload.get_data()).unwrap();
+        assert_eq!(decoded, policy);
+
+        assert!(create_policy_transaction(&signer, &new_policy("empty", &[])).is_err());
+    }
+
+    #[test]
+    fn role_transaction() {
+        let context = create_context("secp256k1").unwrap();
+        let private_key = Secp256k1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap();
+        let signer = Signer::new(&*context, &private_key);
+
+        let mut role = Role::new();
+        role.set_name("transactor".into());
+        role.set_policy_name("policy1".into());
+        let transaction = create_role_transaction(&signer, &role).unwrap();
+
+        let header: TransactionHeader =
+            ProtobufMessage::parse_from_bytes(transaction.get_header()).unwrap();
+        assert_eq!(header.get_outputs(), &[role_address("transactor")]);
+        assert!(header.get_inputs().contains(&policy_address("policy1")));
+
+        let payload: IdentityPayload =
+            ProtobufMessage::parse_from_bytes(transaction.get_payload()).unwrap();
+        assert_eq!(payload.get_field_type(), IdentityPayload_IdentityType::ROLE);
+    }
+}
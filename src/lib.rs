@@ -19,7 +19,9 @@
 extern crate log;
 
 pub mod consensus;
+pub mod identity;
 pub mod messages;
 pub mod messaging;
 pub mod processor;
+pub mod settings;
 pub mod signing;
@@ -0,0 +1,137 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use std::time::Duration;
+
+const DEFAULT_INITIAL_DELAY: Duration = Duration::from_millis(100);
+const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(3);
+
+/// An ordered list of validator endpoints.
+///
+/// The first endpoint is used initially; each failover moves to the next endpoint in the list,
+/// wrapping around to the first one after the last.
+#[derive(Clone, Debug)]
+pub struct Endpoints {
+    endpoints: Vec<String>,
+    active: usize,
+}
+
+impl Endpoints {
+    /// Create a new list of endpoints
+    ///
+    /// # Panics
+    ///
+    /// Panics if `endpoints` is empty.
+    pub fn new<T: AsRef<str>>(endpoints: &[T]) -> Self {
+        assert!(
+            !endpoints.is_empty(),
+            "At least one validator endpoint is required"
+        );
+        Endpoints {
+            endpoints: endpoints.iter().map(|e| e.as_ref().to_string()).collect(),
+            active: 0,
+        }
+    }
+
+    /// Returns the endpoint that is currently in use
+    pub fn active(&self) -> &str {
+        &self.endpoints[self.active]
+    }
+
+    /// Switch to the next endpoint in the list and return it
+    pub fn failover(&mut self) -> &str {
+        self.active = (self.active + 1) % self.endpoints.len();
+        self.active()
+    }
+
+    /// Returns the number of endpoints in the list
+    pub fn len(&self) -> usize {
+        self.endpoints.len()
+    }
+
+    /// Returns true if the list contains no endpoints; this is never the case
+    pub fn is_empty(&self) -> bool {
+        self.endpoints.is_empty()
+    }
+}
+
+/// Exponential backoff between reconnection attempts.
+///
+/// The delay starts at the initial delay and doubles with each attempt, up to the maximum delay.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Backoff {
+            initial,
+            max,
+            next: initial,
+        }
+    }
+
+    /// Returns the delay to wait before the next attempt and increases the following one
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = std::cmp::min(self.next * 2, self.max);
+        delay
+    }
+
+    /// Start over from the initial delay, typically after a successful connection
+    pub fn reset(&mut self) {
+        self.next = self.initial;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::new(DEFAULT_INITIAL_DELAY, DEFAULT_MAX_DELAY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoints_failover() {
+        let mut endpoints = Endpoints::new(&["tcp://a:4004", "tcp://b:4004", "tcp://c:4004"]);
+        assert_eq!(endpoints.len(), 3);
+        assert_eq!(endpoints.active(), "tcp://a:4004");
+        assert_eq!(endpoints.failover(), "tcp://b:4004");
+        assert_eq!(endpoints.failover(), "tcp://c:4004");
+        assert_eq!(endpoints.failover(), "tcp://a:4004");
+        assert_eq!(endpoints.active(), "tcp://a:4004");
+    }
+
+    #[test]
+    fn backoff_delays() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(500));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(200));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(400));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(500));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(500));
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+    }
+}
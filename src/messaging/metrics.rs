@@ -0,0 +1,498 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Metrics collected on connections to the validator.
+//!
+//! A `ConnectionMetrics` is shared by a `ZmqMessageConnection` and the senders it creates; the
+//! same instance may be given to successive connections so that the metrics survive reconnects.
+//! With the `prometheus-exporter` feature, a `PrometheusExporter` serves the metrics in the
+//! Prometheus text format.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::messages::validator::Message_MessageType;
+
+/// Upper bounds, in seconds, of the reply latency histogram buckets
+pub const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// A histogram of durations, bucketed by `LATENCY_BUCKETS`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    // The number of observations in each bucket, and one more for those above the last bound
+    counts: Vec<u64>,
+    sum: Duration,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: vec![0; LATENCY_BUCKETS.len() + 1],
+            sum: Duration::default(),
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += duration;
+    }
+
+    /// Returns the number of observations
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Returns the sum of all observations
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    /// Returns the cumulative number of observations less than or equal to each bucket bound
+    pub fn buckets(&self) -> Vec<(f64, u64)> {
+        LATENCY_BUCKETS
+            .iter()
+            .zip(&self.counts)
+            .scan(0, |total, (bound, count)| {
+                *total += count;
+                Some((*bound, *total))
+            })
+            .collect()
+    }
+}
+
+/// A point in time view of a connection's metrics
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    /// Messages written to the socket, by type
+    pub messages_sent: HashMap<Message_MessageType, u64>,
+    /// Messages read from the socket, by type
+    pub messages_received: HashMap<Message_MessageType, u64>,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Time between sending a request and receiving its reply, by request type
+    pub reply_latency: HashMap<Message_MessageType, Histogram>,
+    /// Sent messages still waiting for a reply
+    pub pending_replies: usize,
+    /// Connections created after the first one
+    pub reconnects: u64,
+}
+
+impl MetricsSnapshot {
+    /// Render the metrics in the Prometheus text exposition format
+    pub fn to_prometheus_text(&self) -> String {
+        let mut text = String::new();
+
+        write_counters(
+            &mut text,
+            "sawtooth_messages_sent_total",
+            "Messages sent to the validator",
+            &self.messages_sent,
+        );
+        write_counters(
+            &mut text,
+            "sawtooth_messages_received_total",
+            "Messages received from the validator",
+            &self.messages_received,
+        );
+        write_metric(
+            &mut text,
+            "sawtooth_bytes_sent_total",
+            "counter",
+            "Bytes sent to the validator",
+            self.bytes_sent,
+        );
+        write_metric(
+            &mut text,
+            "sawtooth_bytes_received_total",
+            "counter",
+            "Bytes received from the validator",
+            self.bytes_received,
+        );
+
+        let name = "sawtooth_reply_latency_seconds";
+        writeln!(
+            text,
+            "# HELP {} Time to receive a reply, by request type",
+            name
+        )
+        .unwrap();
+        writeln!(text, "# TYPE {} histogram", name).unwrap();
+        for (message_type, histogram) in sorted(&self.reply_latency) {
+            for (bound, count) in histogram.buckets() {
+                writeln!(
+                    text,
+                    "{}_bucket{{type=\"{}\",le=\"{}\"}} {}",
+                    name, message_type, bound, count
+                )
+                .unwrap();
+            }
+            writeln!(
+                text,
+                "{}_bucket{{type=\"{}\",le=\"+Inf\"}} {}",
+                name,
+                message_type,
+                histogram.count()
+            )
+            .unwrap();
+            writeln!(
+                text,
+                "{}_sum{{type=\"{}\"}} {}",
+                name,
+                message_type,
+                histogram.sum().as_secs_f64()
+            )
+            .unwrap();
+            writeln!(
+                text,
+                "{}_count{{type=\"{}\"}} {}",
+                name,
+                message_type,
+                histogram.count()
+            )
+            .unwrap();
+        }
+
+        write_metric(
+            &mut text,
+            "sawtooth_pending_replies",
+            "gauge",
+            "Sent messages waiting for a reply",
+            self.pending_replies,
+        );
+        write_metric(
+            &mut text,
+            "sawtooth_reconnects_total",
+            "counter",
+            "Reconnections to the validator",
+            self.reconnects,
+        );
+
+        text
+    }
+}
+
+/// Sort a map by the name of its message types, so that the output is stable
+fn sorted<V>(map: &HashMap<Message_MessageType, V>) -> Vec<(String, &V)> {
+    let mut entries = map
+        .iter()
+        .map(|(message_type, value)| (format!("{:?}", message_type), value))
+        .collect::<Vec<_>>();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    entries
+}
+
+fn write_counters(
+    text: &mut String,
+    name: &str,
+    help: &str,
+    counters: &HashMap<Message_MessageType, u64>,
+) {
+    writeln!(text, "# HELP {} {}", name, help).unwrap();
+    writeln!(text, "# TYPE {} counter", name).unwrap();
+    for (message_type, count) in sorted(counters) {
+        writeln!(text, "{}{{type=\"{}\"}} {}", name, message_type, count).unwrap();
+    }
+}
+
+fn write_metric<V: std::fmt::Display>(
+    text: &mut String,
+    name: &str,
+    metric_type: &str,
+    help: &str,
+    value: V,
+) {
+    writeln!(text, "# HELP {} {}", name, help).unwrap();
+    writeln!(text, "# TYPE {} {}", name, metric_type).unwrap();
+    writeln!(text, "{} {}", name, value).unwrap();
+}
+
+#[derive(Default)]
+struct MetricsState {
+    snapshot: MetricsSnapshot,
+    connections: u64,
+}
+
+/// The metrics of one or more successive connections to the validator
+#[derive(Clone, Default)]
+pub struct ConnectionMetrics {
+    state: Arc<Mutex<MetricsState>>,
+}
+
+impl ConnectionMetrics {
+    pub fn new() -> Self {
+        ConnectionMetrics::default()
+    }
+
+    /// Returns the current value of the metrics
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.state.lock().unwrap().snapshot.clone()
+    }
+
+    pub(crate) fn record_connection(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.connections += 1;
+        state.snapshot.reconnects = state.connections - 1;
+    }
+
+    pub(crate) fn record_sent(&self, message_type: Message_MessageType, bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        *state
+            .snapshot
+            .messages_sent
+            .entry(message_type)
+            .or_default() += 1;
+        state.snapshot.bytes_sent += bytes as u64;
+    }
+
+    pub(crate) fn record_received(&self, message_type: Message_MessageType, bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        *state
+            .snapshot
+            .messages_received
+            .entry(message_type)
+            .or_default() += 1;
+        state.snapshot.bytes_received += bytes as u64;
+    }
+
+    pub(crate) fn record_reply_latency(
+        &self,
+        message_type: Message_MessageType,
+        latency: Duration,
+    ) {
+        self.state
+            .lock()
+            .unwrap()
+            .snapshot
+            .reply_latency
+            .entry(message_type)
+            .or_default()
+            .observe(latency);
+    }
+
+    pub(crate) fn set_pending_replies(&self, pending_replies: usize) {
+        self.state.lock().unwrap().snapshot.pending_replies = pending_replies;
+    }
+}
+
+#[cfg(feature = "prometheus-exporter")]
+pub use self::exporter::PrometheusExporter;
+
+#[cfg(feature = "prometheus-exporter")]
+mod exporter {
+    use std::io::{self, Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    use super::ConnectionMetrics;
+
+    const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+    const MAX_REQUEST_SIZE: usize = 8192;
+
+    /// Serves connection metrics over HTTP at `/metrics`, in the Prometheus text format.
+    ///
+    /// The exporter runs on its own thread until it is stopped or dropped.
+    pub struct PrometheusExporter {
+        local_addr: SocketAddr,
+        shutdown: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl PrometheusExporter {
+        /// Start serving `metrics` on `addr`, such as `"127.0.0.1:9100"`
+        pub fn start<A: ToSocketAddrs>(addr: A, metrics: ConnectionMetrics) -> io::Result<Self> {
+            let listener = TcpListener::bind(addr)?;
+            listener.set_nonblocking(true)?;
+            let local_addr = listener.local_addr()?;
+
+            let shutdown = Arc::new(AtomicBool::new(false));
+            let thread_shutdown = shutdown.clone();
+            let thread = thread::Builder::new()
+                .name("PrometheusExporter".into())
+                .spawn(move || {
+                    while !thread_shutdown.load(Ordering::SeqCst) {
+                        match listener.accept() {
+                            Ok((stream, _)) => {
+                                if let Err(err) = serve(stream, &metrics) {
+                                    debug!("Unable to serve metrics: {}", err);
+                                }
+                            }
+                            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                                thread::sleep(ACCEPT_INTERVAL)
+                            }
+                            Err(err) => warn!("Unable to accept metrics connection: {}", err),
+                        }
+                    }
+                })?;
+
+            Ok(PrometheusExporter {
+                local_addr,
+                shutdown,
+                thread: Some(thread),
+            })
+        }
+
+        /// Returns the address the exporter is listening on
+        pub fn local_addr(&self) -> SocketAddr {
+            self.local_addr
+        }
+
+        /// Stop serving metrics
+        pub fn stop(self) {}
+    }
+
+    impl Drop for PrometheusExporter {
+        fn drop(&mut self) {
+            self.shutdown.store(true, Ordering::SeqCst);
+            if let Some(thread) = self.thread.take() {
+                thread.join().ok();
+            }
+        }
+    }
+
+    fn serve(mut stream: TcpStream, metrics: &ConnectionMetrics) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+
+        // Read the request head; the body, if any, is ignored
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+            let read = stream.read(&mut buffer)?;
+            if read == 0 || request.len() + read > MAX_REQUEST_SIZE {
+                break;
+            }
+            request.extend_from_slice(&buffer[..read]);
+        }
+
+        let request_line = String::from_utf8_lossy(&request);
+        let mut parts = request_line.split_whitespace();
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", metrics.snapshot().to_prometheus_text()),
+            (Some("GET"), _) => ("404 Not Found", String::new()),
+            _ => ("405 Method Not Allowed", String::new()),
+        };
+
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )?;
+        stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets() {
+        let mut histogram = Histogram::default();
+        histogram.observe(Duration::from_micros(500));
+        histogram.observe(Duration::from_millis(5));
+        histogram.observe(Duration::from_secs(10));
+
+        assert_eq!(histogram.count(), 3);
+        assert_eq!(histogram.sum(), Duration::from_micros(10_005_500));
+
+        let buckets = histogram.buckets();
+        assert_eq!(buckets[0], (0.001, 1));
+        assert_eq!(buckets[2], (0.005, 2));
+        assert_eq!(buckets[LATENCY_BUCKETS.len() - 1], (5.0, 2));
+    }
+
+    #[test]
+    fn metrics_snapshot() {
+        let metrics = ConnectionMetrics::new();
+        metrics.record_connection();
+        metrics.record_sent(Message_MessageType::PING_REQUEST, 10);
+        metrics.record_sent(Message_MessageType::PING_REQUEST, 5);
+        metrics.record_received(Message_MessageType::PING_RESPONSE, 7);
+        metrics.record_reply_latency(Message_MessageType::PING_REQUEST, Duration::from_millis(20));
+        metrics.set_pending_replies(2);
+        metrics.record_connection();
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(
+            snapshot.messages_sent[&Message_MessageType::PING_REQUEST],
+            2
+        );
+        assert_eq!(
+            snapshot.messages_received[&Message_MessageType::PING_RESPONSE],
+            1
+        );
+        assert_eq!(snapshot.bytes_sent, 15);
+        assert_eq!(snapshot.bytes_received, 7);
+        assert_eq!(
+            snapshot.reply_latency[&Message_MessageType::PING_REQUEST].count(),
+            1
+        );
+        assert_eq!(snapshot.pending_replies, 2);
+        assert_eq!(snapshot.reconnects, 1);
+
+        let text = snapshot.to_prometheus_text();
+        assert!(text.contains("sawtooth_messages_sent_total{type=\"PING_REQUEST\"} 2\n"));
+        assert!(text.contains("sawtooth_bytes_received_total 7\n"));
+        assert!(text.contains(
+            "sawtooth_reply_latency_seconds_bucket{type=\"PING_REQUEST\",le=\"0.025\"} 1\n"
+        ));
+        assert!(text.contains("sawtooth_reply_latency_seconds_count{type=\"PING_REQUEST\"} 1\n"));
+        assert!(text.contains("sawtooth_pending_replies 2\n"));
+        assert!(text.contains("sawtooth_reconnects_total 1\n"));
+    }
+
+    #[cfg(feature = "prometheus-exporter")]
+    #[test]
+    fn prometheus_exporter() {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+
+        let metrics = ConnectionMetrics::new();
+        metrics.record_sent(Message_MessageType::PING_REQUEST, 10);
+        let exporter = PrometheusExporter::start("127.0.0.1:0", metrics).unwrap();
+
+        let mut stream = TcpStream::connect(exporter.local_addr()).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("sawtooth_bytes_sent_total 10\n"));
+
+        exporter.stop();
+    }
+}
@@ -14,5 +14,9 @@
  * limitations under the License.
  * ------------------------------------------------------------------------------
  */
+pub mod failover;
+pub mod metrics;
+pub mod recording;
+pub mod replay;
 pub mod stream;
 pub mod zmq_stream;
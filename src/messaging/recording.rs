@@ -0,0 +1,345 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Recording of the messages exchanged with a validator.
+//!
+//! A recording file starts with the magic bytes `SAWREC` and a one byte format version, followed
+//! by length-delimited records. Each record is a big-endian `u32` length, followed by that many
+//! bytes: a one byte direction (`0` for inbound, `1` for outbound), the big-endian `u64` number of
+//! microseconds since the UNIX epoch at which the message was recorded, and the protobuf-encoded
+//! `Message`.
+//!
+//! A `ZmqMessageConnection` given a `Recorder` with `with_recorder` records each message on its
+//! socket's thread, as it is sent to or received from the validator, so that the recording holds
+//! every message in the order it crossed the connection, including replies nobody waits for.
+//! `TransactionProcessor::set_recorder` and `ZmqDriver::set_recorder` give a recorder to every
+//! connection they make, so that a processor or engine is recorded across reconnects and
+//! failovers.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use protobuf::Message as ProtobufMessage;
+
+use crate::messages::validator::Message;
+
+const MAGIC: &[u8] = b"SAWREC";
+const FORMAT_VERSION: u8 = 1;
+/// Size of the direction and timestamp preceding the message in a record
+const RECORD_PREFIX_SIZE: usize = 9;
+
+/// Errors that occur while reading or writing a recording.
+#[derive(Debug)]
+pub enum RecordingError {
+    /// Returned when the underlying file could not be read or written
+    IoError(io::Error),
+    /// Returned when a recorded message could not be encoded or decoded
+    EncodingError(protobuf::ProtobufError),
+    /// Returned when the contents are not a valid recording
+    InvalidFormat(String),
+}
+
+impl std::error::Error for RecordingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RecordingError::IoError(err) => Some(err),
+            RecordingError::EncodingError(err) => Some(err),
+            RecordingError::InvalidFormat(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            RecordingError::IoError(ref err) => write!(f, "IoError: {}", err),
+            RecordingError::EncodingError(ref err) => write!(f, "EncodingError: {}", err),
+            RecordingError::InvalidFormat(ref s) => write!(f, "InvalidFormat: {}", s),
+        }
+    }
+}
+
+impl From<io::Error> for RecordingError {
+    fn from(err: io::Error) -> Self {
+        RecordingError::IoError(err)
+    }
+}
+
+impl From<protobuf::ProtobufError> for RecordingError {
+    fn from(err: protobuf::ProtobufError) -> Self {
+        RecordingError::EncodingError(err)
+    }
+}
+
+/// Whether a message was received from or sent to the validator
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// A message captured on a connection
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub direction: Direction,
+    pub timestamp: SystemTime,
+    pub message: Message,
+}
+
+impl Record {
+    /// Create a record of the given message, timestamped now
+    pub fn new(direction: Direction, message: Message) -> Self {
+        Record {
+            direction,
+            timestamp: SystemTime::now(),
+            message,
+        }
+    }
+}
+
+/// Writes records in the recording file format
+pub struct RecordWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> RecordWriter<W> {
+    /// Create a new writer, writing the file header to `writer`
+    pub fn new(mut writer: W) -> Result<Self, RecordingError> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[FORMAT_VERSION])?;
+        Ok(RecordWriter { writer })
+    }
+
+    /// Write a single record
+    pub fn write(&mut self, record: &Record) -> Result<(), RecordingError> {
+        let message_bytes = record.message.write_to_bytes()?;
+        let micros = record
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_micros() as u64)
+            .unwrap_or(0);
+
+        let length = (RECORD_PREFIX_SIZE + message_bytes.len()) as u32;
+        self.writer.write_all(&length.to_be_bytes())?;
+        self.writer.write_all(&[match record.direction {
+            Direction::Inbound => 0,
+            Direction::Outbound => 1,
+        }])?;
+        self.writer.write_all(&micros.to_be_bytes())?;
+        self.writer.write_all(&message_bytes)?;
+        Ok(())
+    }
+
+    /// Flush the underlying writer
+    pub fn flush(&mut self) -> Result<(), RecordingError> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Reads records in the recording file format
+pub struct RecordReader<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> RecordReader<R> {
+    /// Create a new reader, checking the file header read from `reader`
+    pub fn new(mut reader: R) -> Result<Self, RecordingError> {
+        let mut header = [0u8; 7];
+        reader.read_exact(&mut header)?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(RecordingError::InvalidFormat(
+                "Not a message recording".into(),
+            ));
+        }
+        if header[MAGIC.len()] != FORMAT_VERSION {
+            return Err(RecordingError::InvalidFormat(format!(
+                "Unsupported recording version {}",
+                header[MAGIC.len()]
+            )));
+        }
+        Ok(RecordReader { reader })
+    }
+
+    /// Read the next record, returning `None` at the end of the recording
+    pub fn read(&mut self) -> Result<Option<Record>, RecordingError> {
+        let mut length = [0u8; 4];
+        match self.reader.read_exact(&mut length) {
+            Ok(()) => (),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        let length = u32::from_be_bytes(length) as usize;
+        if length < RECORD_PREFIX_SIZE {
+            return Err(RecordingError::InvalidFormat(format!(
+                "Record length {} is too short",
+                length
+            )));
+        }
+
+        let mut bytes = vec![0u8; length];
+        self.reader.read_exact(&mut bytes)?;
+
+        let direction = match bytes[0] {
+            0 => Direction::Inbound,
+            1 => Direction::Outbound,
+            other => {
+                return Err(RecordingError::InvalidFormat(format!(
+                    "Invalid direction {}",
+                    other
+                )))
+            }
+        };
+        let mut micros = [0u8; 8];
+        micros.copy_from_slice(&bytes[1..RECORD_PREFIX_SIZE]);
+        let timestamp = UNIX_EPOCH + Duration::from_micros(u64::from_be_bytes(micros));
+        let message = ProtobufMessage::parse_from_bytes(&bytes[RECORD_PREFIX_SIZE..])?;
+
+        Ok(Some(Record {
+            direction,
+            timestamp,
+            message,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for RecordReader<R> {
+    type Item = Result<Record, RecordingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
+
+/// Read all of the records in the recording file at `path`
+pub fn read_recording<P: AsRef<Path>>(path: P) -> Result<Vec<Record>, RecordingError> {
+    RecordReader::new(BufReader::new(File::open(path)?))?.collect()
+}
+
+/// A shareable handle for writing records as messages cross a connection
+#[derive(Clone)]
+pub struct Recorder {
+    writer: Arc<Mutex<RecordWriter<Box<dyn Write + Send>>>>,
+}
+
+impl Recorder {
+    /// Create a recorder writing to `writer`
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Result<Self, RecordingError> {
+        let writer: Box<dyn Write + Send> = Box::new(writer);
+        Ok(Recorder {
+            writer: Arc::new(Mutex::new(RecordWriter::new(writer)?)),
+        })
+    }
+
+    /// Create a recorder writing to a new file at `path`
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, RecordingError> {
+        Recorder::new(BufWriter::new(File::create(path)?))
+    }
+
+    /// Record a message; failures are logged, as recording must not interfere with the connection
+    pub fn record(&self, direction: Direction, message: &Message) {
+        let record = Record::new(direction, message.clone());
+        let mut writer = match self.writer.lock() {
+            Ok(writer) => writer,
+            Err(_) => {
+                error!("Recorder lock poisoned; message not recorded");
+                return;
+            }
+        };
+        if let Err(err) = writer.write(&record).and_then(|_| writer.flush()) {
+            error!("Unable to record message: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use crate::messages::validator::Message_MessageType;
+
+    fn make_message(
+        message_type: Message_MessageType,
+        correlation_id: &str,
+        contents: &[u8],
+    ) -> Message {
+        let mut msg = Message::new();
+        msg.set_message_type(message_type);
+        msg.set_correlation_id(String::from(correlation_id));
+        msg.set_content(Vec::from(contents));
+        msg
+    }
+
+    fn make_ping(correlation_id: &str) -> Message {
+        make_message(Message_MessageType::PING_REQUEST, correlation_id, b"PING")
+    }
+
+    #[test]
+    fn record_roundtrip() {
+        let records = vec![
+            Record::new(Direction::Inbound, make_ping("one")),
+            Record::new(Direction::Outbound, make_ping("two")),
+        ];
+
+        let mut writer = RecordWriter::new(Vec::new()).unwrap();
+        for record in &records {
+            writer.write(record).unwrap();
+        }
+        let bytes = writer.writer;
+
+        let read = RecordReader::new(Cursor::new(bytes))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(read.len(), 2);
+        for (read, written) in read.iter().zip(records.iter()) {
+            assert_eq!(read.direction, written.direction);
+            assert_eq!(read.message, written.message);
+            assert_eq!(
+                read.timestamp
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_micros(),
+                written
+                    .timestamp
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_micros()
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_recording() {
+        assert!(RecordReader::new(Cursor::new(b"NOTREC\x01".to_vec())).is_err());
+        assert!(RecordReader::new(Cursor::new(b"SAWREC\x09".to_vec())).is_err());
+
+        // A truncated record is an error
+        let mut writer = RecordWriter::new(Vec::new()).unwrap();
+        writer
+            .write(&Record::new(Direction::Inbound, make_ping("one")))
+            .unwrap();
+        let mut bytes = writer.writer;
+        bytes.pop();
+        let mut reader = RecordReader::new(Cursor::new(bytes)).unwrap();
+        assert!(reader.read().is_err());
+    }
+}
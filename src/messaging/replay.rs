@@ -0,0 +1,360 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+
+use crate::messages::validator::Message;
+use crate::messages::validator::Message_MessageType;
+use crate::messaging::recording::{read_recording, Direction, Record, RecordingError};
+use crate::messaging::stream::*;
+
+/// Errors found when verifying a replay.
+#[derive(Debug)]
+pub enum ReplayError {
+    /// Returned when outbound messages did not match the recording
+    Mismatch(Vec<String>),
+    /// Returned when the replay ended before all of the recorded messages were exchanged
+    Incomplete(usize),
+}
+
+impl std::error::Error for ReplayError {}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            ReplayError::Mismatch(ref mismatches) => {
+                write!(f, "Mismatch: {}", mismatches.join("; "))
+            }
+            ReplayError::Incomplete(remaining) => {
+                write!(f, "Incomplete: {} records were not replayed", remaining)
+            }
+        }
+    }
+}
+
+struct ReplayState {
+    records: VecDeque<Record>,
+    inbound_tx: Option<Sender<MessageResult>>,
+    // Maps the recorded correlation id of a sent message to its live correlation id and the
+    // sender for its reply
+    expected_replies: HashMap<String, (String, Sender<MessageResult>)>,
+    mismatches: Vec<String>,
+}
+
+impl ReplayState {
+    /// Deliver the recorded inbound messages up to the next outbound message
+    fn advance(&mut self) {
+        while let Some(Direction::Inbound) = self.records.front().map(|record| record.direction) {
+            let mut message = self.records.pop_front().unwrap().message;
+            match self.expected_replies.remove(message.get_correlation_id()) {
+                Some((correlation_id, reply_tx)) => {
+                    message.set_correlation_id(correlation_id);
+                    reply_tx.send(Ok(message)).ok();
+                }
+                None => {
+                    if let Some(ref inbound_tx) = self.inbound_tx {
+                        inbound_tx.send(Ok(message)).ok();
+                    }
+                }
+            }
+        }
+
+        if self.records.is_empty() {
+            self.disconnect();
+        }
+    }
+
+    fn disconnect(&mut self) {
+        for (_, (_, reply_tx)) in self.expected_replies.drain() {
+            reply_tx.send(Err(ReceiveError::DisconnectedError)).ok();
+        }
+        if let Some(inbound_tx) = self.inbound_tx.take() {
+            inbound_tx.send(Err(ReceiveError::DisconnectedError)).ok();
+        }
+    }
+
+    /// Compare an outbound message against the next record, returning the recorded message
+    fn expect_outbound(
+        &mut self,
+        message_type: Message_MessageType,
+        correlation_id: Option<&str>,
+        contents: &[u8],
+    ) -> Option<Message> {
+        let recorded = match self.records.front() {
+            Some(record) if record.direction == Direction::Outbound => {
+                self.records.pop_front().unwrap().message
+            }
+            _ => {
+                self.mismatches.push(format!(
+                    "Unexpected outbound message of type {:?}",
+                    message_type
+                ));
+                return None;
+            }
+        };
+
+        if recorded.get_message_type() != message_type {
+            self.mismatches.push(format!(
+                "Expected outbound message of type {:?}, but was {:?}",
+                recorded.get_message_type(),
+                message_type
+            ));
+        } else if correlation_id.is_some() && correlation_id != Some(recorded.get_correlation_id())
+        {
+            self.mismatches.push(format!(
+                "Expected {:?} with correlation id {}, but was {}",
+                message_type,
+                recorded.get_correlation_id(),
+                correlation_id.unwrap_or_default()
+            ));
+        } else if recorded.get_content() != contents {
+            self.mismatches.push(format!(
+                "Content of {:?} does not match the recording",
+                message_type
+            ));
+        }
+
+        Some(recorded)
+    }
+}
+
+/// A MessageConnection which replays a recording.
+///
+/// Recorded inbound messages are delivered in order, as soon as the outbound messages recorded
+/// before them have been sent. Outbound messages are checked against the recording; replies to
+/// sent messages are delivered to their `MessageFuture` with the live correlation id. When the
+/// recording is exhausted the connection disconnects.
+#[derive(Clone)]
+pub struct ReplayConnection {
+    state: Arc<Mutex<ReplayState>>,
+}
+
+impl ReplayConnection {
+    pub fn new(records: Vec<Record>) -> Self {
+        ReplayConnection {
+            state: Arc::new(Mutex::new(ReplayState {
+                records: records.into(),
+                inbound_tx: None,
+                expected_replies: HashMap::new(),
+                mismatches: Vec::new(),
+            })),
+        }
+    }
+
+    /// Create a connection replaying the recording file at `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, RecordingError> {
+        Ok(ReplayConnection::new(read_recording(path)?))
+    }
+
+    /// Check that every outbound message matched the recording and that the whole recording was
+    /// replayed.
+    pub fn verify(&self) -> Result<(), ReplayError> {
+        let state = self.state.lock().unwrap();
+        if !state.mismatches.is_empty() {
+            return Err(ReplayError::Mismatch(state.mismatches.clone()));
+        }
+        if !state.records.is_empty() {
+            return Err(ReplayError::Incomplete(state.records.len()));
+        }
+        Ok(())
+    }
+}
+
+impl MessageConnection<ReplayMessageSender> for ReplayConnection {
+    fn create(&self) -> (ReplayMessageSender, MessageReceiver) {
+        let (inbound_tx, inbound_rx) = channel();
+        let mut state = self.state.lock().unwrap();
+        state.inbound_tx = Some(inbound_tx);
+        state.advance();
+
+        (
+            ReplayMessageSender {
+                state: self.state.clone(),
+            },
+            inbound_rx,
+        )
+    }
+}
+
+/// The MessageSender of a ReplayConnection
+#[derive(Clone)]
+pub struct ReplayMessageSender {
+    state: Arc<Mutex<ReplayState>>,
+}
+
+impl MessageSender for ReplayMessageSender {
+    fn send(
+        &self,
+        destination: Message_MessageType,
+        correlation_id: &str,
+        contents: &[u8],
+    ) -> Result<MessageFuture, SendError> {
+        let mut state = self.state.lock().unwrap();
+        if state.inbound_tx.is_none() {
+            return Err(SendError::DisconnectedError);
+        }
+
+        let (reply_tx, reply_rx) = channel();
+        match state.expect_outbound(destination, None, contents) {
+            Some(recorded) => {
+                state.expected_replies.insert(
+                    recorded.get_correlation_id().to_string(),
+                    (correlation_id.to_string(), reply_tx),
+                );
+            }
+            None => {
+                reply_tx.send(Err(ReceiveError::DisconnectedError)).ok();
+            }
+        }
+        state.advance();
+
+        Ok(MessageFuture::new(reply_rx))
+    }
+
+    fn reply(
+        &self,
+        destination: Message_MessageType,
+        correlation_id: &str,
+        contents: &[u8],
+    ) -> Result<(), SendError> {
+        let mut state = self.state.lock().unwrap();
+        if state.inbound_tx.is_none() {
+            return Err(SendError::DisconnectedError);
+        }
+
+        state.expect_outbound(destination, Some(correlation_id), contents);
+        state.advance();
+
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.state.lock().unwrap().disconnect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_record(
+        direction: Direction,
+        message_type: Message_MessageType,
+        correlation_id: &str,
+        content: &[u8],
+    ) -> Record {
+        let mut message = Message::new();
+        message.set_message_type(message_type);
+        message.set_correlation_id(correlation_id.into());
+        message.set_content(content.to_vec());
+        Record::new(direction, message)
+    }
+
+    fn recording() -> Vec<Record> {
+        vec![
+            make_record(
+                Direction::Outbound,
+                Message_MessageType::TP_REGISTER_REQUEST,
+                "recorded",
+                b"register",
+            ),
+            make_record(
+                Direction::Inbound,
+                Message_MessageType::TP_REGISTER_RESPONSE,
+                "recorded",
+                b"registered",
+            ),
+            make_record(
+                Direction::Inbound,
+                Message_MessageType::PING_REQUEST,
+                "ping",
+                b"",
+            ),
+            make_record(
+                Direction::Outbound,
+                Message_MessageType::PING_RESPONSE,
+                "ping",
+                b"",
+            ),
+        ]
+    }
+
+    #[test]
+    fn replay_matching() {
+        let connection = ReplayConnection::new(recording());
+        let (sender, receiver) = connection.create();
+
+        // Nothing is delivered before the recorded outbound message is sent
+        assert!(receiver.try_recv().is_err());
+
+        let mut future = sender
+            .send(
+                Message_MessageType::TP_REGISTER_REQUEST,
+                "live",
+                b"register",
+            )
+            .unwrap();
+        let reply = future.get().unwrap();
+        assert_eq!(reply.get_correlation_id(), "live");
+        assert_eq!(reply.get_content(), b"registered");
+
+        let ping = receiver.recv().unwrap().unwrap();
+        assert_eq!(ping.get_message_type(), Message_MessageType::PING_REQUEST);
+        sender
+            .reply(
+                Message_MessageType::PING_RESPONSE,
+                ping.get_correlation_id(),
+                b"",
+            )
+            .unwrap();
+
+        match receiver.recv().unwrap() {
+            Err(ReceiveError::DisconnectedError) => (),
+            res => panic!("Expected a disconnect, got {:?}", res),
+        }
+        connection.verify().unwrap();
+    }
+
+    #[test]
+    fn replay_mismatch() {
+        let connection = ReplayConnection::new(recording());
+        let (sender, _receiver) = connection.create();
+
+        sender
+            .send(Message_MessageType::TP_REGISTER_REQUEST, "live", b"changed")
+            .unwrap();
+
+        match connection.verify() {
+            Err(ReplayError::Mismatch(mismatches)) => assert_eq!(mismatches.len(), 1),
+            res => panic!("Expected a mismatch, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn replay_incomplete() {
+        let connection = ReplayConnection::new(recording());
+        let (_sender, _receiver) = connection.create();
+
+        match connection.verify() {
+            Err(ReplayError::Incomplete(4)) => (),
+            res => panic!("Expected an incomplete replay, got {:?}", res),
+        }
+    }
+}
@@ -18,6 +18,7 @@ use crate::messages::validator::Message;
 use crate::messages::validator::Message_MessageType;
 use std::sync::mpsc::Receiver;
 use std::sync::mpsc::RecvError;
+use std::sync::mpsc::RecvTimeoutError;
 use std::time::Duration;
 
 /// A Message Sender
@@ -57,8 +58,12 @@ pub trait MessageConnection<MS: MessageSender> {
 /// Errors that occur on sending a message.
 #[derive(Debug)]
 pub enum SendError {
+    /// The connection is closed
     DisconnectedError,
+    /// The message could not be queued before the send timeout expired
     TimeoutError,
+    /// The outbound queue is full and the message was not queued
+    QueueFullError,
     UnknownError,
 }
 
@@ -69,6 +74,7 @@ impl std::fmt::Display for SendError {
         match *self {
             SendError::DisconnectedError => write!(f, "DisconnectedError"),
             SendError::TimeoutError => write!(f, "TimeoutError"),
+            SendError::QueueFullError => write!(f, "QueueFullError"),
             SendError::UnknownError => write!(f, "UnknownError"),
         }
     }
@@ -104,6 +110,7 @@ impl std::fmt::Display for ReceiveError {
 pub struct MessageFuture {
     inner: Receiver<MessageResult>,
     result: Option<MessageResult>,
+    cancel: Option<Box<dyn FnOnce() + Send>>,
 }
 
 impl MessageFuture {
@@ -111,6 +118,17 @@ impl MessageFuture {
         MessageFuture {
             inner,
             result: None,
+            cancel: None,
+        }
+    }
+
+    /// Create a MessageFuture which calls `cancel` if it is dropped before a reply has been
+    /// received, allowing the connection to stop waiting for the reply.
+    pub fn with_cancel(inner: Receiver<MessageResult>, cancel: Box<dyn FnOnce() + Send>) -> Self {
+        MessageFuture {
+            inner,
+            result: None,
+            cancel: Some(cancel),
         }
     }
 
@@ -138,7 +156,18 @@ impl MessageFuture {
                 self.result = Some(result.clone());
                 result
             }
-            Err(_) => Err(ReceiveError::TimeoutError),
+            Err(RecvTimeoutError::Timeout) => Err(ReceiveError::TimeoutError),
+            Err(RecvTimeoutError::Disconnected) => Err(ReceiveError::ChannelError(RecvError)),
+        }
+    }
+}
+
+impl Drop for MessageFuture {
+    fn drop(&mut self) {
+        if self.result.is_none() {
+            if let Some(cancel) = self.cancel.take() {
+                cancel();
+            }
         }
     }
 }
@@ -148,7 +177,9 @@ impl MessageFuture {
 #[cfg(test)]
 mod tests {
 
+    use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::mpsc::channel;
+    use std::sync::Arc;
     use std::thread;
 
     use crate::messages::validator::Message;
@@ -181,4 +212,27 @@ mod tests {
 
         assert_eq!(msg, make_ping("my_test"));
     }
+
+    #[test]
+    fn future_cancel_on_drop() {
+        let (_tx, rx) = channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let flag = cancelled.clone();
+        let fut =
+            MessageFuture::with_cancel(rx, Box::new(move || flag.store(true, Ordering::SeqCst)));
+        drop(fut);
+        assert!(cancelled.load(Ordering::SeqCst));
+
+        // A future that has received its reply is not cancelled
+        cancelled.store(false, Ordering::SeqCst);
+        let (tx, rx) = channel();
+        let flag = cancelled.clone();
+        let mut fut =
+            MessageFuture::with_cancel(rx, Box::new(move || flag.store(true, Ordering::SeqCst)));
+        tx.send(Ok(make_ping("my_test"))).unwrap();
+        fut.get().expect("Should have a message");
+        drop(fut);
+        assert!(!cancelled.load(Ordering::SeqCst));
+    }
 }
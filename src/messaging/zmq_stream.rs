@@ -16,42 +16,128 @@
  */
 
 use std::collections::HashMap;
-use std::sync::mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{
+    channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError,
+};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use protobuf::Message as ProtobufMessage;
 
 use crate::messages::validator::Message;
 use crate::messages::validator::Message_MessageType;
 
+use crate::messaging::metrics::{ConnectionMetrics, MetricsSnapshot};
+use crate::messaging::recording::{Direction, Recorder};
 use crate::messaging::stream::*;
 
 /// A MessageConnection over ZMQ sockets
 pub struct ZmqMessageConnection {
     address: String,
     context: zmq::Context,
+    reply_timeout: Option<Duration>,
+    send_timeout: Option<Duration>,
+    inbound_buffer_size: usize,
+    outbound_buffer_size: usize,
+    metrics: ConnectionMetrics,
+    recorder: Option<Recorder>,
 }
 
 const CHANNEL_BUFFER_SIZE: usize = 128;
 
+/// How long to wait between attempts to queue a message while the outbound queue is full.
+const SEND_RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+/// How long the correlation id of an abandoned reply is remembered, so that a late reply is
+/// dropped instead of being routed as a new message.
+const ABANDONED_REPLY_RETENTION: Duration = Duration::from_secs(300);
+
+/// How often expected replies are checked for expired deadlines.
+const REPLY_SWEEP_INTERVAL: Duration = Duration::from_millis(100);
+
 impl ZmqMessageConnection {
     /// Create a new ZmqMessageConnection
     pub fn new(address: &str) -> Self {
         ZmqMessageConnection {
             address: String::from(address),
             context: zmq::Context::new(),
+            reply_timeout: None,
+            send_timeout: None,
+            inbound_buffer_size: CHANNEL_BUFFER_SIZE,
+            outbound_buffer_size: CHANNEL_BUFFER_SIZE,
+            metrics: ConnectionMetrics::new(),
+            recorder: None,
         }
     }
+
+    /// Set the deadline for replies to messages sent on this connection.
+    ///
+    /// A reply which has not arrived within `timeout` is abandoned: its `MessageFuture` receives
+    /// a `ReceiveError::TimeoutError` and a reply arriving afterwards is dropped and counted as
+    /// orphaned.
+    pub fn with_reply_timeout(mut self, timeout: Duration) -> Self {
+        self.reply_timeout = Some(timeout);
+        self
+    }
+
+    /// Set how long `send` and `reply` wait for room in the outbound queue.
+    ///
+    /// By default they block until the message is queued. With a send timeout, a message which
+    /// cannot be queued in time fails with `SendError::TimeoutError`.
+    pub fn with_send_timeout(mut self, timeout: Duration) -> Self {
+        self.send_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the number of messages received from the validator which may be queued before the
+    /// receiver is read. Defaults to 128.
+    pub fn with_inbound_buffer_size(mut self, size: usize) -> Self {
+        self.inbound_buffer_size = size;
+        self
+    }
+
+    /// Set the number of messages which may be queued for sending to the validator. Defaults to
+    /// 128.
+    pub fn with_outbound_buffer_size(mut self, size: usize) -> Self {
+        self.outbound_buffer_size = size;
+        self
+    }
+
+    /// Collect the metrics of this connection in `metrics`.
+    ///
+    /// Giving the same metrics to the connections made when reconnecting keeps the counts across
+    /// reconnects, and counts the reconnects.
+    pub fn with_metrics(mut self, metrics: ConnectionMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Record every message sent or received on this connection with `recorder`.
+    ///
+    /// Messages are recorded by the socket's thread as they are sent and received, so replies
+    /// are recorded when they arrive whether or not their `MessageFuture` is waited on.
+    pub fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
 }
 
 impl MessageConnection<ZmqMessageSender> for ZmqMessageConnection {
     fn create(&self) -> (ZmqMessageSender, MessageReceiver) {
         // Create the channel for request messages (i.e. non-reply messages)
-        let (request_tx, request_rx) = sync_channel(CHANNEL_BUFFER_SIZE);
-        let router = InboundRouter::new(request_tx);
+        let (request_tx, request_rx) = sync_channel(self.inbound_buffer_size);
+        self.metrics.record_connection();
+        let router = InboundRouter::new(
+            request_tx,
+            self.reply_timeout,
+            self.metrics.clone(),
+            self.recorder.clone(),
+        );
         let mut sender = ZmqMessageSender::new(self.context.clone(), self.address.clone(), router);
+        sender.send_timeout = self.send_timeout;
+        sender.outbound_buffer_size = self.outbound_buffer_size;
 
         sender.start();
 
@@ -65,12 +151,22 @@ enum SocketCommand {
     Shutdown,
 }
 
+/// How to wait for room in the outbound queue
+#[derive(Clone, Copy)]
+enum Enqueue {
+    Block,
+    Timeout(Duration),
+    Try,
+}
+
 #[derive(Clone)]
 pub struct ZmqMessageSender {
     context: zmq::Context,
     address: String,
     inbound_router: InboundRouter,
     outbound_sender: Option<SyncSender<SocketCommand>>,
+    outbound_buffer_size: usize,
+    send_timeout: Option<Duration>,
 }
 
 impl ZmqMessageSender {
@@ -80,12 +176,145 @@ impl ZmqMessageSender {
             address,
             inbound_router: router,
             outbound_sender: None,
+            outbound_buffer_size: CHANNEL_BUFFER_SIZE,
+            send_timeout: None,
+        }
+    }
+
+    /// Send a message, waiting at most `timeout` for room in the outbound queue.
+    ///
+    /// Fails with `SendError::TimeoutError` if the message could not be queued in time.
+    pub fn send_timeout(
+        &self,
+        destination: Message_MessageType,
+        correlation_id: &str,
+        contents: &[u8],
+        timeout: Duration,
+    ) -> Result<MessageFuture, SendError> {
+        self.send_message(
+            destination,
+            correlation_id,
+            contents,
+            Enqueue::Timeout(timeout),
+        )
+    }
+
+    /// Send a message without waiting for room in the outbound queue.
+    ///
+    /// Fails with `SendError::QueueFullError` if the outbound queue is full.
+    pub fn try_send(
+        &self,
+        destination: Message_MessageType,
+        correlation_id: &str,
+        contents: &[u8],
+    ) -> Result<MessageFuture, SendError> {
+        self.send_message(destination, correlation_id, contents, Enqueue::Try)
+    }
+
+    /// Reply to a message without waiting for room in the outbound queue.
+    ///
+    /// Fails with `SendError::QueueFullError` if the outbound queue is full.
+    pub fn try_reply(
+        &self,
+        destination: Message_MessageType,
+        correlation_id: &str,
+        contents: &[u8],
+    ) -> Result<(), SendError> {
+        self.enqueue(
+            make_message(destination, correlation_id, contents),
+            Enqueue::Try,
+        )
+    }
+
+    /// Returns a snapshot of the metrics of this sender's connection.
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.inbound_router.metrics.snapshot()
+    }
+
+    /// Returns the number of sent messages which are still waiting for a reply.
+    pub fn pending_replies(&self) -> usize {
+        self.inbound_router.pending_replies()
+    }
+
+    /// Returns the number of replies which arrived after their `MessageFuture` was dropped or
+    /// their deadline passed, and were therefore discarded.
+    pub fn orphaned_replies(&self) -> usize {
+        self.inbound_router.orphaned_replies()
+    }
+
+    fn default_enqueue(&self) -> Enqueue {
+        self.send_timeout
+            .map(Enqueue::Timeout)
+            .unwrap_or(Enqueue::Block)
+    }
+
+    fn send_message(
+        &self,
+        destination: Message_MessageType,
+        correlation_id: &str,
+        contents: &[u8],
+        enqueue: Enqueue,
+    ) -> Result<MessageFuture, SendError> {
+        if self.outbound_sender.is_none() {
+            return Err(SendError::DisconnectedError);
+        }
+
+        // Expect the reply before sending, so that it cannot arrive before it is expected
+        let future = self
+            .inbound_router
+            .expect_reply(String::from(correlation_id), destination);
+
+        match self.enqueue(make_message(destination, correlation_id, contents), enqueue) {
+            Ok(()) => Ok(future),
+            Err(err) => {
+                self.inbound_router.forget_reply(correlation_id);
+                Err(err)
+            }
+        }
+    }
+
+    /// Queue a message for the socket thread
+    fn enqueue(&self, msg: Message, enqueue: Enqueue) -> Result<(), SendError> {
+        let sender = match self.outbound_sender {
+            Some(ref sender) => sender,
+            None => return Err(SendError::DisconnectedError),
+        };
+
+        let mut command = SocketCommand::Send(msg);
+        let deadline = match enqueue {
+            Enqueue::Block => {
+                return sender
+                    .send(command)
+                    .map_err(|_| SendError::DisconnectedError)
+            }
+            Enqueue::Timeout(timeout) => Some(Instant::now() + timeout),
+            Enqueue::Try => None,
+        };
+
+        loop {
+            match sender.try_send(command) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Disconnected(_)) => return Err(SendError::DisconnectedError),
+                Err(TrySendError::Full(returned)) => {
+                    let deadline = match deadline {
+                        Some(deadline) => deadline,
+                        None => return Err(SendError::QueueFullError),
+                    };
+                    let now = Instant::now();
+                    if now >= deadline {
+                        warn!("Outbound queue is full, unable to send message");
+                        return Err(SendError::TimeoutError);
+                    }
+                    thread::sleep(SEND_RETRY_INTERVAL.min(deadline - now));
+                    command = returned;
+                }
+            }
         }
     }
 
     /// Start the message stream instance
     fn start(&mut self) {
-        let (outbound_send, outbound_recv) = sync_channel(CHANNEL_BUFFER_SIZE);
+        let (outbound_send, outbound_recv) = sync_channel(self.outbound_buffer_size);
         self.outbound_sender = Some(outbound_send);
 
         let ctx = self.context.clone();
@@ -106,28 +335,12 @@ impl MessageSender for ZmqMessageSender {
         correlation_id: &str,
         contents: &[u8],
     ) -> Result<MessageFuture, SendError> {
-        if let Some(ref sender) = self.outbound_sender {
-            let mut msg = Message::new();
-
-            msg.set_message_type(destination);
-            msg.set_correlation_id(String::from(correlation_id));
-            msg.set_content(Vec::from(contents));
-
-            let future = MessageFuture::new(
-                self.inbound_router
-                    .expect_reply(String::from(correlation_id)),
-            );
-
-            match sender.send(SocketCommand::Send(msg)) {
-                Ok(_) => Ok(future),
-                Err(e) => {
-                    log::error!("Unable to send message: {:?}", e);
-                    Err(SendError::UnknownError)
-                }
-            }
-        } else {
-            Err(SendError::DisconnectedError)
-        }
+        self.send_message(
+            destination,
+            correlation_id,
+            contents,
+            self.default_enqueue(),
+        )
     }
 
     fn reply(
@@ -136,22 +349,10 @@ impl MessageSender for ZmqMessageSender {
         correlation_id: &str,
         contents: &[u8],
     ) -> Result<(), SendError> {
-        if let Some(ref sender) = self.outbound_sender {
-            let mut msg = Message::new();
-            msg.set_message_type(destination);
-            msg.set_correlation_id(String::from(correlation_id));
-            msg.set_content(Vec::from(contents));
-
-            match sender.send(SocketCommand::Send(msg)) {
-                Ok(_) => Ok(()),
-                Err(e) => {
-                    log::error!("Unable to send message: {:?}", e);
-                    Err(SendError::UnknownError)
-                }
-            }
-        } else {
-            Err(SendError::DisconnectedError)
-        }
+        self.enqueue(
+            make_message(destination, correlation_id, contents),
+            self.default_enqueue(),
+        )
     }
 
     fn close(&mut self) {
@@ -166,42 +367,119 @@ impl MessageSender for ZmqMessageSender {
     }
 }
 
+fn make_message(
+    destination: Message_MessageType,
+    correlation_id: &str,
+    contents: &[u8],
+) -> Message {
+    let mut msg = Message::new();
+    msg.set_message_type(destination);
+    msg.set_correlation_id(String::from(correlation_id));
+    msg.set_content(Vec::from(contents));
+    msg
+}
+
+struct ExpectedReply {
+    sender: Sender<MessageResult>,
+    deadline: Option<Instant>,
+    message_type: Message_MessageType,
+    sent_at: Instant,
+}
+
+#[derive(Default)]
+struct ExpectedReplies {
+    pending: HashMap<String, ExpectedReply>,
+    // Correlation ids of replies nobody is waiting for anymore, with the time they were abandoned
+    abandoned: HashMap<String, Instant>,
+}
+
+impl ExpectedReplies {
+    fn abandon(&mut self, correlation_id: &str) -> Option<ExpectedReply> {
+        let expected = self.pending.remove(correlation_id);
+        if expected.is_some() {
+            self.abandoned
+                .insert(correlation_id.to_string(), Instant::now());
+        }
+        expected
+    }
+}
+
 #[derive(Clone)]
 struct InboundRouter {
     inbound_tx: SyncSender<MessageResult>,
-    expected_replies: Arc<Mutex<HashMap<String, Sender<MessageResult>>>>,
+    expected_replies: Arc<Mutex<ExpectedReplies>>,
+    reply_timeout: Option<Duration>,
+    orphaned_replies: Arc<AtomicUsize>,
+    metrics: ConnectionMetrics,
+    recorder: Option<Recorder>,
 }
 
 impl InboundRouter {
-    fn new(inbound_tx: SyncSender<MessageResult>) -> Self {
+    fn new(
+        inbound_tx: SyncSender<MessageResult>,
+        reply_timeout: Option<Duration>,
+        metrics: ConnectionMetrics,
+        recorder: Option<Recorder>,
+    ) -> Self {
         InboundRouter {
             inbound_tx,
-            expected_replies: Arc::new(Mutex::new(HashMap::new())),
+            expected_replies: Arc::new(Mutex::new(ExpectedReplies::default())),
+            reply_timeout,
+            orphaned_replies: Arc::new(AtomicUsize::new(0)),
+            metrics,
+            recorder,
         }
     }
+
+    fn record(&self, direction: Direction, message: &Message) {
+        if let Some(ref recorder) = self.recorder {
+            recorder.record(direction, message);
+        }
+    }
+
     fn route(&mut self, message_result: MessageResult) {
         match message_result {
             Ok(message) => {
+                self.record(Direction::Inbound, &message);
                 let mut expected_replies = self.expected_replies.lock().unwrap();
-                match expected_replies.remove(message.get_correlation_id()) {
-                    Some(sender) => sender
-                        .send(Ok(message))
-                        .map_err(|e| log::warn!("Unable to route reply: {:?}", e))
-                        .ok(),
-                    None => self
-                        .inbound_tx
-                        .send(Ok(message))
-                        .map_err(|e| log::warn!("Unable to route new message: {:?}", e))
-                        .ok(),
+                let correlation_id = message.get_correlation_id();
+                let expected = expected_replies.pending.remove(correlation_id);
+                self.metrics
+                    .set_pending_replies(expected_replies.pending.len());
+                match expected {
+                    Some(expected) => {
+                        self.metrics.record_reply_latency(
+                            expected.message_type,
+                            expected.sent_at.elapsed(),
+                        );
+                        if let Err(e) = expected.sender.send(Ok(message)) {
+                            log::warn!("Unable to route reply: {:?}", e);
+                            self.orphaned_replies.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                    None if expected_replies.abandoned.remove(correlation_id).is_some() => {
+                        debug!(
+                            "Dropping reply to abandoned request {}",
+                            message.get_correlation_id()
+                        );
+                        self.orphaned_replies.fetch_add(1, Ordering::Relaxed);
+                    }
+                    None => {
+                        self.inbound_tx
+                            .send(Ok(message))
+                            .unwrap_or_else(|e| log::warn!("Unable to route new message: {:?}", e));
+                    }
                 };
             }
             Err(ReceiveError::DisconnectedError) => {
                 let mut expected_replies = self.expected_replies.lock().unwrap();
-                for (_, sender) in expected_replies.iter_mut() {
-                    sender
+                for (_, expected) in expected_replies.pending.drain() {
+                    expected
+                        .sender
                         .send(Err(ReceiveError::DisconnectedError))
                         .unwrap_or_else(|err| warn!("Failed to send disconnect reply: {}", err));
                 }
+                self.metrics.set_pending_replies(0);
                 self.inbound_tx
                     .send(Err(ReceiveError::DisconnectedError))
                     .unwrap_or_else(|err| warn!("Failed to send disconnect: {}", err));
@@ -210,12 +488,80 @@ impl InboundRouter {
         }
     }
 
-    fn expect_reply(&self, correlation_id: String) -> Receiver<MessageResult> {
+    fn expect_reply(
+        &self,
+        correlation_id: String,
+        message_type: Message_MessageType,
+    ) -> MessageFuture {
         let (expect_tx, expect_rx) = channel();
         let mut expected_replies = self.expected_replies.lock().unwrap();
-        expected_replies.insert(correlation_id, expect_tx);
+        expected_replies.pending.insert(
+            correlation_id.clone(),
+            ExpectedReply {
+                sender: expect_tx,
+                deadline: self.reply_timeout.map(|timeout| Instant::now() + timeout),
+                message_type,
+                sent_at: Instant::now(),
+            },
+        );
+        self.metrics
+            .set_pending_replies(expected_replies.pending.len());
+
+        let replies = Arc::downgrade(&self.expected_replies);
+        let metrics = self.metrics.clone();
+        MessageFuture::with_cancel(
+            expect_rx,
+            Box::new(move || {
+                if let Some(replies) = replies.upgrade() {
+                    if let Ok(mut replies) = replies.lock() {
+                        replies.abandon(&correlation_id);
+                        metrics.set_pending_replies(replies.pending.len());
+                    }
+                }
+            }),
+        )
+    }
+
+    /// Stop expecting a reply to a message which was never sent
+    fn forget_reply(&self, correlation_id: &str) {
+        let mut expected_replies = self.expected_replies.lock().unwrap();
+        expected_replies.pending.remove(correlation_id);
+        self.metrics
+            .set_pending_replies(expected_replies.pending.len());
+    }
+
+    /// Abandon the expected replies whose deadline has passed, notifying their futures, and
+    /// forget abandoned correlation ids once the retention period is over.
+    fn expire_replies(&self) {
+        let now = Instant::now();
+        let mut expected_replies = self.expected_replies.lock().unwrap();
 
-        expect_rx
+        let expired = expected_replies
+            .pending
+            .iter()
+            .filter(|(_, expected)| matches!(expected.deadline, Some(deadline) if deadline <= now))
+            .map(|(correlation_id, _)| correlation_id.clone())
+            .collect::<Vec<_>>();
+        for correlation_id in expired {
+            if let Some(expected) = expected_replies.abandon(&correlation_id) {
+                debug!("Reply to {} timed out", correlation_id);
+                expected.sender.send(Err(ReceiveError::TimeoutError)).ok();
+            }
+        }
+        self.metrics
+            .set_pending_replies(expected_replies.pending.len());
+
+        expected_replies.abandoned.retain(|_, abandoned_at| {
+            now.duration_since(*abandoned_at) < ABANDONED_REPLY_RETENTION
+        });
+    }
+
+    fn pending_replies(&self) -> usize {
+        self.expected_replies.lock().unwrap().pending.len()
+    }
+
+    fn orphaned_replies(&self) -> usize {
+        self.orphaned_replies.load(Ordering::Relaxed)
     }
 }
 
@@ -263,7 +609,13 @@ impl SendReceiveStream {
         self.monitor_socket
             .connect("inproc://monitor-socket")
             .unwrap();
+        let mut next_sweep = Instant::now() + REPLY_SWEEP_INTERVAL;
         loop {
+            if Instant::now() >= next_sweep {
+                self.inbound_router.expire_replies();
+                next_sweep = Instant::now() + REPLY_SWEEP_INTERVAL;
+            }
+
             let mut poll_items = [
                 self.socket.as_poll_item(zmq::POLLIN),
                 self.monitor_socket.as_poll_item(zmq::POLLIN),
@@ -277,7 +629,11 @@ impl SendReceiveStream {
                 if let Some(received_bytes) = received_parts.pop() {
                     trace!("Received {} bytes", received_bytes.len());
                     if !received_bytes.is_empty() {
-                        let message = ProtobufMessage::parse_from_bytes(&received_bytes).unwrap();
+                        let message: Message =
+                            ProtobufMessage::parse_from_bytes(&received_bytes).unwrap();
+                        self.inbound_router
+                            .metrics
+                            .record_received(message.get_message_type(), received_bytes.len());
                         self.inbound_router.route(Ok(message));
                     }
                 } else {
@@ -300,6 +656,10 @@ impl SendReceiveStream {
                     let message_bytes = protobuf::Message::write_to_bytes(&msg).unwrap();
                     trace!("Sending {} bytes", message_bytes.len());
                     self.socket.send(&message_bytes, 0).unwrap();
+                    self.inbound_router
+                        .metrics
+                        .record_sent(msg.get_message_type(), message_bytes.len());
+                    self.inbound_router.record(Direction::Outbound, &msg);
                 }
                 Ok(SocketCommand::Shutdown) => {
                     trace!("Shutdown Signal Received");
@@ -324,3 +684,190 @@ impl SendReceiveStream {
             .unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::messaging::recording::read_recording;
+
+    fn make_reply(correlation_id: &str) -> Message {
+        let mut message = Message::new();
+        message.set_message_type(Message_MessageType::PING_RESPONSE);
+        message.set_correlation_id(String::from(correlation_id));
+        message
+    }
+
+    #[test]
+    fn route_expected_reply() {
+        let (inbound_tx, inbound_rx) = sync_channel(CHANNEL_BUFFER_SIZE);
+        let mut router = InboundRouter::new(inbound_tx, None, ConnectionMetrics::new(), None);
+
+        let mut future = router.expect_reply("reply".into(), Message_MessageType::PING_REQUEST);
+        assert_eq!(router.pending_replies(), 1);
+
+        router.route(Ok(make_reply("reply")));
+        assert_eq!(future.get().unwrap(), make_reply("reply"));
+        assert_eq!(router.pending_replies(), 0);
+        assert!(inbound_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn dropped_future_is_removed() {
+        let (inbound_tx, inbound_rx) = sync_channel(CHANNEL_BUFFER_SIZE);
+        let mut router = InboundRouter::new(inbound_tx, None, ConnectionMetrics::new(), None);
+
+        let future = router.expect_reply("dropped".into(), Message_MessageType::PING_REQUEST);
+        assert_eq!(router.pending_replies(), 1);
+        drop(future);
+        assert_eq!(router.pending_replies(), 0);
+
+        // The late reply is counted, not routed as a new message
+        router.route(Ok(make_reply("dropped")));
+        assert_eq!(router.orphaned_replies(), 1);
+        assert!(inbound_rx.try_recv().is_err());
+
+        // Messages with unknown correlation ids are still routed as new messages
+        router.route(Ok(make_reply("unknown")));
+        assert_eq!(router.orphaned_replies(), 1);
+        assert_eq!(
+            inbound_rx.try_recv().unwrap().unwrap(),
+            make_reply("unknown")
+        );
+    }
+
+    #[test]
+    fn expired_reply_is_abandoned() {
+        let (inbound_tx, inbound_rx) = sync_channel(CHANNEL_BUFFER_SIZE);
+        let mut router = InboundRouter::new(
+            inbound_tx,
+            Some(Duration::from_millis(10)),
+            ConnectionMetrics::new(),
+            None,
+        );
+
+        let mut future = router.expect_reply("expired".into(), Message_MessageType::PING_REQUEST);
+        thread::sleep(Duration::from_millis(20));
+        router.expire_replies();
+        assert_eq!(router.pending_replies(), 0);
+
+        match future.get() {
+            Err(ReceiveError::TimeoutError) => (),
+            res => panic!("Expected a timeout, got {:?}", res),
+        }
+
+        router.route(Ok(make_reply("expired")));
+        assert_eq!(router.orphaned_replies(), 1);
+        assert!(inbound_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn replies_are_recorded_on_arrival() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("recording");
+        let (inbound_tx, _inbound_rx) = sync_channel(CHANNEL_BUFFER_SIZE);
+        let mut router = InboundRouter::new(
+            inbound_tx,
+            None,
+            ConnectionMetrics::new(),
+            Some(Recorder::create(&path).unwrap()),
+        );
+
+        // Neither reply is waited for, and one of them is abandoned
+        let _future = router.expect_reply("waiting".into(), Message_MessageType::PING_REQUEST);
+        drop(router.expect_reply("dropped".into(), Message_MessageType::PING_REQUEST));
+        router.route(Ok(make_reply("dropped")));
+        router.route(Ok(make_reply("waiting")));
+
+        let records = read_recording(&path).unwrap();
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| (record.direction, record.message.get_correlation_id()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Direction::Inbound, "dropped"),
+                (Direction::Inbound, "waiting")
+            ]
+        );
+    }
+
+    /// Create a sender whose outbound queue holds `size` messages, without a socket thread
+    /// draining it.
+    fn make_stalled_sender(size: usize) -> (ZmqMessageSender, Receiver<SocketCommand>) {
+        let (inbound_tx, _) = sync_channel(CHANNEL_BUFFER_SIZE);
+        let router = InboundRouter::new(inbound_tx, None, ConnectionMetrics::new(), None);
+        let mut sender = ZmqMessageSender::new(zmq::Context::new(), "inproc://test".into(), router);
+        let (outbound_tx, outbound_rx) = sync_channel(size);
+        sender.outbound_sender = Some(outbound_tx);
+        (sender, outbound_rx)
+    }
+
+    #[test]
+    fn try_send_full_queue() {
+        let (sender, outbound_rx) = make_stalled_sender(1);
+
+        let _future = sender
+            .try_send(Message_MessageType::PING_REQUEST, "first", b"")
+            .unwrap();
+        match sender.try_send(Message_MessageType::PING_REQUEST, "second", b"") {
+            Err(SendError::QueueFullError) => (),
+            res => panic!("Expected a full queue, got {:?}", res.map(|_| ())),
+        }
+        match sender.try_reply(Message_MessageType::PING_RESPONSE, "reply", b"") {
+            Err(SendError::QueueFullError) => (),
+            res => panic!("Expected a full queue, got {:?}", res),
+        }
+        // Only the queued message expects a reply
+        assert_eq!(sender.pending_replies(), 1);
+
+        outbound_rx.recv().unwrap();
+        sender
+            .try_reply(Message_MessageType::PING_RESPONSE, "reply", b"")
+            .unwrap();
+    }
+
+    #[test]
+    fn send_timeout_full_queue() {
+        let (mut sender, _outbound_rx) = make_stalled_sender(1);
+        sender
+            .reply(Message_MessageType::PING_RESPONSE, "first", b"")
+            .unwrap();
+
+        let start = Instant::now();
+        match sender.send_timeout(
+            Message_MessageType::PING_REQUEST,
+            "second",
+            b"",
+            Duration::from_millis(20),
+        ) {
+            Err(SendError::TimeoutError) => (),
+            res => panic!("Expected a timeout, got {:?}", res.map(|_| ())),
+        }
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(sender.pending_replies(), 0);
+
+        // The connection send timeout applies to send and reply
+        sender.send_timeout = Some(Duration::from_millis(10));
+        match sender.reply(Message_MessageType::PING_RESPONSE, "third", b"") {
+            Err(SendError::TimeoutError) => (),
+            res => panic!("Expected a timeout, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn send_after_socket_thread_exit() {
+        let (sender, outbound_rx) = make_stalled_sender(1);
+        drop(outbound_rx);
+
+        match sender.try_send(Message_MessageType::PING_REQUEST, "try", b"") {
+            Err(SendError::DisconnectedError) => (),
+            res => panic!("Expected a disconnect, got {:?}", res.map(|_| ())),
+        }
+        match sender.send(Message_MessageType::PING_REQUEST, "send", b"") {
+            Err(SendError::DisconnectedError) => (),
+            res => panic!("Expected a disconnect, got {:?}", res.map(|_| ())),
+        }
+        assert_eq!(sender.pending_replies(), 0);
+    }
+}
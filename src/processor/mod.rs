@@ -25,6 +25,7 @@ extern crate zmq;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::RecvTimeoutError;
 use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
 
 use rand::{distributions::Alphanumeric, Rng};
@@ -39,12 +40,15 @@ use crate::messages::processor::TpProcessResponse_Status;
 use crate::messages::processor::TpRegisterRequest;
 use crate::messages::processor::TpUnregisterRequest;
 use crate::messages::validator::Message_MessageType;
+use crate::messaging::failover::{Backoff, Endpoints};
+use crate::messaging::metrics::ConnectionMetrics;
+use crate::messaging::recording::Recorder;
 use crate::messaging::stream::MessageConnection;
+use crate::messaging::stream::MessageReceiver;
 use crate::messaging::stream::MessageSender;
 use crate::messaging::stream::ReceiveError;
 use crate::messaging::stream::SendError;
 use crate::messaging::zmq_stream::ZmqMessageConnection;
-use crate::messaging::zmq_stream::ZmqMessageSender;
 use protobuf::Message as ProtobufMessage;
 use protobuf::RepeatedField;
 
@@ -63,10 +67,25 @@ fn generate_correlation_id() -> String {
         .collect::<String>()
 }
 
+/// How a connection to the validator ended
+enum Served {
+    /// The connection was lost, the processor should reconnect
+    Disconnected,
+    /// The processor was stopped
+    Stopped,
+}
+
+/// How long to wait for a registration response before checking whether the processor has been
+/// stopped, or, with several endpoints, before failing over to the next one
+const REGISTER_TIMEOUT: Duration = Duration::from_millis(10000);
+
 pub struct TransactionProcessor<'a> {
-    endpoint: String,
+    endpoints: Endpoints,
     conn: ZmqMessageConnection,
     handlers: Vec<&'a dyn TransactionHandler>,
+    reconnect_backoff: Backoff,
+    metrics: ConnectionMetrics,
+    recorder: Option<Recorder>,
 }
 
 impl<'a> TransactionProcessor<'a> {
@@ -74,10 +93,65 @@ impl<'a> TransactionProcessor<'a> {
     /// validator and routing transaction processing requests to a registered
     /// handler. It uses ZMQ and channels to handle requests concurrently.
     pub fn new(endpoint: &str) -> TransactionProcessor {
+        TransactionProcessor::with_endpoints(&[endpoint])
+    }
+
+    /// Create a TransactionProcessor which fails over between several validator endpoints.
+    ///
+    /// The endpoints are tried in order: when the connection to the active endpoint is lost, or
+    /// the validator does not answer the registration, the processor reconnects to the next
+    /// endpoint and registers its handlers again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `endpoints` is empty.
+    pub fn with_endpoints<T: AsRef<str>>(endpoints: &[T]) -> Self {
+        let endpoints = Endpoints::new(endpoints);
+        let metrics = ConnectionMetrics::new();
         TransactionProcessor {
-            endpoint: String::from(endpoint),
-            conn: ZmqMessageConnection::new(endpoint),
+            conn: ZmqMessageConnection::new(endpoints.active()).with_metrics(metrics.clone()),
+            endpoints,
             handlers: Vec::new(),
+            reconnect_backoff: Backoff::default(),
+            metrics,
+            recorder: None,
+        }
+    }
+
+    /// Returns the metrics of the processor's connections to the validator.
+    ///
+    /// The metrics are kept across reconnects and may be read while the processor is running.
+    pub fn connection_metrics(&self) -> ConnectionMetrics {
+        self.metrics.clone()
+    }
+
+    /// Sets the delays to wait between reconnection attempts
+    ///
+    /// # Arguments
+    ///
+    /// * backoff - the backoff to use when reconnecting
+    pub fn set_reconnect_backoff(&mut self, backoff: Backoff) {
+        self.reconnect_backoff = backoff;
+    }
+
+    /// Records every message exchanged with the validator, on every connection the processor
+    /// makes, including those made when reconnecting or failing over
+    ///
+    /// # Arguments
+    ///
+    /// * recorder - the recorder to write the messages to
+    pub fn set_recorder(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+        self.conn = self.active_connection();
+    }
+
+    /// Create a connection to the active endpoint
+    fn active_connection(&self) -> ZmqMessageConnection {
+        let conn =
+            ZmqMessageConnection::new(self.endpoints.active()).with_metrics(self.metrics.clone());
+        match self.recorder {
+            Some(ref recorder) => conn.with_recorder(recorder.clone()),
+            None => conn,
         }
     }
 
@@ -90,7 +164,7 @@ impl<'a> TransactionProcessor<'a> {
         self.handlers.push(handler);
     }
 
-    fn register(&mut self, sender: &ZmqMessageSender, unregister: &Arc<AtomicBool>) -> bool {
+    fn register(&mut self, sender: &dyn MessageSender, unregister: &AtomicBool) -> bool {
         for handler in &self.handlers {
             for version in handler.family_versions() {
                 let mut request = TpRegisterRequest::new();
@@ -125,23 +199,27 @@ impl<'a> TransactionProcessor<'a> {
                     }
                 };
 
-                // Absorb the TpRegisterResponse message
+                // Absorb the TpRegisterResponse message. With a single endpoint there is nothing
+                // to fail over to, so keep waiting until the validator answers or the processor
+                // is stopped.
                 loop {
-                    match future.get_timeout(Duration::from_millis(10000)) {
+                    match future.get_timeout(REGISTER_TIMEOUT) {
                         Ok(_) => break,
-                        Err(_) => {
-                            if unregister.load(Ordering::SeqCst) {
-                                return false;
-                            }
+                        Err(ReceiveError::TimeoutError)
+                            if self.endpoints.len() == 1 && !unregister.load(Ordering::SeqCst) => {}
+                        Err(err) => {
+                            error!("No registration response: {}", err);
+                            // try reconnect
+                            return false;
                         }
-                    };
+                    }
                 }
             }
         }
         true
     }
 
-    fn unregister(&mut self, sender: &ZmqMessageSender) {
+    fn unregister(&mut self, sender: &dyn MessageSender) {
         let request = TpUnregisterRequest::new();
         info!("sending TpUnregisterRequest");
         let serialized = match request.write_to_bytes() {
@@ -189,12 +267,18 @@ impl<'a> TransactionProcessor<'a> {
         let mut restart = true;
 
         while restart {
-            info!("connecting to endpoint: {}", self.endpoint);
             if first_time {
                 first_time = false;
             } else {
-                self.conn = ZmqMessageConnection::new(&self.endpoint);
+                let delay = self.reconnect_backoff.next_delay();
+                if self.endpoints.len() > 1 {
+                    let endpoint = self.endpoints.failover();
+                    info!("failing over to endpoint {} in {:?}", endpoint, delay);
+                }
+                thread::sleep(delay);
+                self.conn = self.active_connection();
             }
+            info!("connecting to endpoint: {}", self.endpoints.active());
             let (mut sender, receiver) = self.conn.create();
 
             if unregister.load(Ordering::SeqCst) {
@@ -204,145 +288,175 @@ impl<'a> TransactionProcessor<'a> {
             }
 
             // if registration is not succesful, retry
-            if !self.register(&sender, &unregister.clone()) {
+            if !self.register(&sender, &unregister) {
+                sender.close();
                 continue;
             }
+            info!("registered with endpoint: {}", self.endpoints.active());
+            self.reconnect_backoff.reset();
 
-            loop {
-                if unregister.load(Ordering::SeqCst) {
-                    self.unregister(&sender);
-                    restart = false;
-                    break;
-                }
-                match receiver.recv_timeout(Duration::from_millis(1000)) {
-                    Ok(r) => {
-                        // Check if we have a message
-                        let message = match r {
-                            Ok(message) => message,
-                            Err(ReceiveError::DisconnectedError) => {
-                                info!("Trying to Reconnect");
-                                break;
-                            }
-                            Err(err) => {
-                                error!("Error: {}", err);
-                                continue;
-                            }
-                        };
-
-                        trace!("Message: {}", message.get_correlation_id());
-
-                        match message.get_message_type() {
-                            Message_MessageType::TP_PROCESS_REQUEST => {
-                                let request: TpProcessRequest =
-                                    match ProtobufMessage::parse_from_bytes(message.get_content()) {
-                                        Ok(request) => request,
-                                        Err(err) => {
-                                            error!("Cannot parse TpProcessRequest: {}", err);
-                                            continue;
-                                        }
-                                    };
-
-                                let mut context = ZmqTransactionContext::new(
-                                    request.get_context_id(),
-                                    sender.clone(),
-                                );
-
-                                let mut response = TpProcessResponse::new();
-                                match self.handlers[0].apply(&request, &mut context) {
-                                    Ok(()) => {
-                                        info!("TP_PROCESS_REQUEST sending TpProcessResponse: OK");
-                                        response.set_status(TpProcessResponse_Status::OK);
-                                    }
-                                    Err(ApplyError::InvalidTransaction(msg)) => {
-                                        info!(
-                                            "TP_PROCESS_REQUEST sending TpProcessResponse: {}",
-                                            &msg
-                                        );
-                                        response.set_status(
-                                            TpProcessResponse_Status::INVALID_TRANSACTION,
-                                        );
-                                        response.set_message(msg);
-                                    }
-                                    Err(err) => {
-                                        info!(
-                                            "TP_PROCESS_REQUEST sending TpProcessResponse: {}",
-                                            err
-                                        );
-                                        response
-                                            .set_status(TpProcessResponse_Status::INTERNAL_ERROR);
-                                        response.set_message(err.to_string());
-                                    }
-                                };
+            if let Served::Stopped = self.serve(&sender, &receiver, &unregister) {
+                restart = false;
+            }
+            sender.close();
+        }
+    }
 
-                                let serialized = match response.write_to_bytes() {
-                                    Ok(serialized) => serialized,
-                                    Err(err) => {
-                                        error!("Serialization failed: {}", err);
-                                        continue;
-                                    }
-                                };
+    /// Connects the transaction processor over the given connection, registers its handlers and
+    /// routes requests to them until the connection is closed.
+    ///
+    /// Unlike `start`, the processor does not reconnect and does not install a Ctrl-C handler.
+    /// This is useful for running a processor over another transport, such as a `ReplayConnection`
+    /// replaying a recorded session.
+    pub fn start_with_connection<C, S>(&mut self, connection: &C)
+    where
+        C: MessageConnection<S>,
+        S: MessageSender + Clone + Send + 'static,
+    {
+        let (mut sender, receiver) = connection.create();
+        let unregister = AtomicBool::new(false);
+        if self.register(&sender, &unregister) {
+            self.serve(&sender, &receiver, &unregister);
+        }
+        sender.close();
+    }
 
-                                match sender.reply(
-                                    Message_MessageType::TP_PROCESS_RESPONSE,
-                                    message.get_correlation_id(),
-                                    &serialized,
-                                ) {
-                                    Ok(_) => (),
-                                    Err(SendError::DisconnectedError) => {
-                                        error!("DisconnectedError");
-                                        break;
-                                    }
-                                    Err(SendError::TimeoutError) => error!("TimeoutError"),
-                                    Err(SendError::UnknownError) => {
-                                        restart = false;
-                                        println!("UnknownError");
-                                        break;
-                                    }
-                                };
-                            }
-                            Message_MessageType::PING_REQUEST => {
-                                trace!("sending PingResponse");
-                                let response = PingResponse::new();
-                                let serialized = match response.write_to_bytes() {
-                                    Ok(serialized) => serialized,
+    /// Routes requests received from the validator to the handlers until the connection is lost
+    /// or the processor is stopped.
+    #[allow(clippy::cognitive_complexity)]
+    fn serve<S>(
+        &mut self,
+        sender: &S,
+        receiver: &MessageReceiver,
+        unregister: &AtomicBool,
+    ) -> Served
+    where
+        S: MessageSender + Clone + Send + 'static,
+    {
+        loop {
+            if unregister.load(Ordering::SeqCst) {
+                self.unregister(sender);
+                return Served::Stopped;
+            }
+            match receiver.recv_timeout(Duration::from_millis(1000)) {
+                Ok(r) => {
+                    // Check if we have a message
+                    let message = match r {
+                        Ok(message) => message,
+                        Err(ReceiveError::DisconnectedError) => {
+                            info!("Trying to Reconnect");
+                            return Served::Disconnected;
+                        }
+                        Err(err) => {
+                            error!("Error: {}", err);
+                            continue;
+                        }
+                    };
+
+                    trace!("Message: {}", message.get_correlation_id());
+
+                    match message.get_message_type() {
+                        Message_MessageType::TP_PROCESS_REQUEST => {
+                            let request: TpProcessRequest =
+                                match ProtobufMessage::parse_from_bytes(message.get_content()) {
+                                    Ok(request) => request,
                                     Err(err) => {
-                                        error!("Serialization failed: {}", err);
+                                        error!("Cannot parse TpProcessRequest: {}", err);
                                         continue;
                                     }
                                 };
-                                match sender.reply(
-                                    Message_MessageType::PING_RESPONSE,
-                                    message.get_correlation_id(),
-                                    &serialized,
-                                ) {
-                                    Ok(_) => (),
-                                    Err(SendError::DisconnectedError) => {
-                                        error!("DisconnectedError");
-                                        break;
-                                    }
-                                    Err(SendError::TimeoutError) => error!("TimeoutError"),
-                                    Err(SendError::UnknownError) => {
-                                        restart = false;
-                                        println!("UnknownError");
-                                        break;
-                                    }
-                                };
-                            }
-                            _ => {
-                                info!(
-                                    "Transaction Processor recieved invalid message type: {:?}",
-                                    message.get_message_type()
-                                );
-                            }
+
+                            let mut context = ZmqTransactionContext::new(
+                                request.get_context_id(),
+                                sender.clone(),
+                            );
+
+                            let mut response = TpProcessResponse::new();
+                            match self.handlers[0].apply(&request, &mut context) {
+                                Ok(()) => {
+                                    info!("TP_PROCESS_REQUEST sending TpProcessResponse: OK");
+                                    response.set_status(TpProcessResponse_Status::OK);
+                                }
+                                Err(ApplyError::InvalidTransaction(msg)) => {
+                                    info!("TP_PROCESS_REQUEST sending TpProcessResponse: {}", &msg);
+                                    response
+                                        .set_status(TpProcessResponse_Status::INVALID_TRANSACTION);
+                                    response.set_message(msg);
+                                }
+                                Err(err) => {
+                                    info!("TP_PROCESS_REQUEST sending TpProcessResponse: {}", err);
+                                    response.set_status(TpProcessResponse_Status::INTERNAL_ERROR);
+                                    response.set_message(err.to_string());
+                                }
+                            };
+
+                            let serialized = match response.write_to_bytes() {
+                                Ok(serialized) => serialized,
+                                Err(err) => {
+                                    error!("Serialization failed: {}", err);
+                                    continue;
+                                }
+                            };
+
+                            match sender.reply(
+                                Message_MessageType::TP_PROCESS_RESPONSE,
+                                message.get_correlation_id(),
+                                &serialized,
+                            ) {
+                                Ok(_) => (),
+                                Err(SendError::DisconnectedError) => {
+                                    error!("DisconnectedError");
+                                    return Served::Disconnected;
+                                }
+                                Err(SendError::TimeoutError) => error!("TimeoutError"),
+                                Err(SendError::QueueFullError) => error!("QueueFullError"),
+                                Err(SendError::UnknownError) => {
+                                    println!("UnknownError");
+                                    return Served::Stopped;
+                                }
+                            };
+                        }
+                        Message_MessageType::PING_REQUEST => {
+                            trace!("sending PingResponse");
+                            let response = PingResponse::new();
+                            let serialized = match response.write_to_bytes() {
+                                Ok(serialized) => serialized,
+                                Err(err) => {
+                                    error!("Serialization failed: {}", err);
+                                    continue;
+                                }
+                            };
+                            match sender.reply(
+                                Message_MessageType::PING_RESPONSE,
+                                message.get_correlation_id(),
+                                &serialized,
+                            ) {
+                                Ok(_) => (),
+                                Err(SendError::DisconnectedError) => {
+                                    error!("DisconnectedError");
+                                    return Served::Disconnected;
+                                }
+                                Err(SendError::TimeoutError) => error!("TimeoutError"),
+                                Err(SendError::QueueFullError) => error!("QueueFullError"),
+                                Err(SendError::UnknownError) => {
+                                    println!("UnknownError");
+                                    return Served::Stopped;
+                                }
+                            };
+                        }
+                        _ => {
+                            info!(
+                                "Transaction Processor recieved invalid message type: {:?}",
+                                message.get_message_type()
+                            );
                         }
-                    }
-                    Err(RecvTimeoutError::Timeout) => (),
-                    Err(err) => {
-                        error!("Error: {}", err);
                     }
                 }
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(err) => {
+                    error!("Error: {}", err);
+                }
             }
-            sender.close();
         }
     }
 }
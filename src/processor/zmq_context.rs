@@ -30,12 +30,12 @@ use crate::processor::handler::{ContextError, TransactionContext};
 use super::generate_correlation_id;
 
 #[derive(Clone)]
-pub struct ZmqTransactionContext {
+pub struct ZmqTransactionContext<S: MessageSender = ZmqMessageSender> {
     context_id: String,
-    sender: ZmqMessageSender,
+    sender: S,
 }
 
-impl ZmqTransactionContext {
+impl<S: MessageSender> ZmqTransactionContext<S> {
     /// Context provides an interface for getting, setting, and deleting
     /// validator state. All validator interactions by a handler should be
     /// through a Context instance.
@@ -44,7 +44,7 @@ impl ZmqTransactionContext {
     ///
     /// * `sender` - for client grpc communication
     /// * `context_id` - the context_id passed in from the validator
-    pub fn new(context_id: &str, sender: ZmqMessageSender) -> Self {
+    pub fn new(context_id: &str, sender: S) -> Self {
         ZmqTransactionContext {
             context_id: String::from(context_id),
             sender,
@@ -52,7 +52,7 @@ impl ZmqTransactionContext {
     }
 }
 
-impl TransactionContext for ZmqTransactionContext {
+impl<S: MessageSender> TransactionContext for ZmqTransactionContext<S> {
     /// get_state_entries queries the validator state for data at each of the
     /// addresses in the given list. The addresses that have been set
     /// are returned.
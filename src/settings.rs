@@ -0,0 +1,494 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Typed access to on-chain settings.
+//!
+//! A type implementing `Settings` declares the keys it reads and builds itself from their
+//! values, using the parsers and defaults of `SettingValues`. It can then be loaded by a
+//! consensus engine at a given block with `load_settings`, or through a `SettingsCache` which
+//! keeps the settings of recent blocks, and by a transaction processor from the settings state
+//! with `load_settings_from_state`.
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use sawtooth_sdk::settings::{SettingValues, Settings, SettingsError};
+//!
+//! struct DevmodeSettings {
+//!     min_wait_time: Duration,
+//!     max_wait_time: Duration,
+//! }
+//!
+//! impl Settings for DevmodeSettings {
+//!     fn keys() -> Vec<String> {
+//!         vec![
+//!             "sawtooth.consensus.min_wait_time".into(),
+//!             "sawtooth.consensus.max_wait_time".into(),
+//!         ]
+//!     }
+//!
+//!     fn from_values(values: &SettingValues) -> Result<Self, SettingsError> {
+//!         Ok(DevmodeSettings {
+//!             min_wait_time: values.get_seconds("sawtooth.consensus.min_wait_time", 0)?,
+//!             max_wait_time: values.get_seconds("sawtooth.consensus.max_wait_time", 0)?,
+//!         })
+//!     }
+//! }
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use protobuf::Message as ProtobufMessage;
+use sha2::{Digest, Sha256};
+
+use crate::consensus::engine::BlockId;
+use crate::consensus::service::Service;
+use crate::messages::setting::Setting;
+use crate::processor::handler::TransactionContext;
+
+/// The namespace of the settings transaction family's state
+pub const SETTINGS_NAMESPACE: &str = "000000";
+
+const MAX_KEY_PARTS: usize = 4;
+const ADDRESS_PART_SIZE: usize = 16;
+
+#[derive(Debug)]
+pub enum SettingsError {
+    /// Returned when a setting has a value which cannot be parsed
+    InvalidSetting(String),
+    /// Returned when the settings cannot be read from the validator
+    ReadError(String),
+}
+
+impl error::Error for SettingsError {}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SettingsError::InvalidSetting(ref s) => write!(f, "InvalidSetting: {}", s),
+            SettingsError::ReadError(ref s) => write!(f, "ReadError: {}", s),
+        }
+    }
+}
+
+/// A set of settings read together and parsed into a typed value
+pub trait Settings: Sized {
+    /// The keys of the settings to read
+    fn keys() -> Vec<String>;
+
+    /// Build the settings from the values of the keys that are set
+    fn from_values(values: &SettingValues) -> Result<Self, SettingsError>;
+}
+
+/// The raw values of the settings that are set, with typed getters which fall back to a default
+/// when a setting is not set
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SettingValues {
+    values: HashMap<String, String>,
+}
+
+impl SettingValues {
+    pub fn new(values: HashMap<String, String>) -> Self {
+        SettingValues { values }
+    }
+
+    /// Returns the raw value of a setting, if it is set
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    /// Returns the value of a setting parsed with `parse`, or `default` if it is not set
+    pub fn get_with<T, F>(&self, key: &str, default: T, parse: F) -> Result<T, SettingsError>
+    where
+        F: FnOnce(&str) -> Result<T, String>,
+    {
+        match self.get(key) {
+            Some(value) => parse(value).map_err(|err| {
+                SettingsError::InvalidSetting(format!(
+                    "Invalid value {:?} for {}: {}",
+                    value, key, err
+                ))
+            }),
+            None => Ok(default),
+        }
+    }
+
+    /// Returns the value of a setting parsed with `FromStr`, such as an integer, or `default` if
+    /// it is not set
+    pub fn get_parsed<T>(&self, key: &str, default: T) -> Result<T, SettingsError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.get_with(key, default, |value| {
+            value.trim().parse().map_err(|err: T::Err| err.to_string())
+        })
+    }
+
+    /// Returns the value of a setting, or `default` if it is not set
+    pub fn get_string(&self, key: &str, default: &str) -> String {
+        self.get(key).unwrap_or(default).to_string()
+    }
+
+    /// Returns a boolean setting, given as `true` or `false`, or `default` if it is not set
+    pub fn get_bool(&self, key: &str, default: bool) -> Result<bool, SettingsError> {
+        self.get_parsed(key, default)
+    }
+
+    /// Returns a duration setting given as a whole number of seconds
+    pub fn get_seconds(&self, key: &str, default: u64) -> Result<Duration, SettingsError> {
+        self.get_parsed(key, default).map(Duration::from_secs)
+    }
+
+    /// Returns a duration setting given as a whole number of milliseconds
+    pub fn get_millis(&self, key: &str, default: u64) -> Result<Duration, SettingsError> {
+        self.get_parsed(key, default).map(Duration::from_millis)
+    }
+
+    /// Returns a list setting, such as a list of public keys, or `default` if it is not set.
+    ///
+    /// The list may be given either as comma-separated values or as a JSON array of strings.
+    pub fn get_list(&self, key: &str, default: &[&str]) -> Result<Vec<String>, SettingsError> {
+        self.get_with(
+            key,
+            default.iter().map(|item| item.to_string()).collect(),
+            parse_list,
+        )
+    }
+}
+
+fn parse_list(value: &str) -> Result<Vec<String>, String> {
+    let value = value.trim();
+    let (items, quoted) = match value.strip_prefix('[') {
+        Some(array) => (
+            array
+                .strip_suffix(']')
+                .ok_or_else(|| "unterminated list".to_string())?,
+            true,
+        ),
+        None => (value, false),
+    };
+
+    items
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            if !quoted {
+                Ok(item.to_string())
+            } else if item.len() >= 2 && item.starts_with('"') && item.ends_with('"') {
+                Ok(item[1..item.len() - 1].to_string())
+            } else {
+                Err(format!("list item {} is not a string", item))
+            }
+        })
+        .collect()
+}
+
+/// Load settings as of the given block through a consensus engine's service
+pub fn load_settings<S: Settings>(
+    service: &mut dyn Service,
+    block_id: BlockId,
+) -> Result<S, SettingsError> {
+    let values = service
+        .get_settings(block_id, S::keys())
+        .map_err(|err| SettingsError::ReadError(err.to_string()))?;
+    S::from_values(&SettingValues::new(values))
+}
+
+/// Returns the state address of a setting
+pub fn setting_address(key: &str) -> String {
+    let mut parts = key.splitn(MAX_KEY_PARTS, '.').collect::<Vec<_>>();
+    parts.resize(MAX_KEY_PARTS, "");
+
+    let mut address = String::from(SETTINGS_NAMESPACE);
+    for part in parts {
+        address.push_str(&hex::encode(Sha256::digest(part.as_bytes()))[..ADDRESS_PART_SIZE]);
+    }
+    address
+}
+
+/// Load settings from the settings state, as a transaction processor does
+pub fn load_settings_from_state<S: Settings>(
+    context: &dyn TransactionContext,
+) -> Result<S, SettingsError> {
+    let keys = S::keys();
+    let addresses = keys
+        .iter()
+        .map(|key| setting_address(key))
+        .collect::<Vec<_>>();
+    let entries = context
+        .get_state_entries(&addresses)
+        .map_err(|err| SettingsError::ReadError(err.to_string()))?;
+
+    let mut values = HashMap::new();
+    for (_, data) in entries {
+        let setting: Setting = ProtobufMessage::parse_from_bytes(&data)
+            .map_err(|err| SettingsError::ReadError(format!("Invalid setting entry: {}", err)))?;
+        // Several keys may share an address, so only keep the entries of the requested keys
+        for entry in setting.get_entries() {
+            if keys.iter().any(|key| key == entry.get_key()) {
+                values.insert(entry.get_key().to_string(), entry.get_value().to_string());
+            }
+        }
+    }
+    S::from_values(&SettingValues::new(values))
+}
+
+/// Settings loaded at recent blocks, so that they are read from the validator once per block
+pub struct SettingsCache<S> {
+    capacity: usize,
+    settings: HashMap<BlockId, S>,
+    // The blocks whose settings are cached, oldest first
+    blocks: VecDeque<BlockId>,
+}
+
+impl<S: Settings> SettingsCache<S> {
+    /// Create a cache keeping the settings of up to `capacity` blocks
+    pub fn new(capacity: usize) -> Self {
+        SettingsCache {
+            capacity: capacity.max(1),
+            settings: HashMap::new(),
+            blocks: VecDeque::new(),
+        }
+    }
+
+    /// Returns the settings as of the given block, loading them through `service` unless they
+    /// are cached
+    pub fn get(&mut self, service: &mut dyn Service, block_id: &[u8]) -> Result<&S, SettingsError> {
+        if !self.settings.contains_key(block_id) {
+            let settings = load_settings(service, block_id.to_vec())?;
+            if self.blocks.len() == self.capacity {
+                if let Some(oldest) = self.blocks.pop_front() {
+                    self.settings.remove(&oldest);
+                }
+            }
+            self.blocks.push_back(block_id.to_vec());
+            self.settings.insert(block_id.to_vec(), settings);
+        }
+        Ok(&self.settings[block_id])
+    }
+
+    /// Forget the cached settings
+    pub fn clear(&mut self) {
+        self.settings.clear();
+        self.blocks.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    use crate::consensus::testing::{ServiceCall, TestService};
+    use crate::messages::setting::Setting_Entry;
+    use crate::processor::handler::ContextError;
+
+    #[derive(Debug, PartialEq)]
+    struct TestSettings {
+        block_publishing_delay: Duration,
+        max_log_size: u64,
+        members: Vec<String>,
+        enabled: bool,
+    }
+
+    impl Settings for TestSettings {
+        fn keys() -> Vec<String> {
+            vec![
+                "sawtooth.consensus.block_publishing_delay".into(),
+                "sawtooth.consensus.max_log_size".into(),
+                "sawtooth.consensus.members".into(),
+                "sawtooth.consensus.enabled".into(),
+            ]
+        }
+
+        fn from_values(values: &SettingValues) -> Result<Self, SettingsError> {
+            Ok(TestSettings {
+                block_publishing_delay: values
+                    .get_millis("sawtooth.consensus.block_publishing_delay", 1000)?,
+                max_log_size: values.get_parsed("sawtooth.consensus.max_log_size", 10000)?,
+                members: values.get_list("sawtooth.consensus.members", &[])?,
+                enabled: values.get_bool("sawtooth.consensus.enabled", true)?,
+            })
+        }
+    }
+
+    #[test]
+    fn parse_values_and_defaults() {
+        let settings = TestSettings::from_values(&SettingValues::new(
+            vec![
+                (
+                    "sawtooth.consensus.block_publishing_delay".to_string(),
+                    "250".to_string(),
+                ),
+                (
+                    "sawtooth.consensus.members".to_string(),
+                    r#"["02a1", "03b2"]"#.to_string(),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        ))
+        .unwrap();
+        assert_eq!(
+            settings,
+            TestSettings {
+                block_publishing_delay: Duration::from_millis(250),
+                max_log_size: 10000,
+                members: vec!["02a1".into(), "03b2".into()],
+                enabled: true,
+            }
+        );
+
+        assert_eq!(parse_list("a, b,,c").unwrap(), vec!["a", "b", "c"]);
+        assert_eq!(parse_list("[]").unwrap(), Vec::<String>::new());
+        assert!(parse_list("[a]").is_err());
+
+        let values = SettingValues::new(
+            vec![(
+                "sawtooth.consensus.max_log_size".to_string(),
+                "-1".to_string(),
+            )]
+            .into_iter()
+            .collect(),
+        );
+        match TestSettings::from_values(&values) {
+            Err(SettingsError::InvalidSetting(_)) => (),
+            res => panic!("Expected InvalidSetting, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn cache_per_block() {
+        let (mut service, _updates) = TestService::new(b"local".to_vec());
+        service.set_setting("sawtooth.consensus.max_log_size", "5");
+        let genesis = service.chain_head();
+
+        let mut cache = SettingsCache::<TestSettings>::new(1);
+        assert_eq!(
+            cache
+                .get(&mut service, &genesis.block_id)
+                .unwrap()
+                .max_log_size,
+            5
+        );
+        cache.get(&mut service, &genesis.block_id).unwrap();
+
+        let block = service.build_block(&genesis.block_id, b"peer", b"");
+        service.receive_block(block.clone());
+        cache.get(&mut service, &block.block_id).unwrap();
+        cache.get(&mut service, &genesis.block_id).unwrap();
+
+        let get_settings = service
+            .calls()
+            .into_iter()
+            .filter(|call| matches!(call, ServiceCall::GetSettings(_, _)))
+            .count();
+        assert_eq!(get_settings, 3);
+    }
+
+    #[test]
+    fn settings_addresses() {
+        assert_eq!(
+            setting_address("sawtooth.settings.vote.authorized_keys"),
+            "000000a87cb5eafdcca6a8cde0fb0dec1400c5ab274474a6aa82c12840f169a04216b7"
+        );
+        assert_eq!(setting_address("a").len(), 70);
+    }
+
+    /// A context holding the settings state
+    struct SettingsState {
+        state: RefCell<HashMap<String, Vec<u8>>>,
+    }
+
+    impl SettingsState {
+        fn set(&self, key: &str, value: &str) {
+            let mut entry = Setting_Entry::new();
+            entry.set_key(key.into());
+            entry.set_value(value.into());
+            let mut setting = Setting::new();
+            setting.mut_entries().push(entry);
+            self.state
+                .borrow_mut()
+                .insert(setting_address(key), setting.write_to_bytes().unwrap());
+        }
+    }
+
+    impl TransactionContext for SettingsState {
+        fn get_state_entries(
+            &self,
+            addresses: &[String],
+        ) -> Result<Vec<(String, Vec<u8>)>, ContextError> {
+            let state = self.state.borrow();
+            Ok(addresses
+                .iter()
+                .filter_map(|address| {
+                    state
+                        .get(address)
+                        .map(|data| (address.clone(), data.clone()))
+                })
+                .collect())
+        }
+
+        fn set_state_entries(&self, entries: Vec<(String, Vec<u8>)>) -> Result<(), ContextError> {
+            self.state.borrow_mut().extend(entries);
+            Ok(())
+        }
+
+        fn delete_state_entries(&self, addresses: &[String]) -> Result<Vec<String>, ContextError> {
+            let mut state = self.state.borrow_mut();
+            Ok(addresses
+                .iter()
+                .filter(|address| state.remove(*address).is_some())
+                .cloned()
+                .collect())
+        }
+
+        fn add_receipt_data(&self, _data: &[u8]) -> Result<(), ContextError> {
+            Ok(())
+        }
+
+        fn add_event(
+            &self,
+            _event_type: String,
+            _attributes: Vec<(String, String)>,
+            _data: &[u8],
+        ) -> Result<(), ContextError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn load_from_state() {
+        let context = SettingsState {
+            state: RefCell::new(HashMap::new()),
+        };
+        context.set("sawtooth.consensus.enabled", "false");
+        context.set("sawtooth.consensus.members", "02a1,03b2");
+
+        let settings: TestSettings = load_settings_from_state(&context).unwrap();
+        assert!(!settings.enabled);
+        assert_eq!(settings.members, vec!["02a1", "03b2"]);
+        assert_eq!(settings.max_log_size, 10000);
+    }
+}
@@ -0,0 +1,508 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Hierarchical deterministic (BIP32) derivation of secp256k1 keys.
+//!
+//! A tree of keys is derived from a master seed, which can be given directly or imported from a
+//! BIP39 mnemonic. Paths are written as in BIP32, such as `m/44'/0'/0'/0/7`, where `'` (or `h`)
+//! marks a hardened index. Public keys can be derived along non-hardened paths from an
+//! `ExtendedPublicKey` alone, without any private key.
+//!
+//! ```
+//! use sawtooth_sdk::signing::hd::ExtendedPrivateKey;
+//! use sawtooth_sdk::signing::secp256k1::Secp256k1Context;
+//! use sawtooth_sdk::signing::CryptoFactory;
+//!
+//! let master = ExtendedPrivateKey::from_mnemonic(
+//!     "abandon abandon abandon abandon abandon abandon \
+//!      abandon abandon abandon abandon abandon about",
+//!     "",
+//! )
+//! .unwrap();
+//! let user_key = master.derive_path("m/0'/42").unwrap();
+//!
+//! let context = Secp256k1Context::new();
+//! let signer = CryptoFactory::new(&context).new_signer(user_key.private_key());
+//! ```
+
+use std::fmt;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha512;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::signing::fmt_private_key;
+use crate::signing::secp256k1::{Secp256k1PrivateKey, Secp256k1PublicKey};
+use crate::signing::Error;
+use crate::signing::PrivateKey;
+
+/// The first hardened child index; indexes from here on are hardened
+pub const HARDENED: u32 = 0x8000_0000;
+
+/// The key used to derive the master key from a seed
+const MASTER_KEY: &[u8] = b"Bitcoin seed";
+const MIN_SEED_SIZE: usize = 16;
+const MAX_SEED_SIZE: usize = 64;
+const CHAIN_CODE_SIZE: usize = 32;
+
+/// Returns the hardened form of child index `index`
+pub fn hardened(index: u32) -> u32 {
+    index | HARDENED
+}
+
+/// An extended private key: a secp256k1 private key, its chain code, and its position in the
+/// tree
+pub struct ExtendedPrivateKey {
+    private_key: Secp256k1PrivateKey,
+    chain_code: [u8; CHAIN_CODE_SIZE],
+    depth: u8,
+    child_number: u32,
+}
+
+impl ExtendedPrivateKey {
+    /// Derive the master key from a seed of 16 to 64 bytes
+    pub fn from_seed(seed: &[u8]) -> Result<Self, Error> {
+        if seed.len() < MIN_SEED_SIZE || seed.len() > MAX_SEED_SIZE {
+            return Err(Error::KeyGenError(format!(
+                "seed must be {} to {} bytes, not {}",
+                MIN_SEED_SIZE,
+                MAX_SEED_SIZE,
+                seed.len()
+            )));
+        }
+
+        let (key, chain_code) = hmac_sha512(MASTER_KEY, &[seed])?;
+        Ok(ExtendedPrivateKey {
+            private_key: Secp256k1PrivateKey::from_bytes(&key[..])
+                .map_err(|_| Error::KeyGenError("seed produces an invalid master key".into()))?,
+            chain_code: *chain_code,
+            depth: 0,
+            child_number: 0,
+        })
+    }
+
+    /// Derive the master key from a BIP39 mnemonic phrase and optional passphrase
+    pub fn from_mnemonic(phrase: &str, passphrase: &str) -> Result<Self, Error> {
+        let mnemonic = bip39::Mnemonic::parse(phrase)
+            .map_err(|err| Error::ParseError(format!("invalid mnemonic: {}", err)))?;
+        let seed = Zeroizing::new(mnemonic.to_seed(passphrase));
+        Self::from_seed(&*seed)
+    }
+
+    /// Derive the child key at `index`, which is hardened if it is `HARDENED` or above
+    pub fn derive_child(&self, index: u32) -> Result<Self, Error> {
+        let (tweak, chain_code) = if index >= HARDENED {
+            hmac_sha512(
+                &self.chain_code,
+                &[&[0u8], self.private_key.as_slice(), &index.to_be_bytes()],
+            )?
+        } else {
+            hmac_sha512(
+                &self.chain_code,
+                &[&self.public_key_point()?.serialize(), &index.to_be_bytes()],
+            )?
+        };
+
+        let mut parent = secp256k1::SecretKey::from_slice(self.private_key.as_slice())?;
+        let child = parent.add_tweak(&scalar(&tweak)?);
+        parent.non_secure_erase();
+        let mut child = child?;
+        let child_bytes = Zeroizing::new(child.secret_bytes());
+        child.non_secure_erase();
+
+        Ok(ExtendedPrivateKey {
+            private_key: Secp256k1PrivateKey::from_bytes(&child_bytes[..])?,
+            chain_code: *chain_code,
+            depth: self.child_depth()?,
+            child_number: index,
+        })
+    }
+
+    /// Derive the key at a path such as `m/44'/0'/0'/0/7`, relative to this key
+    pub fn derive_path(&self, path: &str) -> Result<Self, Error> {
+        parse_path(path)?
+            .into_iter()
+            .try_fold(self.clone_key(), |key, index| key.derive_child(index))
+    }
+
+    /// Returns the private key
+    pub fn private_key(&self) -> &Secp256k1PrivateKey {
+        &self.private_key
+    }
+
+    /// Returns the compressed public key
+    pub fn public_key(&self) -> Result<Secp256k1PublicKey, Error> {
+        Secp256k1PublicKey::from_bytes(&self.public_key_point()?.serialize())
+    }
+
+    /// Returns the extended public key, from which the public keys of non-hardened children
+    /// can be derived
+    pub fn to_extended_public_key(&self) -> Result<ExtendedPublicKey, Error> {
+        Ok(ExtendedPublicKey {
+            public_key: self.public_key_point()?,
+            chain_code: self.chain_code,
+            depth: self.depth,
+            child_number: self.child_number,
+        })
+    }
+
+    pub fn chain_code(&self) -> &[u8] {
+        &self.chain_code
+    }
+
+    /// Returns the number of derivations between the master key and this key
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    /// Returns the index this key was derived at, or 0 for the master key
+    pub fn child_number(&self) -> u32 {
+        self.child_number
+    }
+
+    fn public_key_point(&self) -> Result<secp256k1::PublicKey, Error> {
+        let mut sk = secp256k1::SecretKey::from_slice(self.private_key.as_slice())?;
+        let public_key = secp256k1::PublicKey::from_secret_key(secp256k1::SECP256K1, &sk);
+        sk.non_secure_erase();
+        Ok(public_key)
+    }
+
+    fn child_depth(&self) -> Result<u8, Error> {
+        self.depth
+            .checked_add(1)
+            .ok_or_else(|| Error::KeyGenError("maximum derivation depth reached".into()))
+    }
+
+    fn clone_key(&self) -> Self {
+        ExtendedPrivateKey {
+            private_key: Secp256k1PrivateKey::from_bytes(self.private_key.as_slice())
+                .expect("an extended key always holds a valid private key"),
+            chain_code: self.chain_code,
+            depth: self.depth,
+            child_number: self.child_number,
+        }
+    }
+}
+
+impl Drop for ExtendedPrivateKey {
+    fn drop(&mut self) {
+        self.chain_code.zeroize();
+    }
+}
+
+impl fmt::Debug for ExtendedPrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_private_key("ExtendedPrivateKey", f)
+    }
+}
+
+/// An extended public key: a secp256k1 public key, its chain code, and its position in the tree
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtendedPublicKey {
+    public_key: secp256k1::PublicKey,
+    chain_code: [u8; CHAIN_CODE_SIZE],
+    depth: u8,
+    child_number: u32,
+}
+
+impl ExtendedPublicKey {
+    /// Derive the public key of the non-hardened child at `index`
+    pub fn derive_child(&self, index: u32) -> Result<Self, Error> {
+        if index >= HARDENED {
+            return Err(Error::KeyGenError(
+                "hardened keys cannot be derived from a public key".into(),
+            ));
+        }
+
+        let (tweak, chain_code) = hmac_sha512(
+            &self.chain_code,
+            &[&self.public_key.serialize(), &index.to_be_bytes()],
+        )?;
+        let public_key = self
+            .public_key
+            .add_exp_tweak(secp256k1::SECP256K1, &scalar(&tweak)?)?;
+
+        Ok(ExtendedPublicKey {
+            public_key,
+            chain_code: *chain_code,
+            depth: self
+                .depth
+                .checked_add(1)
+                .ok_or_else(|| Error::KeyGenError("maximum derivation depth reached".into()))?,
+            child_number: index,
+        })
+    }
+
+    /// Derive the public key at a non-hardened path such as `m/0/7`, relative to this key
+    pub fn derive_path(&self, path: &str) -> Result<Self, Error> {
+        parse_path(path)?
+            .into_iter()
+            .try_fold(self.clone(), |key, index| key.derive_child(index))
+    }
+
+    /// Returns the compressed public key
+    pub fn public_key(&self) -> Result<Secp256k1PublicKey, Error> {
+        Secp256k1PublicKey::from_bytes(&self.public_key.serialize())
+    }
+
+    pub fn chain_code(&self) -> &[u8] {
+        &self.chain_code
+    }
+
+    /// Returns the number of derivations between the master key and this key
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    /// Returns the index this key was derived at, or 0 for the master key
+    pub fn child_number(&self) -> u32 {
+        self.child_number
+    }
+}
+
+/// Parse a derivation path into its child indexes. The leading `m` is optional.
+fn parse_path(path: &str) -> Result<Vec<u32>, Error> {
+    let mut components = path.split('/').peekable();
+    if components.peek() == Some(&"m") {
+        components.next();
+    }
+
+    components
+        .map(|component| {
+            let (number, hardened_index) = match component
+                .strip_suffix('\'')
+                .or_else(|| component.strip_suffix('h'))
+            {
+                Some(number) => (number, true),
+                None => (component, false),
+            };
+            let index: u32 = number
+                .parse()
+                .ok()
+                .filter(|index| *index < HARDENED)
+                .ok_or_else(|| {
+                    Error::ParseError(format!("invalid path component {:?}", component))
+                })?;
+            Ok(if hardened_index {
+                hardened(index)
+            } else {
+                index
+            })
+        })
+        .collect()
+}
+
+/// One half of an HMAC-SHA512 output
+type Half = Zeroizing<[u8; CHAIN_CODE_SIZE]>;
+
+/// Computes HMAC-SHA512 over the concatenated `data`, returning the left half, used as a key
+/// or tweak, and the right half, used as the chain code
+fn hmac_sha512(key: &[u8], data: &[&[u8]]) -> Result<(Half, Half), Error> {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key)
+        .map_err(|err| Error::KeyGenError(format!("invalid HMAC key: {}", err)))?;
+    for part in data {
+        mac.update(part);
+    }
+    let mut output = mac.finalize().into_bytes();
+
+    let mut left = Zeroizing::new([0u8; CHAIN_CODE_SIZE]);
+    let mut right = Zeroizing::new([0u8; CHAIN_CODE_SIZE]);
+    left.copy_from_slice(&output[..CHAIN_CODE_SIZE]);
+    right.copy_from_slice(&output[CHAIN_CODE_SIZE..]);
+    output.as_mut_slice().zeroize();
+    Ok((left, right))
+}
+
+fn scalar(bytes: &[u8; CHAIN_CODE_SIZE]) -> Result<secp256k1::Scalar, Error> {
+    // BIP32 skips to the next index in this case, which has a probability below 2^-127
+    secp256k1::Scalar::from_be_bytes(*bytes)
+        .map_err(|_| Error::KeyGenError("derived tweak is out of range".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::signing::PublicKey;
+
+    /// BIP32 test vector 1: (path, chain code, private key, public key)
+    static VECTOR1_SEED: &str = "000102030405060708090a0b0c0d0e0f";
+    static VECTOR1: &[(&str, &str, &str, &str)] = &[
+        (
+            "m",
+            "873dff81c02f525623fd1fe5167eac3a55a049de3d314bb42ee227ffed37d508",
+            "e8f32e723decf4051aefac8e2c93c9c5b214313817cdb01a1494b917c8436b35",
+            "0339a36013301597daef41fbe593a02cc513d0b55527ec2df1050e2e8ff49c85c2",
+        ),
+        (
+            "m/0'",
+            "47fdacbd0f1097043b78c63c20c34ef4ed9a111d980047ad16282c7ae6236141",
+            "edb2e14f9ee77d26dd93b4ecede8d16ed408ce149b6cd80b0715a2d911a0afea",
+            "035a784662a4a20a65bf6aab9ae98a6c068a81c52e4b032c0fb5400c706cfccc56",
+        ),
+        (
+            "m/0'/1",
+            "2a7857631386ba23dacac34180dd1983734e444fdbf774041578e9b6adb37c19",
+            "3c6cb8d0f6a264c91ea8b5030fadaa8e538b020f0a387421a12de9319dc93368",
+            "03501e454bf00751f24b1b489aa925215d66af2234e3891c3b21a52bedb3cd711c",
+        ),
+        (
+            "m/0'/1/2'",
+            "04466b9cc8e161e966409ca52986c584f07e9dc81f735db683c3ff6ec7b1503f",
+            "cbce0d719ecf7431d88e6a89fa1483e02e35092af60c042b1df2ff59fa424dca",
+            "0357bfe1e341d01c69fe5654309956cbea516822fba8a601743a012a7896ee8dc2",
+        ),
+        (
+            "m/0'/1/2'/2",
+            "cfb71883f01676f587d023cc53a35bc7f88f724b1f8c2892ac1275ac822a3edd",
+            "0f479245fb19a38a1954c5c7c0ebab2f9bdfd96a17563ef28a6a4b1a2a764ef4",
+            "02e8445082a72f29b75ca48748a914df60622a609cacfce8ed0e35804560741d29",
+        ),
+        (
+            "m/0'/1/2'/2/1000000000",
+            "c783e67b921d2beb8f6b389cc646d7263b4145701dadd2161548a8b078e65e9e",
+            "471b76e389e528d6de6d816857e012c5455051cad6660850e58372a6c3e6e7c8",
+            "022a471424da5e657499d1ff51cb43c47481a03b1e77f951fe64cec9f5a48f7011",
+        ),
+    ];
+
+    /// BIP32 test vector 2
+    static VECTOR2_SEED: &str = "fffcf9f6f3f0edeae7e4e1dedbd8d5d2cfccc9c6c3c0bdbab7b4b1aeaba8a5a29f9c999693908d8a8784817e7b7875726f6c696663605d5a5754514e4b484542";
+    static VECTOR2: &[(&str, &str, &str, &str)] = &[
+        (
+            "m",
+            "60499f801b896d83179a4374aeb7822aaeaceaa0db1f85ee3e904c4defbd9689",
+            "4b03d6fc340455b363f51020ad3ecca4f0850280cf436c70c727923f6db46c3e",
+            "03cbcaa9c98c877a26977d00825c956a238e8dddfbd322cce4f74b0b5bd6ace4a7",
+        ),
+        (
+            "m/0",
+            "f0909affaa7ee7abe5dd4e100598d4dc53cd709d5a5c2cac40e7412f232f7c9c",
+            "abe74a98f6c7eabee0428f53798f0ab8aa1bd37873999041703c742f15ac7e1e",
+            "02fc9e5af0ac8d9b3cecfe2a888e2117ba3d089d8585886c9c826b6b22a98d12ea",
+        ),
+        (
+            "m/0/2147483647'",
+            "be17a268474a6bb9c61e1d720cf6215e2a88c5406c4aee7b38547f585c9a37d9",
+            "877c779ad9687164e9c2f4f0f4ff0340814392330693ce95a58fe18fd52e6e93",
+            "03c01e7425647bdefa82b12d9bad5e3e6865bee0502694b94ca58b666abc0a5c3b",
+        ),
+        (
+            "m/0/2147483647'/1",
+            "f366f48f1ea9f2d1d3fe958c95ca84ea18e4c4ddb9366c336c927eb246fb38cb",
+            "704addf544a06e5ee4bea37098463c23613da32020d604506da8c0518e1da4b7",
+            "03a7d1d856deb74c508e05031f9895dab54626251b3806e16b4bd12e781a7df5b9",
+        ),
+        (
+            "m/0/2147483647'/1/2147483646'",
+            "637807030d55d01f9a0cb3a7839515d796bd07706386a6eddf06cc29a65a0e29",
+            "f1c7c871a54a804afe328b4c83a1c33b8e5ff48f5087273f04efa83b247d6a2d",
+            "02d2b36900396c9282fa14628566582f206a5dd0bcc8d5e892611806cafb0301f0",
+        ),
+        (
+            "m/0/2147483647'/1/2147483646'/2",
+            "9452b549be8cea3ecb7a84bec10dcfd94afe4d129ebfd3b3cb58eedf394ed271",
+            "bb7d39bdb83ecf58f2fd82b6d918341cbef428661ef01ab97c28a4842125ac23",
+            "024d902e1a2fc7a8755ab5b694c575fce742c48d9ff192e63df5193e4c7afe1f9c",
+        ),
+    ];
+
+    fn check_vector(seed: &str, vector: &[(&str, &str, &str, &str)]) {
+        let master = ExtendedPrivateKey::from_seed(&hex::decode(seed).unwrap()).unwrap();
+        for (depth, (path, chain_code, private_key, public_key)) in vector.iter().enumerate() {
+            let key = master.derive_path(path).unwrap();
+            assert_eq!(hex::encode(key.chain_code()), *chain_code, "{}", path);
+            assert_eq!(key.private_key().as_hex(), *private_key, "{}", path);
+            assert_eq!(key.public_key().unwrap().as_hex(), *public_key, "{}", path);
+            assert_eq!(usize::from(key.depth()), depth, "{}", path);
+        }
+    }
+
+    #[test]
+    fn bip32_vector1() {
+        check_vector(VECTOR1_SEED, VECTOR1);
+    }
+
+    #[test]
+    fn bip32_vector2() {
+        check_vector(VECTOR2_SEED, VECTOR2);
+    }
+
+    #[test]
+    fn public_derivation() {
+        let master = ExtendedPrivateKey::from_seed(&hex::decode(VECTOR2_SEED).unwrap()).unwrap();
+        let account = master.derive_path("m/0/2147483647'").unwrap();
+
+        // Deriving the public key from the parent's public key gives the same key
+        let public = account
+            .to_extended_public_key()
+            .unwrap()
+            .derive_path("1")
+            .unwrap();
+        let private = account.derive_path("1").unwrap();
+        assert_eq!(public, private.to_extended_public_key().unwrap());
+        assert_eq!(public.public_key().unwrap().as_hex(), VECTOR2[3].3);
+
+        assert!(public.derive_child(hardened(0)).is_err());
+    }
+
+    #[test]
+    fn mnemonic_import() {
+        // From the BIP39 test vectors published with the reference implementation
+        let master = ExtendedPrivateKey::from_mnemonic(
+            "abandon abandon abandon abandon abandon abandon \
+             abandon abandon abandon abandon abandon about",
+            "TREZOR",
+        )
+        .unwrap();
+        let seed = hex::decode(
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e5349553\
+             1f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04",
+        )
+        .unwrap();
+        let from_seed = ExtendedPrivateKey::from_seed(&seed).unwrap();
+        assert_eq!(master.private_key(), from_seed.private_key());
+        assert_eq!(master.chain_code(), from_seed.chain_code());
+
+        // A bad checksum is rejected
+        assert!(ExtendedPrivateKey::from_mnemonic(
+            "abandon abandon abandon abandon abandon abandon \
+             abandon abandon abandon abandon abandon abandon",
+            "",
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn path_parsing() {
+        assert_eq!(parse_path("m").unwrap(), Vec::<u32>::new());
+        assert_eq!(
+            parse_path("m/44'/0h/7").unwrap(),
+            vec![hardened(44), hardened(0), 7]
+        );
+        assert_eq!(parse_path("1/2").unwrap(), vec![1, 2]);
+        assert!(parse_path("m/x").is_err());
+        assert!(parse_path("m/2147483648").is_err());
+        assert!(parse_path("m//1").is_err());
+    }
+
+    #[test]
+    fn seed_length() {
+        assert!(ExtendedPrivateKey::from_seed(&[0; 15]).is_err());
+        assert!(ExtendedPrivateKey::from_seed(&[0; 65]).is_err());
+    }
+}
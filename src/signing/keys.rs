@@ -0,0 +1,534 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Loading and saving secp256k1 keys in the files used by the Sawtooth CLI.
+//!
+//! A key named `name` is stored in a keys directory as `<name>.priv`, holding the private key as
+//! hex, and `<name>.pub`, holding the public key as hex. User keys are kept in
+//! `~/.sawtooth/keys`, and validator keys in `/etc/sawtooth/keys`, or in `$SAWTOOTH_HOME/keys`
+//! when `SAWTOOTH_HOME` is set.
+//!
+//! Private key files are written readable by their owner only, and on Unix, private key files
+//! which other users can access are refused.
+//!
+//! ```no_run
+//! use sawtooth_sdk::signing::keys::KeyDir;
+//!
+//! let key_pair = KeyDir::user().unwrap().load("alice").unwrap();
+//! ```
+
+use std::env;
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use zeroize::Zeroizing;
+
+use crate::signing;
+use crate::signing::secp256k1::{Secp256k1Context, Secp256k1PrivateKey, Secp256k1PublicKey};
+use crate::signing::{Context, PrivateKey, PublicKey};
+
+const PRIVATE_KEY_EXTENSION: &str = "priv";
+const PUBLIC_KEY_EXTENSION: &str = "pub";
+
+/// The name of the validator's key
+pub const VALIDATOR_KEY_NAME: &str = "validator";
+
+pub(crate) const PRIVATE_KEY_MODE: u32 = 0o600;
+const PUBLIC_KEY_MODE: u32 = 0o644;
+#[cfg(unix)]
+const KEY_DIR_MODE: u32 = 0o755;
+/// The permission bits which allow other users to access a file
+#[cfg(unix)]
+const OTHER_USERS_MODE: u32 = 0o007;
+
+#[derive(Debug)]
+pub enum KeyError {
+    /// Returned when the home directory, which holds the user keys directory, is unknown
+    NoHomeDirectory,
+    /// Returned when a key file cannot be read or written
+    IoError(PathBuf, io::Error),
+    /// Returned when other users can access a private key file
+    InsecurePermissions(PathBuf),
+    /// Returned when a key file does not hold a valid key
+    InvalidKey(PathBuf, signing::Error),
+    /// Returned when a public key file does not match its private key
+    MismatchedKeys(PathBuf),
+    /// Returned when writing a key would overwrite an existing key file
+    AlreadyExists(PathBuf),
+}
+
+impl StdError for KeyError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            KeyError::IoError(_, err) => Some(err),
+            KeyError::InvalidKey(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeyError::NoHomeDirectory => write!(f, "NoHomeDirectory"),
+            KeyError::IoError(ref path, ref err) => {
+                write!(f, "IoError: {}: {}", path.display(), err)
+            }
+            KeyError::InsecurePermissions(ref path) => write!(
+                f,
+                "InsecurePermissions: {} is accessible by other users",
+                path.display()
+            ),
+            KeyError::InvalidKey(ref path, ref err) => {
+                write!(f, "InvalidKey: {}: {}", path.display(), err)
+            }
+            KeyError::MismatchedKeys(ref path) => write!(
+                f,
+                "MismatchedKeys: {} does not match its private key",
+                path.display()
+            ),
+            KeyError::AlreadyExists(ref path) => {
+                write!(f, "AlreadyExists: {}", path.display())
+            }
+        }
+    }
+}
+
+/// A private key and its public key
+pub struct KeyPair {
+    pub private_key: Secp256k1PrivateKey,
+    pub public_key: Secp256k1PublicKey,
+}
+
+/// A directory of key files
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyDir {
+    path: PathBuf,
+}
+
+impl KeyDir {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        KeyDir { path: path.into() }
+    }
+
+    /// The user keys directory, `~/.sawtooth/keys`
+    pub fn user() -> Result<Self, KeyError> {
+        user_keys_dir(env::var_os("HOME").map(PathBuf::from)).map(KeyDir::new)
+    }
+
+    /// The validator keys directory, `$SAWTOOTH_HOME/keys` if `SAWTOOTH_HOME` is set and
+    /// `/etc/sawtooth/keys` otherwise
+    pub fn validator() -> Self {
+        KeyDir::new(validator_keys_dir(
+            env::var_os("SAWTOOTH_HOME").map(PathBuf::from),
+        ))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The path of the private key file of the key `name`
+    pub fn private_key_path(&self, name: &str) -> PathBuf {
+        self.path
+            .join(format!("{}.{}", name, PRIVATE_KEY_EXTENSION))
+    }
+
+    /// The path of the public key file of the key `name`
+    pub fn public_key_path(&self, name: &str) -> PathBuf {
+        self.path.join(format!("{}.{}", name, PUBLIC_KEY_EXTENSION))
+    }
+
+    /// Load the key `name` and its public key.
+    ///
+    /// The public key is read from the `.pub` file if there is one, and must match the private
+    /// key; otherwise it is derived from the private key.
+    pub fn load(&self, name: &str) -> Result<KeyPair, KeyError> {
+        let private_key = load_private_key(&self.private_key_path(name))?;
+        let public_key_path = self.public_key_path(name);
+        let public_key = derive_public_key(&private_key)
+            .map_err(|err| KeyError::InvalidKey(self.private_key_path(name), err))?;
+
+        if public_key_path.exists()
+            && load_public_key(&public_key_path)?.as_slice() != public_key.as_slice()
+        {
+            return Err(KeyError::MismatchedKeys(public_key_path));
+        }
+
+        Ok(KeyPair {
+            private_key,
+            public_key,
+        })
+    }
+
+    /// Load the public key of the key `name` from its `.pub` file
+    pub fn load_public_key(&self, name: &str) -> Result<Secp256k1PublicKey, KeyError> {
+        load_public_key(&self.public_key_path(name))
+    }
+
+    /// Save a private key and its public key as the key `name`, creating the directory if needed.
+    ///
+    /// Existing key files are only replaced if `overwrite` is set.
+    pub fn save(
+        &self,
+        name: &str,
+        private_key: &Secp256k1PrivateKey,
+        overwrite: bool,
+    ) -> Result<KeyPair, KeyError> {
+        let public_key = derive_public_key(private_key)
+            .map_err(|err| KeyError::InvalidKey(self.private_key_path(name), err))?;
+
+        let private_key_path = self.private_key_path(name);
+        let public_key_path = self.public_key_path(name);
+
+        create_dir(&self.path)?;
+        let private_hex = Zeroizing::new(private_key.as_hex());
+        write_key_file(&private_key_path, &private_hex, PRIVATE_KEY_MODE, overwrite)?;
+        if let Err(err) = write_key_file(
+            &public_key_path,
+            &public_key.as_hex(),
+            PUBLIC_KEY_MODE,
+            overwrite,
+        ) {
+            // Don't leave a new private key behind without its public key
+            if !overwrite {
+                fs::remove_file(&private_key_path).ok();
+            }
+            return Err(err);
+        }
+
+        Ok(KeyPair {
+            private_key: Secp256k1PrivateKey::from_hex(&private_hex)
+                .map_err(|err| KeyError::InvalidKey(private_key_path, err))?,
+            public_key,
+        })
+    }
+
+    /// Generate a new random key and save it as the key `name`
+    pub fn generate(&self, name: &str, overwrite: bool) -> Result<KeyPair, KeyError> {
+        let private_key = Secp256k1Context::new()
+            .new_random_private_key()
+            .and_then(|key| Secp256k1PrivateKey::from_hex(&Zeroizing::new(key.as_hex())))
+            .map_err(|err| KeyError::InvalidKey(self.private_key_path(name), err))?;
+        self.save(name, &private_key, overwrite)
+    }
+}
+
+/// Load the validator's key from the validator keys directory
+pub fn load_validator_key() -> Result<KeyPair, KeyError> {
+    KeyDir::validator().load(VALIDATOR_KEY_NAME)
+}
+
+fn user_keys_dir(home: Option<PathBuf>) -> Result<PathBuf, KeyError> {
+    home.filter(|home| !home.as_os_str().is_empty())
+        .map(|home| home.join(".sawtooth").join("keys"))
+        .ok_or(KeyError::NoHomeDirectory)
+}
+
+fn validator_keys_dir(sawtooth_home: Option<PathBuf>) -> PathBuf {
+    match sawtooth_home.filter(|home| !home.as_os_str().is_empty()) {
+        Some(home) => home.join("keys"),
+        None => PathBuf::from("/etc/sawtooth/keys"),
+    }
+}
+
+fn derive_public_key(
+    private_key: &Secp256k1PrivateKey,
+) -> Result<Secp256k1PublicKey, signing::Error> {
+    let public_key = Secp256k1Context::new().get_public_key(private_key)?;
+    Secp256k1PublicKey::from_hex(&public_key.as_hex())
+}
+
+/// Read a key file, trimming surrounding whitespace in place so that no unzeroed copy of a
+/// private key is left behind
+pub(crate) fn read_key_file(path: &Path) -> Result<Zeroizing<String>, KeyError> {
+    let mut contents = Zeroizing::new(
+        fs::read_to_string(path).map_err(|err| KeyError::IoError(path.to_path_buf(), err))?,
+    );
+    let end = contents.trim_end().len();
+    contents.truncate(end);
+    let start = contents.len() - contents.trim_start().len();
+    contents.drain(..start);
+    Ok(contents)
+}
+
+/// Load a private key from a file holding it as hex, refusing files other users can access
+pub fn load_private_key(path: &Path) -> Result<Secp256k1PrivateKey, KeyError> {
+    check_private_permissions(path)?;
+
+    let private_key = Secp256k1PrivateKey::from_hex(&read_key_file(path)?)
+        .map_err(|err| KeyError::InvalidKey(path.to_path_buf(), err))?;
+    derive_public_key(&private_key).map_err(|err| KeyError::InvalidKey(path.to_path_buf(), err))?;
+    Ok(private_key)
+}
+
+/// Load a public key from a file holding it as hex
+pub fn load_public_key(path: &Path) -> Result<Secp256k1PublicKey, KeyError> {
+    Secp256k1PublicKey::from_hex(&read_key_file(path)?)
+        .map_err(|err| KeyError::InvalidKey(path.to_path_buf(), err))
+}
+
+#[cfg(unix)]
+pub(crate) fn check_private_permissions(path: &Path) -> Result<(), KeyError> {
+    let metadata = fs::metadata(path).map_err(|err| KeyError::IoError(path.to_path_buf(), err))?;
+    if metadata.permissions().mode() & OTHER_USERS_MODE != 0 {
+        return Err(KeyError::InsecurePermissions(path.to_path_buf()));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn check_private_permissions(_path: &Path) -> Result<(), KeyError> {
+    Ok(())
+}
+
+fn create_dir(path: &Path) -> Result<(), KeyError> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(KEY_DIR_MODE);
+    }
+    builder
+        .create(path)
+        .map_err(|err| KeyError::IoError(path.to_path_buf(), err))
+}
+
+/// Write a key file with the given permissions. An existing file is replaced if `overwrite` is
+/// set, and is otherwise an `AlreadyExists` error.
+pub(crate) fn write_key_file(
+    path: &Path,
+    contents: &str,
+    mode: u32,
+    overwrite: bool,
+) -> Result<(), KeyError> {
+    let io_error = |err| KeyError::IoError(path.to_path_buf(), err);
+
+    let mut options = OpenOptions::new();
+    if overwrite {
+        options.write(true).create(true).truncate(true);
+    } else {
+        options.write(true).create_new(true);
+    }
+    #[cfg(unix)]
+    options.mode(mode);
+    let mut file = options.open(path).map_err(|err| match err.kind() {
+        io::ErrorKind::AlreadyExists if !overwrite => KeyError::AlreadyExists(path.to_path_buf()),
+        _ => io_error(err),
+    })?;
+
+    // The mode only applies to new files, so an existing file's permissions are reset
+    #[cfg(unix)]
+    file.set_permissions(fs::Permissions::from_mode(mode))
+        .map_err(io_error)?;
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    writeln!(file, "{}", contents).map_err(io_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    use crate::signing::test_keys::{KEY1_PRIV_HEX, KEY1_PUB_HEX, KEY2_PUB_HEX};
+
+    #[test]
+    fn keys_directories() {
+        assert_eq!(
+            user_keys_dir(Some("/home/alice".into())).unwrap(),
+            PathBuf::from("/home/alice/.sawtooth/keys")
+        );
+        match user_keys_dir(None) {
+            Err(KeyError::NoHomeDirectory) => (),
+            res => panic!("Expected NoHomeDirectory, got {:?}", res),
+        }
+
+        assert_eq!(
+            validator_keys_dir(Some("/opt/sawtooth".into())),
+            PathBuf::from("/opt/sawtooth/keys")
+        );
+        assert_eq!(
+            validator_keys_dir(None),
+            PathBuf::from("/etc/sawtooth/keys")
+        );
+        assert_eq!(
+            validator_keys_dir(Some("".into())),
+            PathBuf::from("/etc/sawtooth/keys")
+        );
+    }
+
+    #[test]
+    fn save_and_load() {
+        let temp_dir = TempDir::new().unwrap();
+        let key_dir = KeyDir::new(temp_dir.path().join("keys"));
+
+        let private_key = Secp256k1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap();
+        let saved = key_dir.save("alice", &private_key, false).unwrap();
+        assert_eq!(saved.public_key.as_hex(), KEY1_PUB_HEX);
+
+        assert_eq!(
+            fs::read_to_string(key_dir.private_key_path("alice")).unwrap(),
+            format!("{}\n", KEY1_PRIV_HEX)
+        );
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("keys/alice.pub")).unwrap(),
+            format!("{}\n", KEY1_PUB_HEX)
+        );
+
+        let loaded = key_dir.load("alice").unwrap();
+        assert_eq!(loaded.private_key.as_hex(), KEY1_PRIV_HEX);
+        assert_eq!(loaded.public_key.as_hex(), KEY1_PUB_HEX);
+        assert_eq!(
+            key_dir.load_public_key("alice").unwrap().as_hex(),
+            KEY1_PUB_HEX
+        );
+
+        match key_dir.save("alice", &private_key, false) {
+            Err(KeyError::AlreadyExists(_)) => (),
+            res => panic!("Expected AlreadyExists, got {:?}", res.map(|_| ())),
+        }
+        key_dir.save("alice", &private_key, true).unwrap();
+
+        // An existing public key is not overwritten, and the new private key is removed
+        fs::write(key_dir.public_key_path("bob"), "").unwrap();
+        match key_dir.save("bob", &private_key, false) {
+            Err(KeyError::AlreadyExists(path)) => assert_eq!(path, key_dir.public_key_path("bob")),
+            res => panic!("Expected AlreadyExists, got {:?}", res.map(|_| ())),
+        }
+        assert_eq!(
+            fs::read_to_string(key_dir.public_key_path("bob")).unwrap(),
+            ""
+        );
+        assert!(!key_dir.private_key_path("bob").exists());
+    }
+
+    #[test]
+    fn generate() {
+        let temp_dir = TempDir::new().unwrap();
+        let key_dir = KeyDir::new(temp_dir.path());
+
+        let generated = key_dir.generate("validator", false).unwrap();
+        let loaded = key_dir.load("validator").unwrap();
+        assert_eq!(loaded.private_key.as_hex(), generated.private_key.as_hex());
+        assert_eq!(loaded.public_key.as_hex(), generated.public_key.as_hex());
+
+        #[cfg(unix)]
+        {
+            let mode = |path: PathBuf| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(key_dir.private_key_path("validator")), 0o600);
+            assert_eq!(mode(key_dir.public_key_path("validator")), 0o644);
+        }
+
+        assert!(key_dir.generate("validator", false).is_err());
+    }
+
+    #[test]
+    fn public_key_without_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let key_dir = KeyDir::new(temp_dir.path());
+        key_dir
+            .save(
+                "alice",
+                &Secp256k1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap(),
+                false,
+            )
+            .unwrap();
+        fs::remove_file(key_dir.public_key_path("alice")).unwrap();
+
+        assert_eq!(
+            key_dir.load("alice").unwrap().public_key.as_hex(),
+            KEY1_PUB_HEX
+        );
+        match key_dir.load_public_key("alice") {
+            Err(KeyError::IoError(_, _)) => (),
+            res => panic!("Expected IoError, got {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn mismatched_public_key() {
+        let temp_dir = TempDir::new().unwrap();
+        let key_dir = KeyDir::new(temp_dir.path());
+        key_dir
+            .save(
+                "alice",
+                &Secp256k1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap(),
+                false,
+            )
+            .unwrap();
+        fs::write(key_dir.public_key_path("alice"), KEY2_PUB_HEX).unwrap();
+
+        match key_dir.load("alice") {
+            Err(KeyError::MismatchedKeys(_)) => (),
+            res => panic!("Expected MismatchedKeys, got {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn invalid_key_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let key_dir = KeyDir::new(temp_dir.path());
+
+        match key_dir.load("missing") {
+            Err(KeyError::IoError(_, _)) => (),
+            res => panic!("Expected IoError, got {:?}", res.map(|_| ())),
+        }
+
+        let zero = "0".repeat(64);
+        for contents in &["not hex", "abc", zero.as_str()] {
+            write_key_file(
+                &key_dir.private_key_path("bad"),
+                contents,
+                PRIVATE_KEY_MODE,
+                true,
+            )
+            .unwrap();
+            match key_dir.load("bad") {
+                Err(KeyError::InvalidKey(_, _)) => (),
+                res => panic!("Expected InvalidKey, got {:?}", res.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn refuse_world_readable_private_keys() {
+        let temp_dir = TempDir::new().unwrap();
+        let key_dir = KeyDir::new(temp_dir.path());
+        key_dir.generate("alice", false).unwrap();
+
+        let path = key_dir.private_key_path("alice");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        match key_dir.load("alice") {
+            Err(KeyError::InsecurePermissions(insecure)) => assert_eq!(insecure, path),
+            res => panic!("Expected InsecurePermissions, got {:?}", res.map(|_| ())),
+        }
+
+        // Group access is allowed, as for validator keys shared with the validator's group
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        key_dir.load("alice").unwrap();
+    }
+}
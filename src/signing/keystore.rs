@@ -0,0 +1,539 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Password-protected JSON keystore files for private keys, implemented in pure Rust.
+//!
+//! The private key is encrypted with XChaCha20-Poly1305 under a key derived from the password
+//! with scrypt. The keystore also records, in the clear, the key's algorithm name, its public
+//! key and when the keystore was created; this metadata is authenticated along with the key,
+//! so it cannot be altered without the password.
+//!
+//! Any algorithm available through `create_context` can be stored.
+//!
+//! ```no_run
+//! use std::path::Path;
+//!
+//! use sawtooth_sdk::signing::create_context;
+//! use sawtooth_sdk::signing::keystore::Keystore;
+//!
+//! let context = create_context("secp256k1").unwrap();
+//! let private_key = context.new_random_private_key().unwrap();
+//!
+//! let keystore = Keystore::encrypt(&*private_key, "hunter2").unwrap();
+//! keystore.save(Path::new("alice.json")).unwrap();
+//!
+//! let loaded = Keystore::load(Path::new("alice.json")).unwrap();
+//! let private_key = loaded.decrypt("hunter2").unwrap();
+//! ```
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::signing;
+use crate::signing::bytes_to_hex_str;
+use crate::signing::create_context;
+use crate::signing::hex_str_to_bytes;
+use crate::signing::keys::{self, KeyError};
+use crate::signing::PrivateKey;
+
+/// The keystore format version written by this module
+pub const KEYSTORE_VERSION: u32 = 1;
+
+const KDF_NAME: &str = "scrypt";
+const CIPHER_NAME: &str = "xchacha20-poly1305";
+const SALT_SIZE: usize = 32;
+const NONCE_SIZE: usize = 24;
+const KEY_SIZE: usize = 32;
+
+// The most memory, 128 * r * 2^log_n bytes, and the largest r * p which the scrypt parameters may
+// require, so that a crafted or corrupted keystore cannot make decryption exhaust memory or CPU
+// before the password is checked
+const MAX_SCRYPT_MEMORY: u128 = 256 * 1024 * 1024;
+const MAX_SCRYPT_R_P: u64 = 64;
+
+#[derive(Debug)]
+pub enum KeystoreError {
+    /// Returned when a keystore is not valid JSON or is missing fields
+    InvalidFormat(String),
+    /// Returned when a keystore uses a version, KDF, KDF parameters or cipher which is not
+    /// supported
+    Unsupported(String),
+    /// Returned when the password is wrong or the keystore has been altered
+    DecryptionFailed,
+    /// Returned when the decrypted key does not match the keystore's public key
+    MismatchedKey,
+    /// Returned when the key's algorithm is unavailable or the key is invalid
+    SigningError(signing::Error),
+    /// Returned when a keystore file cannot be read or written
+    KeyFileError(KeyError),
+}
+
+impl StdError for KeystoreError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            KeystoreError::SigningError(err) => Some(err),
+            KeystoreError::KeyFileError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for KeystoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeystoreError::InvalidFormat(ref s) => write!(f, "InvalidFormat: {}", s),
+            KeystoreError::Unsupported(ref s) => write!(f, "Unsupported: {}", s),
+            KeystoreError::DecryptionFailed => {
+                write!(f, "DecryptionFailed: wrong password or altered keystore")
+            }
+            KeystoreError::MismatchedKey => write!(
+                f,
+                "MismatchedKey: private key does not match the keystore's public key"
+            ),
+            KeystoreError::SigningError(ref err) => write!(f, "SigningError: {}", err),
+            KeystoreError::KeyFileError(ref err) => write!(f, "KeyFileError: {}", err),
+        }
+    }
+}
+
+impl From<signing::Error> for KeystoreError {
+    fn from(err: signing::Error) -> Self {
+        KeystoreError::SigningError(err)
+    }
+}
+
+impl From<KeyError> for KeystoreError {
+    fn from(err: KeyError) -> Self {
+        KeystoreError::KeyFileError(err)
+    }
+}
+
+/// The cost parameters of the scrypt key derivation. Keystores whose parameters need more than
+/// 256 MiB of memory, or with `r * p` above 64, are not supported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScryptParams {
+    /// The base 2 logarithm of the CPU/memory cost
+    pub log_n: u8,
+    /// The block size
+    pub r: u32,
+    /// The parallelization
+    pub p: u32,
+}
+
+impl ScryptParams {
+    /// Returns the memory needed by the key derivation, in bytes, or `None` if it is too large
+    /// to compute
+    fn memory(&self) -> Option<u128> {
+        if self.log_n >= 64 {
+            return None;
+        }
+        Some((128 * u128::from(self.r)) << self.log_n)
+    }
+
+    fn check_supported(&self) -> Result<(), KeystoreError> {
+        let within_memory = self
+            .memory()
+            .map(|memory| memory <= MAX_SCRYPT_MEMORY)
+            .unwrap_or(false);
+        if !within_memory || u64::from(self.r) * u64::from(self.p) > MAX_SCRYPT_R_P {
+            return Err(KeystoreError::Unsupported(format!(
+                "scrypt parameters log_n {}, r {}, p {} exceed {} MiB of memory or r * p of {}",
+                self.log_n,
+                self.r,
+                self.p,
+                MAX_SCRYPT_MEMORY / (1024 * 1024),
+                MAX_SCRYPT_R_P
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Default for ScryptParams {
+    /// The parameters recommended for interactive logins, using 32 MiB of memory
+    fn default() -> Self {
+        ScryptParams {
+            log_n: 15,
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct KdfParams {
+    name: String,
+    log_n: u8,
+    r: u32,
+    p: u32,
+    salt: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CipherParams {
+    name: String,
+    nonce: String,
+}
+
+/// A private key encrypted with a password
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Keystore {
+    version: u32,
+    algorithm: String,
+    public_key: String,
+    /// Seconds since the Unix epoch
+    created: u64,
+    kdf: KdfParams,
+    cipher: CipherParams,
+    ciphertext: String,
+}
+
+impl Keystore {
+    /// Encrypt a private key with `password`, using the default scrypt parameters
+    pub fn encrypt(private_key: &dyn PrivateKey, password: &str) -> Result<Self, KeystoreError> {
+        Self::encrypt_with_params(private_key, password, ScryptParams::default())
+    }
+
+    /// Encrypt a private key with `password`, using the given scrypt parameters
+    pub fn encrypt_with_params(
+        private_key: &dyn PrivateKey,
+        password: &str,
+        params: ScryptParams,
+    ) -> Result<Self, KeystoreError> {
+        params.check_supported()?;
+        let context = create_context(private_key.get_algorithm_name())?;
+        let public_key = context.get_public_key(private_key)?;
+
+        let mut salt = [0u8; SALT_SIZE];
+        OsRng.fill_bytes(&mut salt);
+        let mut nonce = [0u8; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or(0);
+
+        let mut keystore = Keystore {
+            version: KEYSTORE_VERSION,
+            algorithm: private_key.get_algorithm_name().to_string(),
+            public_key: public_key.as_hex(),
+            created,
+            kdf: KdfParams {
+                name: KDF_NAME.into(),
+                log_n: params.log_n,
+                r: params.r,
+                p: params.p,
+                salt: bytes_to_hex_str(&salt),
+            },
+            cipher: CipherParams {
+                name: CIPHER_NAME.into(),
+                nonce: bytes_to_hex_str(&nonce),
+            },
+            ciphertext: String::new(),
+        };
+
+        let cipher = keystore.derive_cipher(password)?;
+        let ciphertext = cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: private_key.as_slice(),
+                    aad: keystore.associated_data().as_bytes(),
+                },
+            )
+            .map_err(|_| KeystoreError::InvalidFormat("unable to encrypt private key".into()))?;
+        keystore.ciphertext = bytes_to_hex_str(&ciphertext);
+
+        Ok(keystore)
+    }
+
+    /// Decrypt the private key with `password`
+    pub fn decrypt(&self, password: &str) -> Result<Box<dyn PrivateKey>, KeystoreError> {
+        self.check_supported()?;
+        let context = create_context(&self.algorithm)?;
+
+        let nonce = parse_hex(&self.cipher.nonce, "nonce")?;
+        if nonce.len() != NONCE_SIZE {
+            return Err(KeystoreError::InvalidFormat(format!(
+                "nonce must be {} bytes, not {}",
+                NONCE_SIZE,
+                nonce.len()
+            )));
+        }
+        let ciphertext = parse_hex(&self.ciphertext, "ciphertext")?;
+
+        let plaintext = Zeroizing::new(
+            self.derive_cipher(password)?
+                .decrypt(
+                    XNonce::from_slice(&nonce),
+                    Payload {
+                        msg: &ciphertext,
+                        aad: self.associated_data().as_bytes(),
+                    },
+                )
+                .map_err(|_| KeystoreError::DecryptionFailed)?,
+        );
+
+        let private_key = context.private_key_from_bytes(&plaintext)?;
+        if context.get_public_key(&*private_key)?.as_hex() != self.public_key {
+            return Err(KeystoreError::MismatchedKey);
+        }
+        Ok(private_key)
+    }
+
+    /// Returns the name of the key's signing algorithm
+    pub fn algorithm_name(&self) -> &str {
+        &self.algorithm
+    }
+
+    /// Returns the hex-encoded public key of the stored private key
+    pub fn public_key_hex(&self) -> &str {
+        &self.public_key
+    }
+
+    /// Returns when the keystore was created
+    pub fn created(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.created)
+    }
+
+    /// Parse a keystore from its JSON form
+    pub fn from_json(json: &str) -> Result<Self, KeystoreError> {
+        let keystore: Keystore = serde_json::from_str(json)
+            .map_err(|err| KeystoreError::InvalidFormat(err.to_string()))?;
+        keystore.check_supported()?;
+        Ok(keystore)
+    }
+
+    /// Returns the keystore in its JSON form
+    pub fn to_json(&self) -> Result<String, KeystoreError> {
+        serde_json::to_string_pretty(self)
+            .map_err(|err| KeystoreError::InvalidFormat(err.to_string()))
+    }
+
+    /// Load a keystore file, refusing files other users can access
+    pub fn load(path: &Path) -> Result<Self, KeystoreError> {
+        keys::check_private_permissions(path)?;
+        Self::from_json(&keys::read_key_file(path)?)
+    }
+
+    /// Save the keystore to a file readable by its owner only, replacing any existing file
+    pub fn save(&self, path: &Path) -> Result<(), KeystoreError> {
+        keys::write_key_file(path, &self.to_json()?, keys::PRIVATE_KEY_MODE, true)?;
+        Ok(())
+    }
+
+    fn check_supported(&self) -> Result<(), KeystoreError> {
+        if self.version != KEYSTORE_VERSION {
+            return Err(KeystoreError::Unsupported(format!(
+                "keystore version {}",
+                self.version
+            )));
+        }
+        if self.kdf.name != KDF_NAME {
+            return Err(KeystoreError::Unsupported(format!("KDF {}", self.kdf.name)));
+        }
+        ScryptParams {
+            log_n: self.kdf.log_n,
+            r: self.kdf.r,
+            p: self.kdf.p,
+        }
+        .check_supported()?;
+        if self.cipher.name != CIPHER_NAME {
+            return Err(KeystoreError::Unsupported(format!(
+                "cipher {}",
+                self.cipher.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// The metadata which is authenticated along with the encrypted key
+    fn associated_data(&self) -> String {
+        format!(
+            "{}:{}:{}:{}",
+            self.version, self.algorithm, self.public_key, self.created
+        )
+    }
+
+    /// Derive the cipher for this keystore from `password`
+    fn derive_cipher(&self, password: &str) -> Result<XChaCha20Poly1305, KeystoreError> {
+        let salt = parse_hex(&self.kdf.salt, "salt")?;
+        let params = scrypt::Params::new(self.kdf.log_n, self.kdf.r, self.kdf.p, KEY_SIZE)
+            .map_err(|err| KeystoreError::InvalidFormat(format!("scrypt parameters: {}", err)))?;
+
+        let mut key = Zeroizing::new([0u8; KEY_SIZE]);
+        scrypt::scrypt(password.as_bytes(), &salt, &params, &mut *key)
+            .map_err(|err| KeystoreError::InvalidFormat(format!("scrypt: {}", err)))?;
+
+        Ok(XChaCha20Poly1305::new(Key::from_slice(&*key)))
+    }
+}
+
+fn parse_hex(s: &str, field: &str) -> Result<Vec<u8>, KeystoreError> {
+    hex_str_to_bytes(s).map_err(|err| KeystoreError::InvalidFormat(format!("{}: {}", field, err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    use crate::signing::secp256k1::Secp256k1PrivateKey;
+    use crate::signing::test_keys::{KEY1_PRIV_HEX, KEY1_PUB_HEX};
+
+    static PASSWORD: &str = "hunter2";
+
+    /// Cheap parameters, so that the tests run quickly
+    const TEST_PARAMS: ScryptParams = ScryptParams {
+        log_n: 4,
+        r: 8,
+        p: 1,
+    };
+
+    fn encrypted_key() -> Keystore {
+        let private_key = Secp256k1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap();
+        Keystore::encrypt_with_params(&private_key, PASSWORD, TEST_PARAMS).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let keystore = encrypted_key();
+        assert_eq!(keystore.algorithm_name(), "secp256k1");
+        assert_eq!(keystore.public_key_hex(), KEY1_PUB_HEX);
+        assert!(keystore.created() > UNIX_EPOCH);
+
+        let json = keystore.to_json().unwrap();
+        assert!(!json.contains(KEY1_PRIV_HEX));
+
+        let private_key = Keystore::from_json(&json)
+            .unwrap()
+            .decrypt(PASSWORD)
+            .unwrap();
+        assert_eq!(private_key.get_algorithm_name(), "secp256k1");
+        assert_eq!(private_key.as_hex(), KEY1_PRIV_HEX);
+    }
+
+    #[test]
+    fn wrong_password() {
+        match encrypted_key().decrypt("hunter3") {
+            Err(KeystoreError::DecryptionFailed) => (),
+            res => panic!("expected DecryptionFailed, got {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn altered_metadata() {
+        let mut keystore = encrypted_key();
+        keystore.created += 1;
+
+        match keystore.decrypt(PASSWORD) {
+            Err(KeystoreError::DecryptionFailed) => (),
+            res => panic!("expected DecryptionFailed, got {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let json = encrypted_key()
+            .to_json()
+            .unwrap()
+            .replace("\"version\": 1", "\"version\": 2");
+
+        match Keystore::from_json(&json) {
+            Err(KeystoreError::Unsupported(_)) => (),
+            res => panic!("expected Unsupported, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn excessive_scrypt_params() {
+        let mut keystore = encrypted_key();
+        keystore.kdf.log_n = 40;
+        assert_unsupported(&keystore);
+
+        // 128 * 8 * 2^19 bytes is 512 MiB, although each parameter is modest on its own
+        keystore.kdf.log_n = 19;
+        assert_unsupported(&keystore);
+
+        keystore.kdf.log_n = 15;
+        keystore.kdf.p = 16;
+        assert_unsupported(&keystore);
+
+        let private_key = Secp256k1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap();
+        let params = ScryptParams {
+            log_n: 15,
+            r: 8,
+            p: 64,
+        };
+        match Keystore::encrypt_with_params(&private_key, PASSWORD, params) {
+            Err(KeystoreError::Unsupported(_)) => (),
+            res => panic!("expected Unsupported, got {:?}", res),
+        }
+    }
+
+    fn assert_unsupported(keystore: &Keystore) {
+        match Keystore::from_json(&keystore.to_json().unwrap()) {
+            Err(KeystoreError::Unsupported(_)) => (),
+            res => panic!("expected Unsupported, got {:?}", res),
+        }
+        match keystore.decrypt(PASSWORD) {
+            Err(KeystoreError::Unsupported(_)) => (),
+            res => panic!("expected Unsupported, got {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("alice.json");
+
+        encrypted_key().save(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let private_key = Keystore::load(&path).unwrap().decrypt(PASSWORD).unwrap();
+        assert_eq!(private_key.as_hex(), KEY1_PRIV_HEX);
+    }
+
+    #[test]
+    #[cfg(feature = "ed25519")]
+    fn other_algorithms() {
+        let context = create_context("ed25519").unwrap();
+        let private_key = context.new_random_private_key().unwrap();
+
+        let keystore = Keystore::encrypt_with_params(&*private_key, PASSWORD, TEST_PARAMS).unwrap();
+        assert_eq!(keystore.algorithm_name(), "ed25519");
+
+        let decrypted = keystore.decrypt(PASSWORD).unwrap();
+        assert_eq!(decrypted.as_hex(), private_key.as_hex());
+    }
+}
@@ -15,9 +15,25 @@
  * ------------------------------------------------------------------------------
  */
 
+#[cfg(feature = "ed25519")]
+pub mod ed25519;
+#[cfg(feature = "hd")]
+pub mod hd;
+pub mod keys;
+#[cfg(feature = "keystore")]
+pub mod keystore;
 pub mod secp256k1;
+#[cfg(feature = "secp256r1")]
+pub mod secp256r1;
+#[cfg(unix)]
+pub mod socket;
 
+use std::cmp::max;
 use std::error::Error as StdError;
+use std::fmt;
+use std::thread;
+
+use subtle::ConstantTimeEq;
 
 #[derive(Debug)]
 pub enum Error {
@@ -52,8 +68,17 @@ impl std::fmt::Display for Error {
     }
 }
 
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::SigningError(Box::new(e))
+    }
+}
+
 /// A private key instance.
 /// The underlying content is dependent on implementation.
+///
+/// The private keys of the algorithms in this module are zeroed when they are dropped, are
+/// compared in constant time, and are never included in their `Debug` output.
 pub trait PrivateKey {
     /// Returns the algorithm name used for this private key.
     fn get_algorithm_name(&self) -> &str;
@@ -119,11 +144,105 @@ pub trait Context {
     ///
     /// * `private_key` - a random private key
     fn new_random_private_key(&self) -> Result<Box<dyn PrivateKey>, Error>;
+
+    /// Load a private key for this algorithm from its raw bytes, as returned by
+    /// `PrivateKey::as_slice`.
+    /// # Arguments
+    ///
+    /// * `bytes` - the private key bytes
+    ///
+    /// # Returns
+    ///
+    /// * `private_key` - the private key
+    fn private_key_from_bytes(&self, bytes: &[u8]) -> Result<Box<dyn PrivateKey>, Error> {
+        let _ = bytes;
+        Err(Error::ParseError(format!(
+            "{} private keys cannot be loaded from bytes",
+            self.get_algorithm_name()
+        )))
+    }
+
+    /// Verify many signatures at once, such as all of those in a block.
+    ///
+    /// Each signature and public key is parsed once, and the signatures are then verified in
+    /// parallel across threads. The default implementation verifies each entry in turn with
+    /// `verify`.
+    /// # Arguments
+    ///
+    /// * `entries` - the signature, message and public key of each entry
+    ///
+    /// # Returns
+    ///
+    /// * `verification` - the result of each entry, in the order given
+    fn verify_batch(&self, entries: &[BatchEntry]) -> BatchVerification {
+        BatchVerification {
+            results: entries
+                .iter()
+                .map(|(signature, message, key)| self.verify(signature, message, *key))
+                .collect(),
+        }
+    }
+}
+
+/// A signature to verify with `Context::verify_batch`: the hex-encoded signature, the message
+/// bytes and the public key of the signer.
+pub type BatchEntry<'a> = (&'a str, &'a [u8], &'a dyn PublicKey);
+
+/// The outcome of `Context::verify_batch`
+#[derive(Debug)]
+pub struct BatchVerification {
+    results: Vec<Result<bool, Error>>,
+}
+
+impl BatchVerification {
+    /// Returns true if every signature in the batch is valid
+    pub fn is_valid(&self) -> bool {
+        self.results.iter().all(|result| matches!(result, Ok(true)))
+    }
+
+    /// Returns the indexes of the entries whose signature is invalid or could not be parsed
+    pub fn failures(&self) -> Vec<usize> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter(|(_, result)| !matches!(result, Ok(true)))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Returns the result of each entry, as `verify` would have returned it
+    pub fn results(&self) -> &[Result<bool, Error>] {
+        &self.results
+    }
+
+    pub fn into_results(self) -> Vec<Result<bool, Error>> {
+        self.results
+    }
+}
+
+/// A signer which keeps its private key to itself, such as a key held by a PKCS#11 module, an
+/// agent process, or a signing daemon.
+///
+/// Unlike a `PrivateKey`, the key's bytes are never exposed; a `Signer` built with
+/// `Signer::new_external` can be used wherever a signer backed by an in-memory key is.
+pub trait ExternalSigner {
+    /// Returns the name of the signing algorithm
+    fn get_algorithm_name(&self) -> &str;
+
+    /// Sign a message, returning the hex-encoded signature, as `Context::sign` does
+    fn sign(&self, message: &[u8]) -> Result<String, Error>;
+
+    /// Returns the public key of the signer's private key
+    fn get_public_key(&self) -> Result<Box<dyn PublicKey>, Error>;
 }
 
 pub fn create_context(algorithm_name: &str) -> Result<Box<dyn Context>, Error> {
     match algorithm_name {
         "secp256k1" => Ok(Box::new(secp256k1::Secp256k1Context::new())),
+        #[cfg(feature = "ed25519")]
+        "ed25519" => Ok(Box::new(ed25519::Ed25519Context::new())),
+        #[cfg(feature = "secp256r1")]
+        "secp256r1" => Ok(Box::new(secp256r1::Secp256r1Context::new())),
         _ => Err(Error::NoSuchAlgorithm(format!(
             "no such algorithm: {}",
             algorithm_name
@@ -170,9 +289,10 @@ impl<'a> CryptoFactory<'a> {
 enum ContextAndKey<'a> {
     ByRef(&'a dyn Context, &'a dyn PrivateKey),
     ByBox(Box<dyn Context>, Box<dyn PrivateKey>),
+    External(Box<dyn ExternalSigner + 'a>),
 }
 
-/// A convenient wrapper of Context and PrivateKey
+/// A convenient wrapper of Context and PrivateKey, or of an ExternalSigner
 pub struct Signer<'a> {
     context_and_key: ContextAndKey<'a>,
 }
@@ -202,6 +322,17 @@ impl<'a> Signer<'a> {
         }
     }
 
+    /// Constructs a new Signer whose key is held by an external signer
+    ///
+    /// # Arguments
+    ///
+    /// * `signer` - the external signer
+    pub fn new_external(signer: Box<dyn ExternalSigner + 'a>) -> Self {
+        Signer {
+            context_and_key: ContextAndKey::External(signer),
+        }
+    }
+
     /// Signs the given message.
     ///
     /// # Arguments
@@ -215,6 +346,7 @@ impl<'a> Signer<'a> {
         match &self.context_and_key {
             ContextAndKey::ByRef(context, key) => context.sign(message, *key),
             ContextAndKey::ByBox(context, key) => context.sign(message, key.as_ref()),
+            ContextAndKey::External(signer) => signer.sign(message),
         }
     }
 
@@ -227,46 +359,142 @@ impl<'a> Signer<'a> {
         match &self.context_and_key {
             ContextAndKey::ByRef(context, key) => context.get_public_key(*key),
             ContextAndKey::ByBox(context, key) => context.get_public_key(key.as_ref()),
+            ContextAndKey::External(signer) => signer.get_public_key(),
         }
     }
 }
 
 fn hex_str_to_bytes(s: &str) -> Result<Vec<u8>, Error> {
-    for (i, ch) in s.chars().enumerate() {
-        if !ch.is_ascii_hexdigit() {
-            return Err(Error::ParseError(format!(
-                "invalid character position {}",
-                i
-            )));
+    let mut bytes = vec![0; s.len() / 2];
+    decode_hex_into(s, &mut bytes)?;
+    Ok(bytes)
+}
+
+/// Decodes a hex string directly into `out`, which must be exactly half the
+/// length of the string.
+///
+/// Errors never include the offending character, so the input may safely be
+/// secret key material.
+fn decode_hex_into(s: &str, out: &mut [u8]) -> Result<(), Error> {
+    hex::decode_to_slice(s, out).map_err(|err| match err {
+        hex::FromHexError::InvalidHexCharacter { index, .. } => {
+            Error::ParseError(format!("invalid character position {}", index))
         }
-    }
+        hex::FromHexError::OddLength => {
+            Error::ParseError(format!("odd number of hex characters: {}", s.len()))
+        }
+        hex::FromHexError::InvalidStringLength => Error::ParseError(format!(
+            "expected {} hex characters, found {}",
+            out.len() * 2,
+            s.len()
+        )),
+    })
+}
+
+fn bytes_to_hex_str(b: &[u8]) -> String {
+    hex::encode(b)
+}
+
+/// The smallest number of signatures worth handing to a thread of their own
+const MIN_SIGNATURES_PER_THREAD: usize = 32;
+
+/// Verify parsed batch entries, splitting them across the available threads.
+///
+/// Entries which failed to parse keep their error; the rest are checked with `verify`.
+fn verify_parsed<T, F>(parsed: Vec<Result<T, Error>>, verify: F) -> BatchVerification
+where
+    T: Sync,
+    F: Fn(&T) -> bool + Sync,
+{
+    let entries: Vec<Option<&T>> = parsed.iter().map(|entry| entry.as_ref().ok()).collect();
+    let check = |chunk: &[Option<&T>]| -> Vec<bool> {
+        chunk
+            .iter()
+            .map(|entry| entry.map(&verify).unwrap_or(false))
+            .collect()
+    };
 
-    let input: Vec<_> = s.chars().collect();
+    let threads = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let chunk_size = max(
+        (entries.len() + threads - 1) / threads,
+        MIN_SIGNATURES_PER_THREAD,
+    );
 
-    let decoded: Vec<u8> = input
-        .chunks(2)
-        .map(|chunk| {
-            ((chunk[0].to_digit(16).unwrap() << 4) | (chunk[1].to_digit(16).unwrap())) as u8
+    let valid: Vec<bool> = if entries.len() <= chunk_size {
+        check(&entries)
+    } else {
+        thread::scope(|scope| {
+            let handles: Vec<_> = entries
+                .chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || check(chunk)))
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("signature verification panicked"))
+                .collect()
         })
-        .collect();
+    };
 
-    Ok(decoded)
+    BatchVerification {
+        results: parsed
+            .into_iter()
+            .zip(valid)
+            .map(|(entry, valid)| entry.map(|_| valid))
+            .collect(),
+    }
 }
 
-fn bytes_to_hex_str(b: &[u8]) -> String {
-    b.iter()
-        .map(|b| format!("{:02x}", b))
-        .collect::<Vec<_>>()
-        .join("")
+/// Compares two secrets in time that depends only on their lengths.
+fn secret_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// Formats a private key for `Debug` without revealing its contents.
+fn fmt_private_key(name: &str, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct(name).finish_non_exhaustive()
+}
+
+/// The secp256k1 keys shared by the tests of other modules
+#[cfg(test)]
+pub(crate) mod test_keys {
+    pub static KEY1_PRIV_HEX: &str =
+        "2f1e7b7a130d7ba9da0068b3bb0ba1d79e7e77110302c9f746c3c2a63fe40088";
+    pub static KEY1_PUB_HEX: &str =
+        "026a2c795a9776f75464aa3bda3534c3154a6e91b357b1181d3f515110f84b67c5";
+    pub static KEY2_PUB_HEX: &str =
+        "039c20a66b4ec7995391dbec1d8bb0e2c6e6fd63cd259ed5b877cb4ea98858cf6d";
 }
 
 #[cfg(test)]
 mod signing_test {
     use super::create_context;
+    use super::hex_str_to_bytes;
+    use super::secret_eq;
 
     #[test]
     fn no_such_algorithm() {
         let result = create_context("invalid");
         assert!(result.is_err())
     }
+
+    #[test]
+    fn hex_decoding() {
+        assert_eq!(hex_str_to_bytes("00ff1A").unwrap(), vec![0x00, 0xff, 0x1a]);
+        assert!(hex_str_to_bytes("").unwrap().is_empty());
+        assert!(hex_str_to_bytes("abc").is_err());
+
+        // The error reports the position but never the character itself
+        let err = hex_str_to_bytes("00q0").unwrap_err().to_string();
+        assert!(err.contains("position 2"));
+        assert!(!err.contains('q'));
+    }
+
+    #[test]
+    fn secret_comparison() {
+        assert!(secret_eq(b"secret", b"secret"));
+        assert!(!secret_eq(b"secret", b"secreT"));
+        assert!(!secret_eq(b"secret", b"secrets"));
+    }
 }
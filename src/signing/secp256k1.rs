@@ -23,12 +23,23 @@ use openssl::{
     pkey::Private as EcPrivate,
     symm::Cipher,
 };
+use std::fmt;
+
 use rand::rngs::OsRng;
 use rand::RngCore;
 use sha2::{Digest, Sha256};
+use zeroize::Zeroize;
+#[cfg(feature = "pem")]
+use zeroize::Zeroizing;
 
 use crate::signing::bytes_to_hex_str;
+use crate::signing::decode_hex_into;
+use crate::signing::fmt_private_key;
 use crate::signing::hex_str_to_bytes;
+use crate::signing::secret_eq;
+use crate::signing::verify_parsed;
+use crate::signing::BatchEntry;
+use crate::signing::BatchVerification;
 use crate::signing::Context;
 use crate::signing::Error;
 use crate::signing::PrivateKey;
@@ -53,21 +64,56 @@ pub struct Secp256k1PrivateKey {
 
 impl Secp256k1PrivateKey {
     pub fn from_hex(s: &str) -> Result<Self, Error> {
-        hex_str_to_bytes(s).map(|key_bytes| Secp256k1PrivateKey { private: key_bytes })
+        // Decode straight into the key so that a partially decoded secret is
+        // still zeroed on error.
+        let mut key = Secp256k1PrivateKey {
+            private: vec![0; s.len() / 2],
+        };
+        decode_hex_into(s, &mut key.private)?;
+        Ok(key)
+    }
+
+    /// Load a private key from its 32 bytes, checking that it is a valid secp256k1 key
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut sk = secp256k1::SecretKey::from_slice(bytes)?;
+        sk.non_secure_erase();
+        Ok(Secp256k1PrivateKey {
+            private: bytes.to_vec(),
+        })
     }
 
     #[cfg(feature = "pem")]
     pub fn from_pem(s: &str) -> Result<Self, Error> {
         let ec_key = EcKey::private_key_from_pem(s.as_bytes())?;
 
-        Self::from_hex(ec_key.private_key().to_hex_str()?.as_ref())
+        Self::from_ec_key(&ec_key)
     }
 
     #[cfg(feature = "pem")]
     pub fn from_pem_with_password(s: &str, pw: &str) -> Result<Self, Error> {
         let ec_key = EcKey::private_key_from_pem_passphrase(s.as_bytes(), pw.as_bytes())?;
 
-        Self::from_hex(ec_key.private_key().to_hex_str()?.as_ref())
+        Self::from_ec_key(&ec_key)
+    }
+
+    #[cfg(feature = "pem")]
+    fn from_ec_key(ec_key: &EcKey<EcPrivate>) -> Result<Self, Error> {
+        // BigNum drops leading zero bytes, so pad back out to the full size.
+        let bytes = Zeroizing::new(ec_key.private_key().to_vec());
+        let size = secp256k1::constants::SECRET_KEY_SIZE;
+        if bytes.len() > size {
+            return Err(Error::ParseError(format!(
+                "private key is {} bytes, expected {}",
+                bytes.len(),
+                size
+            )));
+        }
+
+        let mut key = Secp256k1PrivateKey {
+            private: vec![0; size],
+        };
+        key.private[size - bytes.len()..].copy_from_slice(&bytes);
+        Ok(key)
     }
 
     #[cfg(feature = "pem")]
@@ -114,6 +160,26 @@ impl PrivateKey for Secp256k1PrivateKey {
     }
 }
 
+impl Drop for Secp256k1PrivateKey {
+    fn drop(&mut self) {
+        self.private.zeroize();
+    }
+}
+
+impl PartialEq for Secp256k1PrivateKey {
+    fn eq(&self, other: &Self) -> bool {
+        secret_eq(&self.private, &other.private)
+    }
+}
+
+impl Eq for Secp256k1PrivateKey {}
+
+impl fmt::Debug for Secp256k1PrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_private_key("Secp256k1PrivateKey", f)
+    }
+}
+
 pub struct Secp256k1PublicKey {
     public: Vec<u8>,
 }
@@ -122,6 +188,14 @@ impl Secp256k1PublicKey {
     pub fn from_hex(s: &str) -> Result<Self, Error> {
         hex_str_to_bytes(s).map(|key_bytes| Secp256k1PublicKey { public: key_bytes })
     }
+
+    /// Load a public key from its serialized form, checking that it is a valid point
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        secp256k1::PublicKey::from_slice(bytes)?;
+        Ok(Secp256k1PublicKey {
+            public: bytes.to_vec(),
+        })
+    }
 }
 
 impl PublicKey for Secp256k1PublicKey {
@@ -138,6 +212,9 @@ impl PublicKey for Secp256k1PublicKey {
     }
 }
 
+/// The length in bytes of a signature from `Secp256k1Context::sign_recoverable`
+pub const RECOVERABLE_SIGNATURE_SIZE: usize = 65;
+
 pub struct Secp256k1Context {
     context: secp256k1::Secp256k1<secp256k1::All>,
 }
@@ -148,6 +225,58 @@ impl Secp256k1Context {
             context: secp256k1::Secp256k1::new(),
         }
     }
+
+    /// Sign a message, producing a signature from which the signer's public key can be
+    /// recovered.
+    ///
+    /// The signature is returned as 65 hex-encoded bytes: the 64-byte compact signature, which
+    /// is the same as that returned by `sign`, followed by the recovery ID (0 to 3).
+    pub fn sign_recoverable(&self, message: &[u8], key: &dyn PrivateKey) -> Result<String, Error> {
+        let hash = Sha256::digest(message);
+
+        let message = secp256k1::Message::from_slice(&hash)?;
+        let mut sk = secp256k1::SecretKey::from_slice(key.as_slice())?;
+        let sig = self.context.sign_ecdsa_recoverable(&message, &sk);
+        sk.non_secure_erase();
+
+        let (recovery_id, compact) = sig.serialize_compact();
+        let mut bytes = [0u8; RECOVERABLE_SIGNATURE_SIZE];
+        bytes[..64].copy_from_slice(&compact);
+        bytes[64] = recovery_id.to_i32() as u8;
+        Ok(bytes_to_hex_str(&bytes))
+    }
+
+    /// Recover the public key which produced a signature from `sign_recoverable`.
+    ///
+    /// A signature which does not match the message recovers a different public key rather
+    /// than failing, so the result must be compared with the expected signer.
+    pub fn recover_public_key(
+        &self,
+        signature: &str,
+        message: &[u8],
+    ) -> Result<Secp256k1PublicKey, Error> {
+        if signature.len() != RECOVERABLE_SIGNATURE_SIZE * 2 {
+            return Err(Error::ParseError(format!(
+                "recoverable signature must be {} hex characters, not {}",
+                RECOVERABLE_SIGNATURE_SIZE * 2,
+                signature.len()
+            )));
+        }
+        let mut bytes = [0u8; RECOVERABLE_SIGNATURE_SIZE];
+        decode_hex_into(signature, &mut bytes)?;
+
+        let recovery_id = secp256k1::ecdsa::RecoveryId::from_i32(i32::from(bytes[64]))?;
+        let sig = secp256k1::ecdsa::RecoverableSignature::from_compact(&bytes[..64], recovery_id)?;
+
+        let hash = Sha256::digest(message);
+        let public_key = self
+            .context
+            .recover_ecdsa(&secp256k1::Message::from_slice(&hash)?, &sig)?;
+
+        Ok(Secp256k1PublicKey {
+            public: public_key.serialize().to_vec(),
+        })
+    }
 }
 
 impl Default for Secp256k1Context {
@@ -164,16 +293,12 @@ impl Context for Secp256k1Context {
     fn sign(&self, message: &[u8], key: &dyn PrivateKey) -> Result<String, Error> {
         let hash = Sha256::digest(message);
 
-        let sk = secp256k1::SecretKey::from_slice(key.as_slice())?;
-        let sig = self
-            .context
-            .sign_ecdsa(&secp256k1::Message::from_slice(&hash)?, &sk);
-        let compact = sig.serialize_compact();
-        Ok(compact
-            .iter()
-            .map(|b| format!("{:02x}", b))
-            .collect::<Vec<_>>()
-            .join(""))
+        let message = secp256k1::Message::from_slice(&hash)?;
+        let mut sk = secp256k1::SecretKey::from_slice(key.as_slice())?;
+        let sig = self.context.sign_ecdsa(&message, &sk);
+        sk.non_secure_erase();
+
+        Ok(bytes_to_hex_str(&sig.serialize_compact()))
     }
 
     fn verify(&self, signature: &str, message: &[u8], key: &dyn PublicKey) -> Result<bool, Error> {
@@ -191,36 +316,59 @@ impl Context for Secp256k1Context {
         }
     }
 
+    fn verify_batch(&self, entries: &[BatchEntry]) -> BatchVerification {
+        let parsed: Vec<Result<_, Error>> = entries
+            .iter()
+            .map(|(signature, message, key)| {
+                Ok((
+                    secp256k1::ecdsa::Signature::from_compact(&hex_str_to_bytes(signature)?)?,
+                    *message,
+                    secp256k1::PublicKey::from_slice(key.as_slice())?,
+                ))
+            })
+            .collect();
+
+        verify_parsed(parsed, |(signature, message, public_key)| {
+            let hash = Sha256::digest(message);
+            secp256k1::Message::from_slice(&hash)
+                .and_then(|message| self.context.verify_ecdsa(&message, signature, public_key))
+                .is_ok()
+        })
+    }
+
     fn get_public_key(&self, private_key: &dyn PrivateKey) -> Result<Box<dyn PublicKey>, Error> {
-        let sk = secp256k1::SecretKey::from_slice(private_key.as_slice())?;
-        let result = Secp256k1PublicKey::from_hex(
-            bytes_to_hex_str(
-                &secp256k1::PublicKey::from_secret_key(&self.context, &sk).serialize(),
-            )
-            .as_str(),
-        );
-        match result {
-            Err(err) => Err(err),
-            Ok(pk) => Ok(Box::new(pk)),
-        }
+        let mut sk = secp256k1::SecretKey::from_slice(private_key.as_slice())?;
+        let public = secp256k1::PublicKey::from_secret_key(&self.context, &sk);
+        sk.non_secure_erase();
+
+        Ok(Box::new(Secp256k1PublicKey {
+            public: public.serialize().to_vec(),
+        }))
+    }
+
+    fn private_key_from_bytes(&self, bytes: &[u8]) -> Result<Box<dyn PrivateKey>, Error> {
+        Ok(Box::new(Secp256k1PrivateKey::from_bytes(bytes)?))
     }
 
     fn new_random_private_key(&self) -> Result<Box<dyn PrivateKey>, Error> {
-        let mut key = [0u8; secp256k1::constants::SECRET_KEY_SIZE];
-        OsRng.fill_bytes(&mut key);
-        Ok(Box::new(Secp256k1PrivateKey {
-            private: Vec::from(&key[..]),
-        }))
+        // Fill the key in place rather than copying it out of a stack buffer
+        let mut key = Secp256k1PrivateKey {
+            private: vec![0; secp256k1::constants::SECRET_KEY_SIZE],
+        };
+        OsRng.fill_bytes(&mut key.private);
+        Ok(Box::new(key))
     }
 }
 
 #[cfg(test)]
 mod secp256k1_test {
     use super::super::create_context;
+    use super::super::BatchEntry;
     use super::super::CryptoFactory;
     use super::super::PrivateKey;
     use super::super::PublicKey;
     use super::super::Signer;
+    use super::Secp256k1Context;
     use super::Secp256k1PrivateKey;
     use super::Secp256k1PublicKey;
 
@@ -242,6 +390,10 @@ mod secp256k1_test {
     static MSG2: &'static str = "test2";
     static MSG2_KEY2_SIG: &'static str = "d589c7b1fa5f8a4c5a389de80ae9582c2f7f2a5e21bab5450b670214e5b1c1235e9eb8102fd0ca690a8b42e2c406a682bd57f6daf6e142e5fa4b2c26ef40a490";
 
+    // MSG1_KEY1_SIG and MSG2_KEY2_SIG followed by their recovery IDs
+    static MSG1_KEY1_RECOVERABLE_SIG: &'static str = "5195115d9be2547b720ee74c23dd841842875db6eae1f5da8605b050a49e702b4aa83be72ab7e3cb20f17c657011b49f4c8632be2745ba4de79e6aa05da57b3501";
+    static MSG2_KEY2_RECOVERABLE_SIG: &'static str = "d589c7b1fa5f8a4c5a389de80ae9582c2f7f2a5e21bab5450b670214e5b1c1235e9eb8102fd0ca690a8b42e2c406a682bd57f6daf6e142e5fa4b2c26ef40a49001";
+
     #[test]
     fn hex_key() {
         let priv_key = Secp256k1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap();
@@ -403,4 +555,114 @@ mod secp256k1_test {
         let result = context.verify(MSG2_KEY2_SIG, &String::from(MSG1).into_bytes(), &pub_key1);
         assert_eq!(result.unwrap(), false);
     }
+
+    #[test]
+    fn private_key_is_redacted() {
+        let priv_key = Secp256k1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap();
+        let debug = format!("{:?}", priv_key);
+        assert_eq!(debug, "Secp256k1PrivateKey { .. }");
+        assert!(!debug.contains(&KEY1_PRIV_HEX[..8]));
+    }
+
+    #[test]
+    fn private_key_equality() {
+        let priv_key1 = Secp256k1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap();
+        assert_eq!(
+            priv_key1,
+            Secp256k1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap()
+        );
+        assert_ne!(
+            priv_key1,
+            Secp256k1PrivateKey::from_hex(KEY2_PRIV_HEX).unwrap()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "pem")]
+    fn pem_roundtrip_leading_zero() {
+        // OpenSSL drops leading zero bytes from the scalar
+        let hex = format!("00{}", &KEY1_PRIV_HEX[2..]);
+        let priv_key = Secp256k1PrivateKey::from_hex(&hex).unwrap();
+        let pem_contents = priv_key.to_pem().unwrap();
+
+        let parsed_priv_key = Secp256k1PrivateKey::from_pem(&pem_contents).unwrap();
+        assert_eq!(parsed_priv_key.as_hex(), hex);
+    }
+
+    #[test]
+    fn batch_verification() {
+        let context = create_context("secp256k1").unwrap();
+        let pub_key1 = Secp256k1PublicKey::from_hex(KEY1_PUB_HEX).unwrap();
+        let pub_key2 = Secp256k1PublicKey::from_hex(KEY2_PUB_HEX).unwrap();
+
+        // Enough entries to be split across threads
+        let mut entries: Vec<BatchEntry> = Vec::new();
+        for _ in 0..100 {
+            entries.push((MSG1_KEY1_SIG, MSG1.as_bytes(), &pub_key1));
+            entries.push((MSG2_KEY2_SIG, MSG2.as_bytes(), &pub_key2));
+        }
+        let verification = context.verify_batch(&entries);
+        assert!(verification.is_valid());
+        assert!(verification.failures().is_empty());
+        assert_eq!(verification.results().len(), 200);
+
+        // A signature for the wrong key, and one which cannot be parsed
+        entries[7] = (MSG2_KEY2_SIG, MSG2.as_bytes(), &pub_key1);
+        entries[150] = ("not hex", MSG1.as_bytes(), &pub_key1);
+        let verification = context.verify_batch(&entries);
+        assert!(!verification.is_valid());
+        assert_eq!(verification.failures(), vec![7, 150]);
+
+        let results = verification.into_results();
+        assert!(!results[7].as_ref().unwrap());
+        assert!(results[150].is_err());
+    }
+
+    #[test]
+    fn recoverable_signing() {
+        let context = Secp256k1Context::new();
+
+        let priv_key1 = Secp256k1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap();
+        let signature = context
+            .sign_recoverable(MSG1.as_bytes(), &priv_key1)
+            .unwrap();
+        assert_eq!(signature, MSG1_KEY1_RECOVERABLE_SIG);
+        assert_eq!(&signature[..128], MSG1_KEY1_SIG);
+
+        let priv_key2 = Secp256k1PrivateKey::from_hex(KEY2_PRIV_HEX).unwrap();
+        let signature = context
+            .sign_recoverable(MSG2.as_bytes(), &priv_key2)
+            .unwrap();
+        assert_eq!(signature, MSG2_KEY2_RECOVERABLE_SIG);
+    }
+
+    #[test]
+    fn public_key_recovery() {
+        let context = Secp256k1Context::new();
+
+        let pub_key1 = context
+            .recover_public_key(MSG1_KEY1_RECOVERABLE_SIG, MSG1.as_bytes())
+            .unwrap();
+        assert_eq!(pub_key1.as_hex(), KEY1_PUB_HEX);
+
+        let pub_key2 = context
+            .recover_public_key(MSG2_KEY2_RECOVERABLE_SIG, MSG2.as_bytes())
+            .unwrap();
+        assert_eq!(pub_key2.as_hex(), KEY2_PUB_HEX);
+
+        // The wrong message recovers some other key
+        let other = context
+            .recover_public_key(MSG1_KEY1_RECOVERABLE_SIG, MSG2.as_bytes())
+            .unwrap();
+        assert_ne!(other.as_hex(), KEY1_PUB_HEX);
+
+        // Plain signatures and out of range recovery IDs are rejected
+        assert!(context
+            .recover_public_key(MSG1_KEY1_SIG, MSG1.as_bytes())
+            .is_err());
+        let bad_id = format!("{}04", MSG1_KEY1_SIG);
+        assert!(context
+            .recover_public_key(&bad_id, MSG1.as_bytes())
+            .is_err());
+    }
 }
@@ -0,0 +1,436 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! The NIST P-256 (secp256r1) signing algorithm.
+//!
+//! Keys and signatures are encoded like those of secp256k1: private keys are 32-byte scalars,
+//! public keys are SEC1 points, 33 bytes compressed or 65 bytes uncompressed, and signatures are
+//! ECDSA signatures over the SHA-256 digest of the message, in the 64-byte compact `r || s` form.
+//! Signatures are deterministic, as specified by RFC 6979.
+
+use std::fmt;
+
+use p256::ecdsa::signature::{Signer as _, Verifier as _};
+use p256::ecdsa::{Signature, SigningKey, VerifyingKey};
+use rand::rngs::OsRng;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::signing::bytes_to_hex_str;
+use crate::signing::decode_hex_into;
+use crate::signing::fmt_private_key;
+use crate::signing::hex_str_to_bytes;
+use crate::signing::secret_eq;
+use crate::signing::verify_parsed;
+use crate::signing::BatchEntry;
+use crate::signing::BatchVerification;
+use crate::signing::Context;
+use crate::signing::Error;
+use crate::signing::PrivateKey;
+use crate::signing::PublicKey;
+
+const ALGORITHM_NAME: &str = "secp256r1";
+
+impl From<p256::ecdsa::Error> for Error {
+    fn from(e: p256::ecdsa::Error) -> Self {
+        Error::SigningError(Box::new(e))
+    }
+}
+
+pub struct Secp256r1PrivateKey {
+    private: Vec<u8>,
+}
+
+impl Secp256r1PrivateKey {
+    pub fn from_hex(s: &str) -> Result<Self, Error> {
+        let mut key = Secp256r1PrivateKey {
+            private: vec![0; s.len() / 2],
+        };
+        decode_hex_into(s, &mut key.private)?;
+        SigningKey::from_slice(&key.private)
+            .map_err(|_| Error::ParseError("invalid secp256r1 private key".into()))?;
+        Ok(key)
+    }
+}
+
+impl PrivateKey for Secp256r1PrivateKey {
+    fn get_algorithm_name(&self) -> &str {
+        ALGORITHM_NAME
+    }
+
+    fn as_hex(&self) -> String {
+        bytes_to_hex_str(&self.private)
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.private
+    }
+}
+
+impl Drop for Secp256r1PrivateKey {
+    fn drop(&mut self) {
+        self.private.zeroize();
+    }
+}
+
+impl PartialEq for Secp256r1PrivateKey {
+    fn eq(&self, other: &Self) -> bool {
+        secret_eq(&self.private, &other.private)
+    }
+}
+
+impl Eq for Secp256r1PrivateKey {}
+
+impl fmt::Debug for Secp256r1PrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_private_key("Secp256r1PrivateKey", f)
+    }
+}
+
+pub struct Secp256r1PublicKey {
+    public: Vec<u8>,
+}
+
+impl Secp256r1PublicKey {
+    /// Parse a public key given as a compressed or uncompressed SEC1 point
+    pub fn from_hex(s: &str) -> Result<Self, Error> {
+        let public = hex_str_to_bytes(s)?;
+        verifying_key(&public)?;
+        Ok(Secp256r1PublicKey { public })
+    }
+
+    /// Returns the public key as a 33-byte compressed point
+    pub fn to_compressed(&self) -> Result<Self, Error> {
+        Ok(Secp256r1PublicKey {
+            public: verifying_key(&self.public)?
+                .to_encoded_point(true)
+                .as_bytes()
+                .to_vec(),
+        })
+    }
+
+    /// Returns the public key as a 65-byte uncompressed point
+    pub fn to_uncompressed(&self) -> Result<Self, Error> {
+        Ok(Secp256r1PublicKey {
+            public: verifying_key(&self.public)?
+                .to_encoded_point(false)
+                .as_bytes()
+                .to_vec(),
+        })
+    }
+}
+
+impl PublicKey for Secp256r1PublicKey {
+    fn get_algorithm_name(&self) -> &str {
+        ALGORITHM_NAME
+    }
+
+    fn as_hex(&self) -> String {
+        bytes_to_hex_str(&self.public)
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.public
+    }
+}
+
+fn verifying_key(public: &[u8]) -> Result<VerifyingKey, Error> {
+    VerifyingKey::from_sec1_bytes(public)
+        .map_err(|_| Error::ParseError("invalid secp256r1 public key".into()))
+}
+
+fn signing_key(private_key: &dyn PrivateKey) -> Result<SigningKey, Error> {
+    Ok(SigningKey::from_slice(private_key.as_slice())?)
+}
+
+#[derive(Default)]
+pub struct Secp256r1Context {}
+
+impl Secp256r1Context {
+    pub fn new() -> Self {
+        Secp256r1Context {}
+    }
+}
+
+impl Context for Secp256r1Context {
+    fn get_algorithm_name(&self) -> &str {
+        ALGORITHM_NAME
+    }
+
+    fn sign(&self, message: &[u8], key: &dyn PrivateKey) -> Result<String, Error> {
+        let signature: Signature = signing_key(key)?.try_sign(message)?;
+        Ok(bytes_to_hex_str(&signature.to_bytes()))
+    }
+
+    fn verify(&self, signature: &str, message: &[u8], key: &dyn PublicKey) -> Result<bool, Error> {
+        let signature = Signature::from_slice(&hex_str_to_bytes(signature)?)?;
+        let public_key = VerifyingKey::from_sec1_bytes(key.as_slice())?;
+
+        Ok(public_key.verify(message, &signature).is_ok())
+    }
+
+    fn verify_batch(&self, entries: &[BatchEntry]) -> BatchVerification {
+        let parsed: Vec<Result<_, Error>> = entries
+            .iter()
+            .map(|(signature, message, key)| {
+                Ok((
+                    Signature::from_slice(&hex_str_to_bytes(signature)?)?,
+                    *message,
+                    VerifyingKey::from_sec1_bytes(key.as_slice())?,
+                ))
+            })
+            .collect();
+
+        verify_parsed(parsed, |(signature, message, public_key)| {
+            public_key.verify(message, signature).is_ok()
+        })
+    }
+
+    fn get_public_key(&self, private_key: &dyn PrivateKey) -> Result<Box<dyn PublicKey>, Error> {
+        Ok(Box::new(Secp256r1PublicKey {
+            public: signing_key(private_key)?
+                .verifying_key()
+                .to_encoded_point(true)
+                .as_bytes()
+                .to_vec(),
+        }))
+    }
+
+    fn private_key_from_bytes(&self, bytes: &[u8]) -> Result<Box<dyn PrivateKey>, Error> {
+        SigningKey::from_slice(bytes)
+            .map_err(|_| Error::ParseError("invalid secp256r1 private key".into()))?;
+        Ok(Box::new(Secp256r1PrivateKey {
+            private: bytes.to_vec(),
+        }))
+    }
+
+    fn new_random_private_key(&self) -> Result<Box<dyn PrivateKey>, Error> {
+        let bytes = Zeroizing::new(SigningKey::random(&mut OsRng).to_bytes());
+        Ok(Box::new(Secp256r1PrivateKey {
+            private: bytes.to_vec(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod secp256r1_test {
+    use super::super::create_context;
+    use super::super::CryptoFactory;
+    use super::super::PrivateKey;
+    use super::super::PublicKey;
+    use super::super::Signer;
+    use super::Secp256r1PrivateKey;
+    use super::Secp256r1PublicKey;
+
+    // Key and signatures from RFC 6979, section A.2.5
+    static KEY1_PRIV_HEX: &str = "c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721";
+    static KEY1_PUB_HEX: &str =
+        "0360fed4ba255a9d31c961eb74c6356d68c049b8923b61fa6ce669622e60f29fb6";
+    static KEY1_PUB_UNCOMPRESSED_HEX: &str = "0460fed4ba255a9d31c961eb74c6356d68c049b8923b61fa6ce669622e60f29fb67903fe1008b8bc99a41ae9e95628bc64f2f1b20c2d7e9f5177a3c294d4462299";
+
+    static KEY2_PRIV_HEX: &str = "51b845c2cdde22fe646148f0b51eaf5feec8c82ee921d5e0cbe7619f3bb9c62d";
+    static KEY2_PUB_HEX: &str =
+        "03403ae50579ba1478a9d9967a39190f1d911ce3ae4cc4a099994d2fbf06827325";
+
+    static MSG1: &str = "sample";
+    static MSG1_KEY1_SIG: &str = "efd48b2aacb6a8fd1140dd9cd45e81d69d2c877b56aaf991c34d0ea84eaf3716f7cb1c942d657c41d436c7a1b6e29f65f3e900dbb9aff4064dc4ab2f843acda8";
+
+    static MSG2: &str = "test";
+    static MSG2_KEY1_SIG: &str = "f1abb023518351cd71d881567b1ea663ed3efcf6c5132b354f28d3b0b7d38367019f4113742a2b14bd25926b49c649155f267e60d3814b4c0cc84250e46f0083";
+
+    // A non-deterministic signature, which must verify all the same
+    static MSG3: &str = "test2";
+    static MSG3_KEY2_SIG: &str = "d8816c39bdfd6d2bb5a555c2944b4d31d6c6ff2ecdb6d0725cd159e5f21a892bdaa874434d60f9fbdec7bddb6ad8f2654c008bdd1ed9021a9be6f370446dc1bb";
+
+    #[test]
+    fn hex_key() {
+        let priv_key = Secp256r1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap();
+        assert_eq!(priv_key.get_algorithm_name(), "secp256r1");
+        assert_eq!(priv_key.as_hex(), KEY1_PRIV_HEX);
+
+        let pub_key = Secp256r1PublicKey::from_hex(KEY1_PUB_HEX).unwrap();
+        assert_eq!(pub_key.get_algorithm_name(), "secp256r1");
+        assert_eq!(pub_key.as_hex(), KEY1_PUB_HEX);
+    }
+
+    #[test]
+    fn compressed_and_uncompressed_keys() {
+        let compressed = Secp256r1PublicKey::from_hex(KEY1_PUB_HEX).unwrap();
+        let uncompressed = Secp256r1PublicKey::from_hex(KEY1_PUB_UNCOMPRESSED_HEX).unwrap();
+        assert_eq!(uncompressed.as_hex(), KEY1_PUB_UNCOMPRESSED_HEX);
+
+        assert_eq!(
+            compressed.to_uncompressed().unwrap().as_hex(),
+            KEY1_PUB_UNCOMPRESSED_HEX
+        );
+        assert_eq!(uncompressed.to_compressed().unwrap().as_hex(), KEY1_PUB_HEX);
+
+        // Both forms verify the same signatures
+        let context = create_context("secp256r1").unwrap();
+        let message = String::from(MSG1).into_bytes();
+        assert!(context
+            .verify(MSG1_KEY1_SIG, &message, &compressed)
+            .unwrap());
+        assert!(context
+            .verify(MSG1_KEY1_SIG, &message, &uncompressed)
+            .unwrap());
+    }
+
+    #[test]
+    fn priv_to_public_key() {
+        let context = create_context("secp256r1").unwrap();
+        assert_eq!(context.get_algorithm_name(), "secp256r1");
+
+        let priv_key1 = Secp256r1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap();
+        let public_key1 = context.get_public_key(&priv_key1).unwrap();
+        assert_eq!(public_key1.get_algorithm_name(), "secp256r1");
+        assert_eq!(public_key1.as_hex(), KEY1_PUB_HEX);
+
+        let priv_key2 = Secp256r1PrivateKey::from_hex(KEY2_PRIV_HEX).unwrap();
+        let public_key2 = context.get_public_key(&priv_key2).unwrap();
+        assert_eq!(public_key2.as_hex(), KEY2_PUB_HEX);
+    }
+
+    #[test]
+    fn check_invalid_digit() {
+        let mut priv_chars: Vec<char> = KEY1_PRIV_HEX.chars().collect();
+        priv_chars[3] = 'i';
+        let priv_result =
+            Secp256r1PrivateKey::from_hex(priv_chars.into_iter().collect::<String>().as_str());
+        assert!(priv_result.is_err());
+
+        let mut pub_chars: Vec<char> = KEY1_PUB_HEX.chars().collect();
+        pub_chars[3] = 'i';
+        let result =
+            Secp256r1PublicKey::from_hex(pub_chars.into_iter().collect::<String>().as_str());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn check_invalid_keys() {
+        // Odd length, zero scalar, and scalar above the group order
+        assert!(Secp256r1PrivateKey::from_hex(&KEY1_PRIV_HEX[..63]).is_err());
+        assert!(Secp256r1PrivateKey::from_hex(&"0".repeat(64)).is_err());
+        assert!(Secp256r1PrivateKey::from_hex(&"f".repeat(64)).is_err());
+
+        // Truncated point, and a point prefix which is neither compressed nor uncompressed
+        assert!(Secp256r1PublicKey::from_hex(&KEY1_PUB_HEX[..64]).is_err());
+        assert!(Secp256r1PublicKey::from_hex(&format!("05{}", &KEY1_PUB_HEX[2..])).is_err());
+
+        let context = create_context("secp256r1").unwrap();
+        let pub_key1 = Secp256r1PublicKey::from_hex(KEY1_PUB_HEX).unwrap();
+        let message = String::from(MSG1).into_bytes();
+        assert!(context
+            .verify(&MSG1_KEY1_SIG[..126], &message, &pub_key1)
+            .is_err());
+    }
+
+    #[test]
+    fn single_key_signing() {
+        let context = create_context("secp256r1").unwrap();
+
+        let factory = CryptoFactory::new(&*context);
+        assert_eq!(factory.get_context().get_algorithm_name(), "secp256r1");
+
+        let priv_key = Secp256r1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap();
+        let signer = factory.new_signer(&priv_key);
+        let signature = signer.sign(&String::from(MSG1).into_bytes()).unwrap();
+        assert_eq!(signature, MSG1_KEY1_SIG);
+    }
+
+    fn create_signer() -> Signer<'static> {
+        let context = create_context("secp256r1").unwrap();
+        let priv_key = Secp256r1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap();
+
+        Signer::new_boxed(context, Box::new(priv_key))
+    }
+
+    #[test]
+    fn single_key_signing_return_from_func() {
+        let signer = create_signer();
+        let signature = signer.sign(&String::from(MSG1).into_bytes()).unwrap();
+        assert_eq!(signature, MSG1_KEY1_SIG);
+        assert_eq!(signer.get_public_key().unwrap().as_hex(), KEY1_PUB_HEX);
+    }
+
+    #[test]
+    fn many_message_signing() {
+        let context = create_context("secp256r1").unwrap();
+        let priv_key1 = Secp256r1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap();
+
+        let signature = context
+            .sign(&String::from(MSG1).into_bytes(), &priv_key1)
+            .unwrap();
+        assert_eq!(signature, MSG1_KEY1_SIG);
+
+        let signature = context
+            .sign(&String::from(MSG2).into_bytes(), &priv_key1)
+            .unwrap();
+        assert_eq!(signature, MSG2_KEY1_SIG);
+    }
+
+    #[test]
+    fn verification() {
+        let context = create_context("secp256r1").unwrap();
+
+        let pub_key1 = Secp256r1PublicKey::from_hex(KEY1_PUB_HEX).unwrap();
+        let result = context.verify(MSG2_KEY1_SIG, &String::from(MSG2).into_bytes(), &pub_key1);
+        assert!(result.unwrap());
+
+        let pub_key2 = Secp256r1PublicKey::from_hex(KEY2_PUB_HEX).unwrap();
+        let result = context.verify(MSG3_KEY2_SIG, &String::from(MSG3).into_bytes(), &pub_key2);
+        assert!(result.unwrap());
+    }
+
+    #[test]
+    fn verification_error() {
+        let context = create_context("secp256r1").unwrap();
+
+        let pub_key1 = Secp256r1PublicKey::from_hex(KEY1_PUB_HEX).unwrap();
+
+        // This signature doesn't match for MSG1/KEY1
+        let result = context.verify(MSG3_KEY2_SIG, &String::from(MSG1).into_bytes(), &pub_key1);
+        assert!(!result.unwrap());
+    }
+
+    #[test]
+    fn random_key_round_trip() {
+        let context = create_context("secp256r1").unwrap();
+
+        let priv_key = context.new_random_private_key().unwrap();
+        assert_eq!(priv_key.get_algorithm_name(), "secp256r1");
+        let pub_key = context.get_public_key(&*priv_key).unwrap();
+
+        let message = String::from(MSG3).into_bytes();
+        let signature = context.sign(&message, &*priv_key).unwrap();
+        assert!(context.verify(&signature, &message, &*pub_key).unwrap());
+    }
+
+    #[test]
+    fn private_key_is_redacted_and_compared() {
+        let priv_key = Secp256r1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap();
+        assert_eq!(format!("{:?}", priv_key), "Secp256r1PrivateKey { .. }");
+
+        assert_eq!(
+            priv_key,
+            Secp256r1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap()
+        );
+        assert_ne!(
+            priv_key,
+            Secp256r1PrivateKey::from_hex(KEY2_PRIV_HEX).unwrap()
+        );
+    }
+}
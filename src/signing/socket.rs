@@ -0,0 +1,480 @@
+/*
+ * Copyright 2020 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! An external signer talking to a signing daemon over a Unix socket.
+//!
+//! `SocketSigner` keeps no key material: it asks the daemon listening on the socket for its
+//! public key and for signatures. `SigningDaemon` is a stand-in daemon serving a key held in
+//! memory, for development and tests; a production daemon would hold its key in a hardware module
+//! or behind an agent implementing the same protocol.
+//!
+//! Every message is a frame: a 4-byte big-endian length, followed by that many bytes. A request
+//! frame holds an opcode byte followed by its payload:
+//!
+//! * `1`, get public key: no payload
+//! * `2`, sign: the message to sign
+//!
+//! A response frame holds a status byte followed by its payload. A status of `0` means success:
+//! the payload of a public key response is the length of the algorithm name as one byte, the
+//! algorithm name, and the public key bytes; the payload of a sign response is the signature
+//! bytes. A status of `1` means failure, with a UTF-8 error message as payload.
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+use crate::signing::bytes_to_hex_str;
+use crate::signing::hex_str_to_bytes;
+use crate::signing::Context;
+use crate::signing::Error;
+use crate::signing::ExternalSigner;
+use crate::signing::PrivateKey;
+use crate::signing::PublicKey;
+
+const OP_GET_PUBLIC_KEY: u8 = 1;
+const OP_SIGN: u8 = 2;
+
+const STATUS_OK: u8 = 0;
+const STATUS_ERROR: u8 = 1;
+
+/// The largest frame accepted, which bounds the size of the messages that can be signed
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the daemon waits on a client while reading its request or writing the response.
+/// Connections are served one at a time, one request each, so this bounds how long a client holds
+/// up the others.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+const SOCKET_MODE: u32 = 0o600;
+
+/// The mode of the directory the socket is bound in before being moved into place
+const STAGING_DIR_MODE: u32 = 0o700;
+
+fn write_frame<W: Write>(writer: &mut W, kind: u8, payload: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(payload.len() + 1)
+        .ok()
+        .filter(|len| *len as usize <= MAX_FRAME_LEN)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "frame too large"))?;
+
+    let mut frame = Vec::with_capacity(payload.len() + 5);
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.push(kind);
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+/// Read a frame, returning its kind byte and payload, or `None` if the stream ended before it
+fn read_frame<R: Read>(reader: &mut R) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => (),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+
+    let len = u32::from_be_bytes(len) as usize;
+    if len == 0 || len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid frame length {}", len),
+        ));
+    }
+
+    let mut frame = vec![0; len];
+    reader.read_exact(&mut frame)?;
+    let payload = frame.split_off(1);
+    Ok(Some((frame[0], payload)))
+}
+
+/// A public key received from the daemon
+struct DaemonPublicKey {
+    algorithm_name: String,
+    public: Vec<u8>,
+}
+
+impl PublicKey for DaemonPublicKey {
+    fn get_algorithm_name(&self) -> &str {
+        &self.algorithm_name
+    }
+
+    fn as_hex(&self) -> String {
+        bytes_to_hex_str(&self.public)
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.public
+    }
+}
+
+/// An external signer whose key is held by the daemon listening on a Unix socket
+pub struct SocketSigner {
+    path: PathBuf,
+    timeout: Duration,
+    public_key: DaemonPublicKey,
+}
+
+impl SocketSigner {
+    /// Connect to the daemon listening on `path` and fetch its public key
+    pub fn connect<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        Self::connect_with_timeout(path, DEFAULT_TIMEOUT)
+    }
+
+    /// Connect to the daemon listening on `path`, waiting at most `timeout` for each response
+    pub fn connect_with_timeout<P: Into<PathBuf>>(
+        path: P,
+        timeout: Duration,
+    ) -> Result<Self, Error> {
+        let path = path.into();
+        let payload = request(&path, timeout, OP_GET_PUBLIC_KEY, &[])?;
+
+        let name_len = *payload
+            .first()
+            .ok_or_else(|| Error::ParseError("empty public key response".into()))?
+            as usize;
+        if payload.len() <= name_len + 1 {
+            return Err(Error::ParseError("truncated public key response".into()));
+        }
+        let algorithm_name = String::from_utf8(payload[1..=name_len].to_vec())
+            .map_err(|err| Error::ParseError(format!("invalid algorithm name: {}", err)))?;
+
+        Ok(SocketSigner {
+            path,
+            timeout,
+            public_key: DaemonPublicKey {
+                algorithm_name,
+                public: payload[name_len + 1..].to_vec(),
+            },
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl ExternalSigner for SocketSigner {
+    fn get_algorithm_name(&self) -> &str {
+        &self.public_key.algorithm_name
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<String, Error> {
+        request(&self.path, self.timeout, OP_SIGN, message).map(|sig| bytes_to_hex_str(&sig))
+    }
+
+    fn get_public_key(&self) -> Result<Box<dyn PublicKey>, Error> {
+        Ok(Box::new(DaemonPublicKey {
+            algorithm_name: self.public_key.algorithm_name.clone(),
+            public: self.public_key.public.clone(),
+        }))
+    }
+}
+
+/// Send a request to the daemon on a new connection and return the payload of its response
+fn request(path: &Path, timeout: Duration, op: u8, payload: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    write_frame(&mut stream, op, payload)?;
+    match read_frame(&mut stream)? {
+        Some((STATUS_OK, payload)) => Ok(payload),
+        Some((STATUS_ERROR, message)) => Err(Error::SigningError(
+            format!(
+                "signing daemon error: {}",
+                String::from_utf8_lossy(&message)
+            )
+            .into(),
+        )),
+        Some((status, _)) => Err(Error::SigningError(
+            format!("unknown signing daemon status {}", status).into(),
+        )),
+        None => Err(Error::SigningError(
+            "signing daemon closed the connection".into(),
+        )),
+    }
+}
+
+/// A stand-in signing daemon, serving a key held in memory on a Unix socket
+pub struct SigningDaemon {
+    listener: UnixListener,
+    path: PathBuf,
+    context: Box<dyn Context + Send>,
+    private_key: Box<dyn PrivateKey + Send>,
+}
+
+impl SigningDaemon {
+    /// Listen on `path`, which must not exist, for requests signed with `private_key`. The socket
+    /// is only accessible by its owner.
+    pub fn bind<P: Into<PathBuf>>(
+        path: P,
+        context: Box<dyn Context + Send>,
+        private_key: Box<dyn PrivateKey + Send>,
+    ) -> io::Result<Self> {
+        let path = path.into();
+
+        // Bind in a directory only the owner can enter, so that no one can connect before the
+        // socket's permissions are restricted, then link the socket into place
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no socket file name"))?;
+        let staging_dir = path.with_file_name(format!(
+            ".{}.{}",
+            file_name.to_string_lossy(),
+            process::id()
+        ));
+        fs::DirBuilder::new()
+            .mode(STAGING_DIR_MODE)
+            .create(&staging_dir)?;
+        let staged = staging_dir.join("socket");
+        let listener = UnixListener::bind(&staged).and_then(|listener| {
+            fs::set_permissions(&staged, fs::Permissions::from_mode(SOCKET_MODE))?;
+            fs::hard_link(&staged, &path)?;
+            Ok(listener)
+        });
+        fs::remove_dir_all(&staging_dir).ok();
+
+        Ok(SigningDaemon {
+            listener: listener?,
+            path,
+            context,
+            private_key,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Serve connections one at a time, answering a single request on each, until accepting a
+    /// connection fails. A client which stalls for more than a second while sending its request
+    /// or receiving the response is disconnected.
+    pub fn serve(&self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            if let Err(err) = self.handle_connection(stream?) {
+                warn!("Signing daemon connection failed: {}", err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Answer the first request of a connection, then close it
+    fn handle_connection(&self, mut stream: UnixStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+        if let Some((op, payload)) = read_frame(&mut stream)? {
+            match self.handle_request(op, &payload) {
+                Ok(response) => write_frame(&mut stream, STATUS_OK, &response)?,
+                Err(err) => write_frame(&mut stream, STATUS_ERROR, err.to_string().as_bytes())?,
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_request(&self, op: u8, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        match op {
+            OP_GET_PUBLIC_KEY => {
+                let public_key = self.context.get_public_key(self.private_key.as_ref())?;
+                let name = self.context.get_algorithm_name().as_bytes();
+                let name_len = u8::try_from(name.len())
+                    .map_err(|_| Error::ParseError("algorithm name too long".into()))?;
+
+                let mut response = vec![name_len];
+                response.extend_from_slice(name);
+                response.extend_from_slice(public_key.as_slice());
+                Ok(response)
+            }
+            OP_SIGN => {
+                let signature = self.context.sign(payload, self.private_key.as_ref())?;
+                hex_str_to_bytes(&signature)
+            }
+            _ => Err(Error::ParseError(format!("unknown opcode {}", op))),
+        }
+    }
+}
+
+impl Drop for SigningDaemon {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+    use std::thread;
+
+    use tempfile::TempDir;
+
+    use crate::signing::secp256k1::{Secp256k1Context, Secp256k1PrivateKey, Secp256k1PublicKey};
+    use crate::signing::test_keys::{KEY1_PRIV_HEX, KEY1_PUB_HEX};
+    use crate::signing::Signer;
+
+    static MSG1: &str = "test";
+    static MSG1_KEY1_SIG: &str = "5195115d9be2547b720ee74c23dd841842875db6eae1f5da8605b050a49e702b4aa83be72ab7e3cb20f17c657011b49f4c8632be2745ba4de79e6aa05da57b35";
+
+    /// Start a daemon serving KEY1 in a temporary directory
+    fn start_daemon() -> (TempDir, PathBuf) {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("signer.sock");
+        let daemon = SigningDaemon::bind(
+            &path,
+            Box::new(Secp256k1Context::new()),
+            Box::new(Secp256k1PrivateKey::from_hex(KEY1_PRIV_HEX).unwrap()),
+        )
+        .unwrap();
+        thread::spawn(move || daemon.serve());
+        (temp_dir, path)
+    }
+
+    #[test]
+    fn sign_through_daemon() {
+        let (_temp_dir, path) = start_daemon();
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            SOCKET_MODE
+        );
+        // The directory the socket was bound in has been removed
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+
+        let signer = SocketSigner::connect(&path).unwrap();
+        assert_eq!(signer.get_algorithm_name(), "secp256k1");
+
+        let public_key = signer.get_public_key().unwrap();
+        assert_eq!(public_key.get_algorithm_name(), "secp256k1");
+        assert_eq!(public_key.as_hex(), KEY1_PUB_HEX);
+
+        let signature = signer.sign(MSG1.as_bytes()).unwrap();
+        assert_eq!(signature, MSG1_KEY1_SIG);
+
+        let context = Secp256k1Context::new();
+        let public_key = Secp256k1PublicKey::from_hex(KEY1_PUB_HEX).unwrap();
+        assert!(context
+            .verify(&signature, MSG1.as_bytes(), &public_key)
+            .unwrap());
+    }
+
+    #[test]
+    fn signer_with_external_key() {
+        let (_temp_dir, path) = start_daemon();
+
+        let signer = Signer::new_external(Box::new(SocketSigner::connect(&path).unwrap()));
+        assert_eq!(signer.get_public_key().unwrap().as_hex(), KEY1_PUB_HEX);
+        assert_eq!(signer.sign(MSG1.as_bytes()).unwrap(), MSG1_KEY1_SIG);
+    }
+
+    #[test]
+    fn concurrent_clients() {
+        let (_temp_dir, path) = start_daemon();
+        let signer = Arc::new(SocketSigner::connect(&path).unwrap());
+
+        let clients = (0..4)
+            .map(|_| {
+                let signer = signer.clone();
+                thread::spawn(move || signer.sign(MSG1.as_bytes()).unwrap())
+            })
+            .collect::<Vec<_>>();
+        for client in clients {
+            assert_eq!(client.join().unwrap(), MSG1_KEY1_SIG);
+        }
+    }
+
+    #[test]
+    fn daemon_errors() {
+        let (_temp_dir, path) = start_daemon();
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        write_frame(&mut stream, 42, &[]).unwrap();
+        match read_frame(&mut stream).unwrap() {
+            Some((STATUS_ERROR, message)) => {
+                assert!(String::from_utf8(message)
+                    .unwrap()
+                    .contains("unknown opcode"))
+            }
+            frame => panic!("Expected an error response, got {:?}", frame),
+        }
+
+        // The daemon keeps serving after a failed connection
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.write_all(&[0, 0, 0, 0]).unwrap();
+        drop(stream);
+        SocketSigner::connect(&path).unwrap();
+    }
+
+    #[test]
+    fn one_request_per_connection() {
+        let (_temp_dir, path) = start_daemon();
+
+        // The daemon closes a connection once it has answered, so a client cannot hold it
+        let mut stream = UnixStream::connect(&path).unwrap();
+        write_frame(&mut stream, OP_GET_PUBLIC_KEY, &[]).unwrap();
+        match read_frame(&mut stream).unwrap() {
+            Some((STATUS_OK, _)) => (),
+            frame => panic!("Expected a public key response, got {:?}", frame),
+        }
+        assert_eq!(read_frame(&mut stream).unwrap(), None);
+
+        let signer = SocketSigner::connect(&path).unwrap();
+        assert_eq!(signer.sign(MSG1.as_bytes()).unwrap(), MSG1_KEY1_SIG);
+    }
+
+    #[test]
+    fn idle_client() {
+        let (_temp_dir, path) = start_daemon();
+
+        // A client which connects and sends nothing does not block the others for long
+        let _idle = UnixStream::connect(&path).unwrap();
+        let signer = SocketSigner::connect(&path).unwrap();
+        assert_eq!(signer.sign(MSG1.as_bytes()).unwrap(), MSG1_KEY1_SIG);
+    }
+
+    #[test]
+    fn no_daemon() {
+        let temp_dir = TempDir::new().unwrap();
+        match SocketSigner::connect(temp_dir.path().join("missing.sock")) {
+            Err(Error::SigningError(_)) => (),
+            Err(err) => panic!("Expected SigningError, got {}", err),
+            Ok(_) => panic!("Expected SigningError"),
+        }
+    }
+
+    #[test]
+    fn frames() {
+        let mut buffer = vec![];
+        write_frame(&mut buffer, OP_SIGN, b"message").unwrap();
+        assert_eq!(&buffer[..5], &[0, 0, 0, 8, OP_SIGN]);
+
+        let mut reader = &buffer[..];
+        assert_eq!(
+            read_frame(&mut reader).unwrap(),
+            Some((OP_SIGN, b"message".to_vec()))
+        );
+        assert_eq!(read_frame(&mut reader).unwrap(), None);
+
+        let mut reader = &[0u8, 0, 0, 0][..];
+        assert!(read_frame(&mut reader).is_err());
+    }
+}